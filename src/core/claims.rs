@@ -0,0 +1,363 @@
+use std::{fmt, str::FromStr};
+
+use binary_codec::{BinaryDeserializer, BinarySerializer, DeserializationError};
+use serde::{Deserialize, Serialize};
+
+use crate::packets::body::error::PlabbleError;
+
+/// Identity claims, key-value pairs like `USERNAME=henk;AGE=24`
+///
+/// On the wire (and in TOML) the claims are a UTF-8 string of `KEY=value` pairs separated by `;`.
+/// Keys are unique, not empty and uppercase alphanumeric (or `_`). Values are not empty,
+/// a `;`, `=` or `\` in a value is escaped with a `\`.
+///
+/// The order of the claims is kept, so certificate data (and therefore the certificate ID) is never changed by parsing it.
+/// Use [`Claims::canonical`] to order the claims by key, for instance before issuing a certificate.
+///
+/// Parsing a string ([`FromStr`]) is strict, but decoding claims (from bytes or TOML) is not: claims that are not well-formed,
+/// like the data of a certificate that was issued before claims were typed (with lowercase keys or a bare `\`), are decoded as well
+/// and keep their exact text. Use [`Claims::is_well_formed`] to check decoded claims.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Claims {
+    /// The (unescaped) claims, in order
+    claims: Vec<(String, String)>,
+
+    /// The (escaped) text of the claims, exactly as it was decoded
+    text: String,
+}
+
+impl Claims {
+    /// Create an empty set of claims
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode claims from their text without validating them.
+    /// An escape sequence that is not valid is kept as is, a claim without `=` gets an empty value.
+    fn decode(text: String) -> Self {
+        let mut claims = Vec::new();
+        if !text.is_empty() {
+            let mut current = (String::new(), String::new());
+            let mut in_value = false;
+            let mut chars = text.chars().peekable();
+            while let Some(c) = chars.next() {
+                let part = if in_value {
+                    &mut current.1
+                } else {
+                    &mut current.0
+                };
+
+                match c {
+                    '\\' => match chars.peek() {
+                        Some(&e @ ('\\' | ';' | '=')) => {
+                            part.push(e);
+                            chars.next();
+                        }
+                        _ => part.push(c),
+                    },
+                    ';' => {
+                        claims.push(std::mem::take(&mut current));
+                        in_value = false;
+                    }
+                    '=' if !in_value => in_value = true,
+                    c => part.push(c),
+                }
+            }
+            claims.push(current);
+        }
+
+        Self { claims, text }
+    }
+
+    /// Check if the claims are well-formed, which is always the case unless they were decoded (see [`Claims`])
+    pub fn is_well_formed(&self) -> bool {
+        self.text.parse::<Claims>().is_ok()
+    }
+
+    /// Get the value of a claim
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.claims
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set the value of a claim, replacing the old value if the key is already used.
+    /// Fails with `PlabbleError::InvalidClaims` if the key or value is not valid.
+    pub fn insert(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<(), PlabbleError> {
+        let (key, value) = (key.into(), value.into());
+        if !is_valid_key(&key) || value.is_empty() {
+            return Err(PlabbleError::InvalidClaims);
+        }
+
+        match self.claims.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.claims.push((key, value)),
+        }
+
+        self.text = encode(&self.claims);
+        Ok(())
+    }
+
+    /// Remove a claim and return its value, if present
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let idx = self.claims.iter().position(|(k, _)| k == key)?;
+        let (_, value) = self.claims.remove(idx);
+        self.text = encode(&self.claims);
+        Some(value)
+    }
+
+    /// Iterate the claims (key, value) in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.claims.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Number of claims
+    pub fn len(&self) -> usize {
+        self.claims.len()
+    }
+
+    /// Check if there are no claims
+    pub fn is_empty(&self) -> bool {
+        self.claims.is_empty()
+    }
+
+    /// The claims in canonical order (ordered by key)
+    pub fn canonical(mut self) -> Self {
+        self.claims.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.text = encode(&self.claims);
+        self
+    }
+
+    /// Check if every claim is accepted by the validator.
+    /// Fails with `PlabbleError::InvalidClaims` on the first claim that is rejected.
+    pub fn validate(&self, validator: impl Fn(&str, &str) -> bool) -> Result<(), PlabbleError> {
+        match self.iter().all(|(k, v)| validator(k, v)) {
+            true => Ok(()),
+            false => Err(PlabbleError::InvalidClaims),
+        }
+    }
+}
+
+/// Keys are not empty and uppercase alphanumeric (or `_`)
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl FromStr for Claims {
+    type Err = PlabbleError;
+
+    /// Parse claims. An empty string contains no claims.
+    /// Fails with `PlabbleError::InvalidClaims` if a key is not valid or used twice, if a value is empty,
+    /// or if a value contains an unescaped `=` or an invalid escape sequence.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut claims = Claims::new();
+        if s.is_empty() {
+            return Ok(claims);
+        }
+
+        let mut chars = s.chars();
+        loop {
+            let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+
+            let mut value = String::new();
+            let mut last = true;
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some(c @ ('\\' | ';' | '=')) => value.push(c),
+                        _ => return Err(PlabbleError::InvalidClaims),
+                    },
+                    ';' => {
+                        last = false;
+                        break;
+                    }
+                    '=' => return Err(PlabbleError::InvalidClaims),
+                    c => value.push(c),
+                }
+            }
+
+            if claims.get(&key).is_some() {
+                return Err(PlabbleError::InvalidClaims);
+            }
+            claims.insert(key, value)?;
+
+            if last {
+                claims.text = s.to_string();
+                return Ok(claims);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Claims {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Encode the claims as text, escaping the values
+fn encode(claims: &[(String, String)]) -> String {
+    let mut text = String::new();
+    for (idx, (key, value)) in claims.iter().enumerate() {
+        if idx > 0 {
+            text.push(';');
+        }
+
+        text.push_str(key);
+        text.push('=');
+        for c in value.chars() {
+            if matches!(c, '\\' | ';' | '=') {
+                text.push('\\');
+            }
+            text.push(c);
+        }
+    }
+
+    text
+}
+
+impl Serialize for Claims {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Claims {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(Self::decode(String::deserialize(deserializer)?))
+    }
+}
+
+impl<T: Clone> BinarySerializer<T> for Claims {
+    fn write_bytes(
+        &self,
+        stream: &mut binary_codec::BitStreamWriter,
+        _: Option<&mut binary_codec::SerializerConfig<T>>,
+    ) -> Result<(), binary_codec::SerializationError> {
+        stream.write_bytes(self.to_string().as_bytes());
+        Ok(())
+    }
+}
+
+impl<T: Clone> BinaryDeserializer<T> for Claims {
+    fn read_bytes(
+        stream: &mut binary_codec::BitStreamReader,
+        _: Option<&mut binary_codec::SerializerConfig<T>>,
+    ) -> Result<Self, DeserializationError> {
+        let bytes = stream.read_bytes(stream.bytes_left())?;
+        let text = String::from_utf8(bytes.to_vec())
+            .map_err(|_| DeserializationError::InvalidData("Claims are not UTF-8".to_string()))?;
+        Ok(Self::decode(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binary_codec::{BinaryDeserializer, BinarySerializer};
+
+    use crate::{core::Claims, packets::body::error::PlabbleError};
+
+    #[test]
+    fn can_parse_and_validate_claims() {
+        let claims: Claims = "USERNAME=henk;AGE=24".parse().unwrap();
+        assert_eq!(
+            vec![("USERNAME", "henk"), ("AGE", "24")],
+            claims.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(Some("24"), claims.get("AGE"));
+        assert_eq!(Ok(Claims::new()), "".parse());
+
+        for invalid in [
+            "USERNAME",
+            "username=henk",
+            "A=1;A=2",
+            "A=",
+            "A=1=2",
+            "A=1;",
+            "A=1\\",
+            "A=\\n",
+        ] {
+            assert_eq!(
+                Err(PlabbleError::InvalidClaims),
+                invalid.parse::<Claims>(),
+                "{invalid}"
+            );
+        }
+
+        assert_eq!(Ok(()), claims.validate(|k, _| k != "ROLE"));
+        assert_eq!(
+            Err(PlabbleError::InvalidClaims),
+            claims.validate(|k, v| k != "AGE" || v.parse::<u8>().is_ok_and(|age| age >= 30))
+        );
+    }
+
+    #[test]
+    fn can_escape_values_and_order_canonically() {
+        let mut claims = Claims::new();
+        claims.insert("NAME", "henk;piet=\\").unwrap();
+        claims.insert("AGE", "24").unwrap();
+        assert_eq!(Err(PlabbleError::InvalidClaims), claims.insert("age", "24"));
+        assert_eq!(Err(PlabbleError::InvalidClaims), claims.insert("AGE", ""));
+
+        let text = claims.to_string();
+        assert_eq!("NAME=henk\\;piet\\=\\\\;AGE=24", text);
+        assert_eq!(Ok(claims.clone()), text.parse());
+
+        let canonical = claims.clone().canonical();
+        assert_eq!("AGE=24;NAME=henk\\;piet\\=\\\\", canonical.to_string());
+        assert_ne!(claims, canonical);
+
+        let bytes = BinarySerializer::<()>::to_bytes(&canonical, None).unwrap();
+        assert_eq!(canonical.to_string().as_bytes(), bytes);
+        let deserialized: Claims = BinaryDeserializer::<()>::from_bytes(&bytes, None).unwrap();
+        assert_eq!(canonical, deserialized);
+    }
+
+    #[test]
+    fn decodes_claims_that_are_not_well_formed_as_is() {
+        for text in [
+            "cn=localhost;A=1\\x",
+            "USERNAME=henk;USERNAME=piet",
+            "A=1;",
+            "A",
+        ] {
+            let claims: Claims =
+                BinaryDeserializer::<()>::from_bytes(text.as_bytes(), None).unwrap();
+            assert!(!claims.is_well_formed(), "{text}");
+            assert_eq!(text, claims.to_string());
+            assert_eq!(
+                text.as_bytes(),
+                BinarySerializer::<()>::to_bytes(&claims, None).unwrap()
+            );
+
+            let deserialized: Claims = toml::Value::String(text.to_string()).try_into().unwrap();
+            assert_eq!(claims, deserialized);
+        }
+
+        let claims: Claims =
+            BinaryDeserializer::<()>::from_bytes(b"cn=local\\;host;A=1\\x", None).unwrap();
+        assert_eq!(
+            vec![("cn", "local;host"), ("A", "1\\x")],
+            claims.iter().collect::<Vec<_>>()
+        );
+
+        let claims: Claims =
+            BinaryDeserializer::<()>::from_bytes(b"USERNAME=henk;AGE=24", None).unwrap();
+        assert!(claims.is_well_formed());
+        assert_eq!(Ok(claims), "USERNAME=henk;AGE=24".parse());
+    }
+}
@@ -1,8 +1,10 @@
 mod bucket_id;
+mod claims;
 mod datetime;
 pub mod node_address;
 
 pub use bucket_id::BucketId;
+pub use claims::Claims;
 pub use datetime::PlabbleDateTime;
 
 /// Default to true for serde boolean fields
@@ -1,4 +1,7 @@
-use std::net::{SocketAddrV4, SocketAddrV6};
+use std::{
+    fmt,
+    net::{SocketAddrV4, SocketAddrV6},
+};
 
 use binary_codec::{FromBytes, ToBytes};
 use serde::{Deserialize, Serialize};
@@ -15,3 +18,14 @@ pub enum NodeAddress {
     /// Domain name address
     Domain(#[dyn_length] String, u16),
 }
+
+impl fmt::Display for NodeAddress {
+    /// Format the address as `host:port`, like `127.0.0.1:1234`, `[::1]:1234` or `example.com:1234`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeAddress::V4(addr) => write!(f, "{}", addr),
+            NodeAddress::V6(addr) => write!(f, "{}", addr),
+            NodeAddress::Domain(domain, port) => write!(f, "{}:{}", domain, port),
+        }
+    }
+}
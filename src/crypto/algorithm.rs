@@ -147,6 +147,28 @@ pub enum SigningKey {
     SlhDsaSha128s(#[serde_as(as = "Base64<UrlSafe, Unpadded>")] [u8; 64]),
 }
 
+impl KeyExhangeRequest {
+    /// The raw bytes of the public key or encapsulation key
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            KeyExhangeRequest::X25519(key) => key,
+            KeyExhangeRequest::Kem512(key) => key,
+            KeyExhangeRequest::Kem768(key) => key,
+        }
+    }
+}
+
+impl KeyExhangeResponse {
+    /// The raw bytes of the public key or encapsulated secret
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            KeyExhangeResponse::X25519(key) => key,
+            KeyExhangeResponse::Kem512(key) => key,
+            KeyExhangeResponse::Kem768(key) => key,
+        }
+    }
+}
+
 #[cfg(feature = "protocol")]
 impl SigningKey {
     /// Get singature algorithm from signing key
@@ -159,4 +181,18 @@ impl SigningKey {
             _ => panic!("Unsupported signing key algorithm"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "protocol")]
+impl VerificationKey {
+    /// Get signature algorithm from verification key, or None if the algorithm is not supported
+    pub fn get_algorithm(&self) -> Option<crate::crypto::SignatureAlgorithm> {
+        match self {
+            VerificationKey::Ed25519(_) => Some(crate::crypto::SignatureAlgorithm::Ed25519),
+            VerificationKey::Ed448(_) => Some(crate::crypto::SignatureAlgorithm::Ed448),
+            VerificationKey::Dsa44(_) => Some(crate::crypto::SignatureAlgorithm::Dsa44),
+            VerificationKey::Dsa65(_) => Some(crate::crypto::SignatureAlgorithm::Dsa65),
+            _ => None,
+        }
+    }
+}
@@ -6,8 +6,8 @@ use serde_with::formats::Unpadded;
 use serde_with::serde_as;
 
 use crate::{
-    core::PlabbleDateTime,
-    crypto::algorithm::{CryptoSignature, VerificationKey, SigningKey},
+    core::{Claims, PlabbleDateTime},
+    crypto::algorithm::{CryptoSignature, SigningKey, VerificationKey},
 };
 
 /// Plabble Certificate
@@ -49,7 +49,10 @@ pub struct Certificate {
 impl Certificate {
     /// Get signing key for a specific signature algorithm, if present in the certificate body
     #[cfg(feature = "protocol")]
-    pub fn get_signing_key(&self, algorithm: crate::crypto::SignatureAlgorithm) -> Option<&SigningKey> {
+    pub fn get_signing_key(
+        &self,
+        algorithm: crate::crypto::SignatureAlgorithm,
+    ) -> Option<&SigningKey> {
         if let Some(keys) = self.body.as_ref().and_then(|b| b.secret_keys.as_ref()) {
             let found = keys.iter().find(|k| k.get_algorithm() == algorithm);
             found
@@ -57,6 +60,139 @@ impl Certificate {
             None
         }
     }
+
+    /// The 16-byte certificate ID
+    pub fn id(&self) -> &[u8; 16] {
+        &self.id
+    }
+
+    /// URI where this certificate can be found
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Indicates if this is a root certificate
+    pub fn is_root(&self) -> bool {
+        self.root_cert
+    }
+
+    /// Certificate body, if this is a full certificate
+    pub fn body(&self) -> Option<&CertificateBody> {
+        self.body.as_ref()
+    }
+
+    /// Check if the certificate is valid at the given moment. Compact certificates are never valid, because they have no validity period.
+    pub fn is_valid_at(&self, moment: &PlabbleDateTime) -> bool {
+        self.body.as_ref().is_some_and(|b| {
+            b.valid_from.timestamp() <= moment.timestamp()
+                && moment.timestamp() <= b.valid_until.timestamp()
+        })
+    }
+
+    /// Check if the ID of this (full) certificate matches the ID calculated from its body. Compact certificates never have a valid ID.
+    pub fn has_valid_id(&self) -> bool {
+        self.body.as_ref().is_some_and(|b| b.get_id() == self.id)
+    }
+
+    /// Create a copy of this certificate without the secret keys, so it can be shared
+    pub fn without_secret_keys(&self) -> Self {
+        let mut cert = self.clone();
+        cert.with_secret_keys = false;
+        if let Some(body) = cert.body.as_mut() {
+            body.secret_keys = None;
+        }
+        cert
+    }
+
+    /// Issue a new certificate for the keys, signed by the issuer. Returns None if the issuer has no signing key for one of the keys.
+    ///
+    /// - `uri`: URI where the certificate can be found, `{id}` is replaced by the (base64url) certificate ID
+    /// - `data`: the certificate data (claims), for instance USERNAME=henk
+    #[cfg(feature = "protocol")]
+    pub fn issue(
+        issuer: &Certificate,
+        uri: &str,
+        valid_from: PlabbleDateTime,
+        valid_until: PlabbleDateTime,
+        data: Claims,
+        keys: Vec<VerificationKey>,
+    ) -> Option<Self> {
+        use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+
+        let mut body = CertificateBody {
+            valid_from,
+            valid_until,
+            issuer_uri: Some(issuer.uri.clone()),
+            data,
+            keys,
+            signatures: Vec::new(),
+            secret_keys: None,
+        };
+
+        body.signatures = body
+            .keys
+            .iter()
+            .map(|key| {
+                issuer
+                    .get_signing_key(key.get_algorithm()?)?
+                    .sign(&body.signature_data(key))
+            })
+            .collect::<Option<_>>()?;
+
+        let id = body.get_id();
+        Some(Self {
+            full_cert: true,
+            root_cert: false,
+            with_secret_keys: false,
+            id,
+            uri: uri.replace("{id}", &BASE64_URL_SAFE_NO_PAD.encode(id)),
+            body: Some(body),
+        })
+    }
+
+    /// Check if this (full) certificate is issued by the issuer: the ID matches the body, the issuer URI matches and every key is signed by the issuer.
+    pub fn is_issued_by(&self, issuer: &Certificate) -> bool {
+        let (Some(body), Some(issuer_body)) = (self.body.as_ref(), issuer.body.as_ref()) else {
+            return false;
+        };
+
+        body.get_id() == self.id
+            && body.issuer_uri.as_deref() == Some(issuer.uri.as_str())
+            && !body.keys.is_empty()
+            && body.keys.len() == body.signatures.len()
+            && body
+                .keys
+                .iter()
+                .zip(&body.signatures)
+                .all(|(key, signature)| {
+                    let data = body.signature_data(key);
+                    issuer_body
+                        .keys
+                        .iter()
+                        .any(|issuer_key| issuer_key.verify(&data, signature) == Some(true))
+                })
+    }
+
+    /// Attach the secret keys to this (full) certificate, so it can be stored by its owner
+    pub fn with_secret_keys(mut self, secret_keys: Vec<SigningKey>) -> Self {
+        if let Some(body) = self.body.as_mut() {
+            self.with_secret_keys = true;
+            body.secret_keys = Some(secret_keys);
+        }
+        self
+    }
+
+    /// Create a compact (non-full) copy of this certificate, containing only the ID and URI
+    pub fn to_compact(&self) -> Self {
+        Self {
+            full_cert: false,
+            root_cert: self.root_cert,
+            with_secret_keys: false,
+            id: self.id,
+            uri: self.uri.clone(),
+            body: None,
+        }
+    }
 }
 
 /// Plabble Certificate body
@@ -73,9 +209,10 @@ pub struct CertificateBody {
     #[dyn_length]
     issuer_uri: Option<String>,
 
-    /// The certificate data, for instance CA=plabble;CN=Root certificate
+    /// The certificate data (claims), for instance CA=plabble;CN=Root certificate.
+    /// The claims are decoded as is (see [`Claims`]), so data that is not well-formed keeps the certificate ID intact.
     #[dyn_length]
-    data: String,
+    data: Claims,
 
     /// The public keys this certificate contains/are issued with this certificate
     #[multi_enum]
@@ -96,6 +233,43 @@ pub struct CertificateBody {
 }
 
 impl CertificateBody {
+    /// From when the certificate is valid
+    pub fn valid_from(&self) -> &PlabbleDateTime {
+        &self.valid_from
+    }
+
+    /// Until when the certificate is valid
+    pub fn valid_until(&self) -> &PlabbleDateTime {
+        &self.valid_until
+    }
+
+    /// Who issued the certificate, if not a root certificate
+    pub fn issuer_uri(&self) -> Option<&str> {
+        self.issuer_uri.as_deref()
+    }
+
+    /// The certificate data (claims), for instance CA=plabble;CN=Root certificate
+    pub fn data(&self) -> &Claims {
+        &self.data
+    }
+
+    /// The public keys this certificate contains
+    pub fn keys(&self) -> &[VerificationKey] {
+        &self.keys
+    }
+
+    /// The signatures by the issuer, one for each key
+    pub fn signatures(&self) -> &[CryptoSignature] {
+        &self.signatures
+    }
+
+    /// Data the issuer signs for a key: the issued key followed by the certificate ID
+    pub fn signature_data(&self, key: &VerificationKey) -> Vec<u8> {
+        let mut data = key.as_bytes().to_vec();
+        data.extend_from_slice(&self.get_id());
+        data
+    }
+
     /// Calculate/hash certificate ID (blake2b-128 hash of `valid_from`, `valid_to` (as u32-BE), `issuer_uri` and `data`)
     pub fn get_id(&self) -> [u8; 16] {
         let from = self.valid_from.timestamp().to_be_bytes();
@@ -107,7 +281,8 @@ impl CertificateBody {
         if let Some(issuer_uri) = &self.issuer_uri {
             data.push(issuer_uri.as_bytes());
         }
-        data.push(self.data.as_bytes());
+        let claims = self.data.to_string();
+        data.push(claims.as_bytes());
 
         hash_128(false, data)
     }
@@ -441,6 +616,61 @@ mod tests {
         );
     }
 
+    #[test]
+    fn can_issue_certificate_and_verify_issuer() {
+        use crate::{
+            core::PlabbleDateTime,
+            crypto::{SignatureAlgorithm, algorithm::SigningKey},
+        };
+
+        let issuer: Certificate = toml::from_str(r#"
+            id = "AQEBAQEBAQEBAQEBAQEBAQ"
+            uri = "plabble:localhost/server.crt"
+            valid_from = "2025-05-15T12:30:00+00:00"
+            valid_until = "2161-02-07T06:28:15+00:00"
+            issuer_uri = "plabble:localhost/root.crt"
+            data = "CN=localhost"
+            with_secret_keys = true
+
+            [[keys]]
+            Ed25519 = "PXCMlNZIKU-TI8BWCB8QsNSJp0bLdB1jDzhlSiLXxas"
+
+            [[signatures]]
+            Ed25519 = "GLr1ep-8O70YihvouWdnsFBAPP6poaAVC3TFyz1Lu60MjK_n5D29lYDWmUWit4JaSiN8SpNSpBNmdlFMu8gODQ"
+
+            [[secret_keys]]
+            Ed25519 = "r91Qx-o5PetYDFuO1T6NPW2Q4w1yL13fKmIj2vRQU9g"
+        "#).unwrap();
+
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519);
+        let cert = Certificate::issue(
+            &issuer,
+            "plabble:localhost/{id}.crt",
+            PlabbleDateTime::new(0),
+            PlabbleDateTime::new(100),
+            "USERNAME=henk".parse().unwrap(),
+            vec![key.verification_key().unwrap()],
+        )
+        .unwrap();
+
+        assert_eq!(cert.body().unwrap().get_id(), *cert.id());
+        assert!(cert.uri().starts_with("plabble:localhost/") && !cert.uri().contains("{id}"));
+        assert!(cert.is_issued_by(&issuer));
+        assert!(!issuer.is_issued_by(&cert));
+
+        // Signatures do not match other certificate data
+        let mut forged = cert.clone();
+        forged.body.as_mut().unwrap().data = "USERNAME=piet".parse().unwrap();
+        assert!(!forged.is_issued_by(&issuer));
+
+        // The ID does not match the (validly signed) content
+        let mut forged = cert.clone();
+        forged.id = [9; 16];
+        assert!(!forged.has_valid_id());
+        assert!(!forged.is_issued_by(&issuer));
+        assert!(cert.has_valid_id());
+    }
+
     #[test]
     fn can_store_certificate_with_secret_keys() {
         let cert: Certificate = toml::from_str(r#"
@@ -65,7 +65,7 @@ pub enum KeyExchangeAlgorithm {
 /// - `Ed448` is the EdDSA signature scheme over Curve448, which offers higher security but is less widely supported.
 /// - `Dsa44` and `Dsa65` are optional post-quantum signature algorithms provided when the `pqc-lite` feature is enabled.
 #[cfg(feature = "protocol")]
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum SignatureAlgorithm {
     Ed25519,
     Ed448,
@@ -159,8 +159,54 @@ impl_hash!(hash_512, 64, Blake2b512, |h: blake3::Hasher| {
     out
 });
 
+/// Incremental 256-bit hash, for data that is hashed in parts.
+/// The result is the same as [`hash_256`] over all parts.
+pub enum Hasher256 {
+    Blake2(Blake2b256),
+    #[cfg(feature = "blake-3")]
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher256 {
+    /// Create new hasher, using blake3 or blake2b-256
+    pub fn new(blake3: bool) -> Self {
+        #[cfg(not(feature = "blake-3"))]
+        if blake3 {
+            panic!("Blake3 is not supported when the 'blake-3' feature is not enabled");
+        }
+
+        #[cfg(feature = "blake-3")]
+        if blake3 {
+            return Self::Blake3(Box::new(blake3::Hasher::new()));
+        }
+
+        Self::Blake2(Blake2b256::new())
+    }
+
+    /// Add the data to the hash
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Blake2(hasher) => Digest::update(hasher, data),
+            #[cfg(feature = "blake-3")]
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// Get the hash of all data that was added
+    pub fn finalize(self) -> [u8; 32] {
+        match self {
+            Self::Blake2(hasher) => hasher.finalize().into(),
+            #[cfg(feature = "blake-3")]
+            Self::Blake3(hasher) => *hasher.finalize().as_bytes(),
+        }
+    }
+}
+
 pub fn mac_poly1305(key: &[u8; 32], data: &[u8]) -> [u8; 16] {
-    let mac = <poly1305::Poly1305 as blake2::digest::KeyInit>::new(key.into()).compute_unpadded(data);
+    let mac =
+        <poly1305::Poly1305 as blake2::digest::KeyInit>::new(key.into()).compute_unpadded(data);
     mac.as_slice().try_into().unwrap()
 }
 
@@ -212,7 +258,7 @@ pub fn calculate_mac(
 mod tests {
     use base64::{Engine, prelude::BASE64_STANDARD};
 
-    use crate::crypto::derive_key;
+    use crate::crypto::{Hasher256, derive_key, hash_256};
 
     #[test]
     fn can_derive_blake2b_key() {
@@ -239,4 +285,14 @@ mod tests {
         let mac = super::mac_poly1305(&key, data);
         println!("{}", hex::encode(&mac));
     }
+
+    #[test]
+    fn incremental_hash_matches_hash_of_all_parts() {
+        for blake3 in [false, true] {
+            let mut hasher = Hasher256::new(blake3);
+            hasher.update(b"Hello, ");
+            hasher.update(b"world!");
+            assert_eq!(hash_256(blake3, vec![b"Hello, world!"]), hasher.finalize());
+        }
+    }
 }
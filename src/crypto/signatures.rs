@@ -42,7 +42,65 @@ impl SigningKey {
     }
 }
 
+#[cfg(feature = "protocol")]
+impl SigningKey {
+    /// Generate a new random signing key for the algorithm
+    pub fn generate(algorithm: crate::crypto::SignatureAlgorithm) -> Self {
+        use crate::crypto::SignatureAlgorithm;
+
+        match algorithm {
+            SignatureAlgorithm::Ed25519 => SigningKey::Ed25519(rand::random()),
+            SignatureAlgorithm::Ed448 => SigningKey::Ed448(rand::random()),
+            SignatureAlgorithm::Dsa44 => SigningKey::Dsa44(rand::random()),
+            SignatureAlgorithm::Dsa65 => SigningKey::Dsa65(rand::random()),
+        }
+    }
+}
+
+impl SigningKey {
+    /// Get the public verification key of this signing key. Returns None if the algorithm is not supported
+    pub fn verification_key(&self) -> Option<VerificationKey> {
+        match self {
+            SigningKey::Ed25519(key) => {
+                let key = ed25519_dalek::SigningKey::from_bytes(key);
+                Some(VerificationKey::Ed25519(key.verifying_key().to_bytes()))
+            }
+            SigningKey::Ed448(key) => {
+                let key = ed448_goldilocks::SigningKey::try_from(&key[..]).ok()?;
+                Some(VerificationKey::Ed448(key.verifying_key().to_bytes()))
+            }
+            #[cfg(feature = "pqc-lite")]
+            SigningKey::Dsa44(key) => {
+                use ml_dsa::{KeyGen, MlDsa44};
+
+                let kp = MlDsa44::from_seed(&(*key).into());
+                Some(VerificationKey::Dsa44(kp.verifying_key().encode().into()))
+            }
+            #[cfg(feature = "pqc-lite")]
+            SigningKey::Dsa65(key) => {
+                use ml_dsa::{KeyGen, MlDsa65};
+
+                let kp = MlDsa65::from_seed(&(*key).into());
+                Some(VerificationKey::Dsa65(kp.verifying_key().encode().into()))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl VerificationKey {
+    /// The raw bytes of the key
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            VerificationKey::Ed25519(key) => key,
+            VerificationKey::Ed448(key) => key,
+            VerificationKey::Dsa44(key) => key,
+            VerificationKey::Dsa65(key) => key,
+            VerificationKey::Falcon(key) => key,
+            VerificationKey::SlhDsaSha128s(key) => key,
+        }
+    }
+
     /// Verify signature with data and this key. Returns Some if succeeded, true if valid, false if not valid.
     /// Returns None if failed (algorithm not supported or key invalid)
     pub fn verify(&self, data: &[u8], signature: &CryptoSignature) -> Option<bool> {
@@ -125,6 +183,26 @@ mod tests {
         assert_eq!(Some(false), inv.verify(&data, &signature)); // Invalid key
     }
 
+    #[test]
+    fn can_generate_key_and_verify_with_its_verification_key() {
+        use crate::crypto::SignatureAlgorithm;
+
+        let data = [0u8; 16];
+        for algorithm in [
+            SignatureAlgorithm::Ed25519,
+            SignatureAlgorithm::Ed448,
+            #[cfg(feature = "pqc-lite")]
+            SignatureAlgorithm::Dsa44,
+        ] {
+            let key = SigningKey::generate(algorithm);
+            assert_eq!(algorithm, key.get_algorithm());
+
+            let signature = key.sign(&data).unwrap();
+            let ver = key.verification_key().unwrap();
+            assert_eq!(Some(true), ver.verify(&data, &signature));
+        }
+    }
+
     #[test]
     fn can_sign_and_verify_ed448() {
         use ed448_goldilocks::SigningKey as SK;
@@ -23,6 +23,9 @@ pub enum SerializationError {
 
     /// No encryption/MAC key available
     NoKeyAvailable,
+
+    /// The bucket key of the key provider has an invalid length
+    InvalidBucketKey,
 }
 
 impl From<BinarySerializationError> for SerializationError {
@@ -69,6 +72,9 @@ pub enum DeserializationError {
 
     /// No decryption/MAC key available
     NoKeyAvailable,
+
+    /// The bucket key of the key provider has an invalid length
+    InvalidBucketKey,
 }
 
 impl From<BinaryDeserializationError> for DeserializationError {
@@ -5,8 +5,11 @@ use futures::lock::Mutex;
 
 use crate::{
     protocol::{
-        PlabbleConnection as InnerPlabbleConnection, deserialize_input,
-        error::PlabbleProtocolError, serialize_output,
+        PlabbleConnection as InnerPlabbleConnection,
+        client::pipeline::{PlabbleReader, PlabbleSender},
+        deserialize_input,
+        error::PlabbleProtocolError,
+        serialize_output,
     },
     providers::KeyProvider,
 };
@@ -16,7 +19,8 @@ use crate::{
 /// Callback interface for looking up bucket keys by bucket ID and pre-shared keys.
 #[uniffi::export(callback_interface)]
 pub trait SessionKeyProvider: Send + Sync {
-    /// Given a bucket ID serialized as bytes, return the 32-byte bucket key, or None.
+    /// Given a bucket ID serialized as bytes, return the 32-byte bucket key, or the 64-byte bucket key that was derived
+    /// when the bucket was created. Return None if the bucket key is unknown.
     fn get_bucket_key(&self, bucket_id_bytes: Vec<u8>) -> Option<Vec<u8>>;
 
     /// Given a 12-byte PSK ID, return the 64-byte pre-shared key, or None.
@@ -42,6 +46,13 @@ impl KeyProvider for KeyProviderBridge {
         result.try_into().ok()
     }
 
+    /// Any key that is not 32 bytes is handed out as derived key, so a key of an invalid length fails the packet
+    fn get_derived_bucket_key(&self, bucket_id: &[u8; 16]) -> Option<Vec<u8>> {
+        self.inner
+            .get_bucket_key(bucket_id.to_vec())
+            .filter(|key| key.len() != 32)
+    }
+
     fn get_psk(&self, psk_id: &[u8; 12]) -> Option<[u8; 64]> {
         let result = self.inner.get_psk(psk_id.to_vec())?;
         result.try_into().ok()
@@ -56,9 +67,12 @@ impl KeyProvider for KeyProviderBridge {
 // ── Connection object ───────────────────────────────────────────────────────
 
 /// A Plabble protocol connection handle.
+///
+/// Requests can be sent concurrently with `send_and_recv` while `run_reader` routes the responses in the background.
 #[derive(uniffi::Object)]
 pub struct PlabbleConnection {
-    inner: Mutex<InnerPlabbleConnection>,
+    inner: PlabbleSender,
+    reader: Mutex<Option<PlabbleReader>>,
     tx: Receiver<Vec<u8>>,
     rx: Sender<Vec<u8>>,
 }
@@ -70,10 +84,11 @@ impl PlabbleConnection {
     pub fn new() -> Self {
         let (rx_sender, inner_rx) = async_channel::unbounded();
         let (inner_tx, tx_receiver) = async_channel::unbounded();
-        let inner = InnerPlabbleConnection::new(inner_tx, inner_rx);
+        let (inner, reader) = InnerPlabbleConnection::new(inner_tx, inner_rx).split();
 
         Self {
-            inner: Mutex::new(inner),
+            inner,
+            reader: Mutex::new(Some(reader)),
             tx: tx_receiver,
             rx: rx_sender,
         }
@@ -81,11 +96,13 @@ impl PlabbleConnection {
 
     /// Set a callback for looking up bucket keys by bucket ID.
     pub async fn set_key_provider(&self, provider: Box<dyn SessionKeyProvider>) {
-        let mut inner = self.inner.lock().await;
-        let data = inner.config.data.as_mut().unwrap();
-
         let provider: Arc<dyn SessionKeyProvider> = Arc::from(provider);
-        data.key_provider = Some(Arc::new(KeyProviderBridge::new(provider)));
+        self.inner
+            .with_connection(|inner| {
+                let data = inner.config.data.as_mut().unwrap();
+                data.key_provider = Some(Arc::new(KeyProviderBridge::new(provider)));
+            })
+            .await;
     }
 
     /// Feed raw incoming bytes received from the transport layer into the connection.
@@ -100,6 +117,20 @@ impl PlabbleConnection {
     pub async fn poll_outgoing(&self) -> Option<Vec<u8>> {
         self.tx.recv().await.ok()
     }
+
+    /// Route incoming responses to the waiting `send_and_recv` calls until the connection is closed.
+    /// Run this in the background once the session is started, it can only be run once (a second call fails with a `ReaderRunning` error).
+    /// While the reader runs, `start_session`, `recv_response`, `send_response` and `recv_request` fail with a `ReaderRunning` error.
+    pub async fn run_reader(&self) -> Result<(), PlabbleProtocolError> {
+        let reader = self
+            .reader
+            .lock()
+            .await
+            .take()
+            .ok_or(PlabbleProtocolError::ReaderRunning)?;
+        reader.run().await;
+        Ok(())
+    }
 }
 
 #[cfg(feature = "client")]
@@ -108,21 +139,19 @@ impl PlabbleConnection {
     /// Send a request packet serialized as a JSON (or TOML) string.
     pub async fn send_request(&self, request: String) -> Result<(), PlabbleProtocolError> {
         let packet = deserialize_input(&request)?;
-        let mut inner = self.inner.lock().await;
-        inner.send_request(packet).await
+        self.inner.send_request(packet).await
     }
 
     /// Send a request packet and wait for the associated response, returning it as a JSON (or TOML) string.
     pub async fn send_and_recv(&self, request: String) -> Result<String, PlabbleProtocolError> {
         let packet = deserialize_input(&request)?;
-        let mut inner = self.inner.lock().await;
-        let response = inner.send_and_recv(packet).await?;
+        let response = self.inner.send_and_recv(packet).await?;
         serialize_output(&response)
     }
 
     /// Wait for the next incoming response packet and return it as a JSON (or TOML) string.
     pub async fn recv_response(&self) -> Result<String, PlabbleProtocolError> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await?;
         let response = inner.recv_response().await?;
         serialize_output(&response)
     }
@@ -132,12 +161,13 @@ impl PlabbleConnection {
 #[uniffi::export]
 impl PlabbleConnection {
     /// Start a new session with the given options serialized as a JSON (or TOML) string. Returns the PSK ID as 12-byte array if a pre-shared key is created.
+    /// The session must be started before `run_reader` is called.
     pub async fn start_session(
         &self,
         options: Option<String>,
     ) -> Result<Option<Vec<u8>>, PlabbleProtocolError> {
         let options = options.map(|opts| deserialize_input(&opts)).transpose()?;
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await?;
         let psk_id = inner.start_session(options).await?;
         Ok(psk_id.map(|id| id.to_vec()))
     }
@@ -149,13 +179,13 @@ impl PlabbleConnection {
     /// Send a response packet serialized as a JSON (or TOML) string.
     pub async fn send_response(&self, response: String) -> Result<(), PlabbleProtocolError> {
         let packet = deserialize_input(&response)?;
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await?;
         inner.send_response(packet).await
     }
 
     /// Wait for the next incoming request packet and return it as a JSON (or TOML) string.
     pub async fn recv_request(&self) -> Result<String, PlabbleProtocolError> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.inner.lock().await?;
         let request = inner.recv_request().await?;
         serialize_output(&request)
     }
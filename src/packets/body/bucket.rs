@@ -6,6 +6,8 @@ use serde_with::base64::{Base64, UrlSafe};
 use serde_with::formats::Unpadded;
 use serde_with::{DisplayFromStr, serde_as};
 
+use crate::core::BucketId;
+
 /// Bucket query structure used for querying bucket data
 /// with a specific ID and range.
 /// The range can be either numeric or binary, depending on the bucket type.
@@ -19,10 +21,10 @@ pub struct BucketQuery {
     #[serde(default)]
     #[dyn_int]
     #[toggled_by = "limit"]
-    limit: Option<u32>,
+    pub limit: Option<u32>,
 
     #[variant_by = "binary_keys"]
-    range: BucketRange,
+    pub range: BucketRange,
 }
 
 /// Bucket put request structure used for inserting data into a bucket
@@ -34,7 +36,41 @@ pub struct BucketQuery {
 #[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone)]
 pub struct PutRequestBody {
     #[variant_by = "binary_keys"]
-    body: BucketBody,
+    pub body: BucketBody,
+}
+
+/// Bucket put response structure (for PUT response)
+///
+/// # Members
+/// - `slots`: The numeric slots the values were appended to (in order), only if the request was in append mode
+#[serde_as]
+#[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct PutResponseBody {
+    #[serde(default)]
+    #[dyn_int]
+    #[toggled_by = "append"]
+    pub slots: Option<Vec<u32>>,
+}
+
+/// Subscription update structure, pushed by the server to subscribers when slots in a bucket change
+/// (fire-and-forget SUBSCRIBE response with the `update` flag set)
+///
+/// # Members
+/// - `id`: The ID of the bucket that changed
+/// - `offset`: The byte offset the bytes in the slot were written at, if the update is for a STREAM write (`stream` flag)
+/// - `slots`: The changed slots (within the subscribed range) with their new values, or with their old values if the slots were deleted.
+///   For a STREAM write it only contains the written bytes of the slot.
+#[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone)]
+pub struct SubscriptionUpdate {
+    pub id: BucketId,
+
+    #[serde(default)]
+    #[dyn_int]
+    #[toggled_by = "stream"]
+    pub offset: Option<u64>,
+
+    #[variant_by = "binary_keys"]
+    pub slots: BucketBody,
 }
 
 /// Bucket body structure used for representing the data within a bucket.
@@ -93,6 +129,95 @@ pub enum BucketRange {
     ),
 }
 
+impl BucketBody {
+    /// Get the slots that are within any of the ranges, or None if there are none
+    pub fn filter(&self, ranges: &[BucketRange]) -> Option<BucketBody> {
+        let body = match self {
+            BucketBody::Numeric(slots) => BucketBody::Numeric(
+                slots
+                    .iter()
+                    .filter(|(slot, _)| ranges.iter().any(|r| r.contains_slot(**slot)))
+                    .map(|(slot, value)| (*slot, value.clone()))
+                    .collect(),
+            ),
+            BucketBody::Binary(slots) => BucketBody::Binary(
+                slots
+                    .iter()
+                    .filter(|(key, _)| ranges.iter().any(|r| r.contains_key(key)))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect(),
+            ),
+        };
+
+        (!body.is_empty()).then_some(body)
+    }
+
+    /// Indicates if the body contains no slots
+    pub fn is_empty(&self) -> bool {
+        match self {
+            BucketBody::Numeric(slots) => slots.is_empty(),
+            BucketBody::Binary(slots) => slots.is_empty(),
+        }
+    }
+}
+
+impl BucketRange {
+    /// Check if a numeric slot is within the (inclusive) range. Always false for binary ranges.
+    pub fn contains_slot(&self, slot: u32) -> bool {
+        match self {
+            BucketRange::Numeric(from, to) => {
+                from.is_none_or(|from| slot >= from) && to.is_none_or(|to| slot <= to)
+            }
+            BucketRange::Binary(..) => false,
+        }
+    }
+
+    /// Check if a binary key is within the (inclusive) range. Always false for numeric ranges.
+    pub fn contains_key(&self, key: &str) -> bool {
+        match self {
+            BucketRange::Binary(from, to) => {
+                from.as_deref().is_none_or(|from| key >= from)
+                    && to.as_deref().is_none_or(|to| key <= to)
+            }
+            BucketRange::Numeric(..) => false,
+        }
+    }
+
+    /// Check if the range has no bounds at all (covering every slot of its variant)
+    pub fn is_unbounded(&self) -> bool {
+        matches!(
+            self,
+            BucketRange::Numeric(None, None) | BucketRange::Binary(None, None)
+        )
+    }
+
+    /// Apply the `range_mode_until` header flag to the range.
+    /// If set, a range with only a start bound is turned into a range with only an end bound (until).
+    pub fn with_range_mode(self, range_mode_until: bool) -> Self {
+        match self {
+            BucketRange::Numeric(Some(end), None) if range_mode_until => {
+                BucketRange::Numeric(None, Some(end))
+            }
+            BucketRange::Binary(Some(end), None) if range_mode_until => {
+                BucketRange::Binary(None, Some(end))
+            }
+            range => range,
+        }
+    }
+
+    /// Get the range as it is sent in a request, together with the `range_mode_until` header flag (the inverse of [`Self::with_range_mode`]).
+    /// A range with only an end bound is sent as a single value with the flag set.
+    pub fn to_range_mode(&self) -> (BucketRange, bool) {
+        match self {
+            BucketRange::Numeric(None, Some(end)) => (BucketRange::Numeric(Some(*end), None), true),
+            BucketRange::Binary(None, Some(end)) => {
+                (BucketRange::Binary(Some(end.clone()), None), true)
+            }
+            range => (range.clone(), false),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use binary_codec::{BinaryDeserializer, BinarySerializer, SerializerConfig};
@@ -558,4 +683,94 @@ mod tests {
         let deserialized = PlabbleResponsePacket::from_bytes(&serialized, None).unwrap();
         assert_eq!(packet, deserialized);
     }
+
+    #[test]
+    fn can_serialize_and_deserialize_put_response_with_appended_slots() {
+        let packet: PlabbleResponsePacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            request_counter = 1
+            append = true
+
+            [body]
+            slots = [10, 300]
+        "#,
+        )
+        .unwrap();
+
+        let serialized = packet.to_bytes(None).unwrap();
+        assert_eq!(0b0001_0110, serialized[1]);
+        assert_eq!("011600010aac02", hex::encode(&serialized));
+
+        let deserialized = PlabbleResponsePacket::from_bytes(&serialized, None).unwrap();
+        assert_eq!(packet, deserialized);
+    }
+
+    #[test]
+    fn can_serialize_and_deserialize_subscription_update() {
+        let packet: PlabbleResponsePacket = toml::from_str(
+            r#"
+            version = 1
+            fire_and_forget = true
+
+            [header]
+            packet_type = "Subscribe"
+            binary_keys = true
+            update = true
+            deleted = true
+
+            [body]
+            id = "AAAAAAAAAAAAAAAAAAAAAA"
+            slots.Binary = { key = "AQ" }
+        "#,
+        )
+        .unwrap();
+
+        let serialized = packet.to_bytes(None).unwrap();
+
+        // version 0001, fire_and_forget flag. Packet type 8, flags 0111 (no request counter)
+        // 16 bytes id, key length 3, key, value length 1, value
+        assert_eq!(
+            format!("1178{}036b65790101", "00".repeat(16)),
+            hex::encode(&serialized)
+        );
+
+        let deserialized = PlabbleResponsePacket::from_bytes(&serialized, None).unwrap();
+        assert_eq!(packet, deserialized);
+    }
+
+    #[test]
+    fn can_serialize_and_deserialize_stream_subscription_update() {
+        let packet: PlabbleResponsePacket = toml::from_str(
+            r#"
+            version = 1
+            fire_and_forget = true
+
+            [header]
+            packet_type = "Subscribe"
+            update = true
+            stream = true
+
+            [body]
+            id = "AAAAAAAAAAAAAAAAAAAAAA"
+            offset = 300
+            slots.Numeric = { 5 = "AQ" }
+        "#,
+        )
+        .unwrap();
+
+        let serialized = packet.to_bytes(None).unwrap();
+
+        // Packet type 8, flags 1010 (no request counter)
+        // 16 bytes id, offset 300 (dyn int), slot 5, value length 1, value
+        assert_eq!(
+            format!("11a8{}ac02050101", "00".repeat(16)),
+            hex::encode(&serialized)
+        );
+        let deserialized = PlabbleResponsePacket::from_bytes(&serialized, None).unwrap();
+        assert_eq!(packet, deserialized);
+    }
 }
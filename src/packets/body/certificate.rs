@@ -1,4 +1,4 @@
-use binary_codec::{FromBytes, ToBytes};
+use binary_codec::{BinarySerializer, FromBytes, SerializationError, SerializerConfig, ToBytes};
 use serde::{Deserialize, Serialize};
 use serde_with::base64::{Base64, UrlSafe};
 use serde_with::formats::Unpadded;
@@ -6,6 +6,7 @@ use serde_with::serde_as;
 
 use crate::crypto::algorithm::CryptoSignature;
 use crate::crypto::certificate::Certificate;
+use crate::packets::base::settings::CryptoSettings;
 
 /// Certificate request body
 #[serde_as]
@@ -14,12 +15,12 @@ pub struct CertificateRequestBody {
     /// Id of the certificate to query
     #[toggled_by = "query_mode"]
     #[serde_as(as = "Option<Base64<UrlSafe, Unpadded>>")]
-    id: Option<[u8; 16]>,
+    pub id: Option<[u8; 16]>,
 
     /// Client-side generated random challenge the server MUST sign when provided, to prove its identity
     #[toggled_by = "challenge"]
     #[serde_as(as = "Option<Base64<UrlSafe, Unpadded>>")]
-    challenge: Option<[u8; 16]>,
+    pub challenge: Option<[u8; 16]>,
 }
 
 /// Certificate response body
@@ -28,10 +29,34 @@ pub struct CertificateResponseBody {
     /// Signatures of the server to prove its identity and authenticity of the message
     /// For each algorithm in the crypto settings header, generate a signature of the challenge (optionally) + all full certificates (in order)
     #[multi_enum]
-    signatures: Vec<CryptoSignature>,
+    pub signatures: Vec<CryptoSignature>,
 
     /// Certificate chain (list in order, first certificate = bottom of chain, last certificate = top of chain)
-    certificates: Vec<Certificate>,
+    pub certificates: Vec<Certificate>,
+}
+
+impl CertificateResponseBody {
+    /// Create the data the server signs to prove its identity: the challenge (if provided) followed by all certificates (as bytes, in order)
+    ///
+    /// The certificates are serialized using the toggles of the given crypto settings, the same way as they are sent in the response packet.
+    pub fn signature_data(
+        challenge: Option<&[u8; 16]>,
+        certificates: &[Certificate],
+        settings: &CryptoSettings,
+    ) -> Result<Vec<u8>, SerializationError> {
+        let mut data = Vec::new();
+        if let Some(challenge) = challenge {
+            data.extend_from_slice(challenge);
+        }
+
+        for certificate in certificates {
+            let mut config = SerializerConfig::<()>::new(None);
+            settings.apply_to(&mut config);
+            data.extend(certificate.to_bytes(Some(&mut config))?);
+        }
+
+        Ok(data)
+    }
 }
 
 #[cfg(test)]
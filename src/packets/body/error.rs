@@ -35,11 +35,46 @@ pub enum PlabbleError {
     /// Bucket with that ID already exists
     BucketAlreadyExists = 11,
 
+    /// Slot with that key already exists (when asserting keys)
+    SlotAlreadyExists = 12,
+
+    /// Bucket has no free numeric slots left to append to
+    BucketFull = 13,
+
+    /// The bucket permissions do not allow this operation
+    PermissionDenied = 14,
+
+    /// The bucket permissions are locked and cannot be updated
+    PermissionsLocked = 15,
+
+    /// The bucket ACL is locked and cannot be updated
+    AclLocked = 16,
+
+    /// Slot with that key does not exist
+    SlotNotFound = 17,
+
     /* certificate errors: 110-115 */
     /// Certificate by ID not found
     CertificateNotFound = 110,
     /// Requested certificate is not valid (according to server)
     CertificateInvalid = 111,
+    /// The claims to register are malformed or not accepted by the server
+    InvalidClaims = 112,
+    /// A certificate with the same claims is already registered
+    ClaimsAlreadyRegistered = 113,
+    /// The identity could not be verified: the signatures are invalid, the timestamp is outside the accepted window
+    /// or the certificate chain is not trusted
+    IdentificationFailed = 114,
+
+    /* proxy errors: 120-125 */
+    /// The PROXY tunnel with that ID does not exist (anymore)
+    TunnelNotFound = 120,
+    /// The next hop or target of a PROXY tunnel could not be reached
+    HopUnreachable = 121,
+    /// Another frame is being relayed through the PROXY tunnel, frames must be sent one by one
+    TunnelBusy = 122,
+    /// The server has the maximum amount of PROXY tunnels open
+    TooManyTunnels = 123,
 
     // ...
     /// OPCODE script execution error
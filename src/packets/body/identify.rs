@@ -9,17 +9,32 @@ use crate::crypto::certificate::Certificate;
 #[derive(FromBytes, ToBytes, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct IdentifyRequestBody {
     /// The timestamp, the server will check if it is within an acceptible range (e.g. a few minutes) to prevent replay attacks
-    timestamp: PlabbleDateTime,
+    pub timestamp: PlabbleDateTime,
 
     /// Signatures of the timestamp, the server ID (certificate ID of the server certificate) and the Session Key.
     /// Algorithms based on crypto_settings in base packet
     #[multi_enum]
-    signatures: Vec<CryptoSignature>,
+    pub signatures: Vec<CryptoSignature>,
 
     /// Certificate chain (list in order, first certificate = bottom of chain, last certificate = top of chain)
     /// This is mostly of the time just a single (partial/non-full) certificate that only contains the certificate header data
     /// Its goal is to make the server able to verify the signatures and get the identity of the user (certificate ID)
-    certificates: Vec<Certificate>,
+    pub certificates: Vec<Certificate>,
+}
+
+impl IdentifyRequestBody {
+    /// Data the client signs to prove its identity: the timestamp (u32-BE), the server certificate ID and the session key.
+    /// The session key binds the identity to the current session, so the request cannot be replayed in another session.
+    pub fn signature_data(
+        timestamp: &PlabbleDateTime,
+        server_id: &[u8; 16],
+        session_key: &[u8; 64],
+    ) -> Vec<u8> {
+        let mut data = timestamp.timestamp().to_be_bytes().to_vec();
+        data.extend_from_slice(server_id);
+        data.extend_from_slice(session_key);
+        data
+    }
 }
 
 #[cfg(test)]
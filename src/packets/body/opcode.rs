@@ -10,7 +10,7 @@ use crate::scripting::opcode_script::OpcodeScript;
 #[derive(FromBytes, ToBytes, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct OpCodeRequestBody {
     /// The script to run
-    script: OpcodeScript,
+    pub script: OpcodeScript,
 }
 
 /// OPCODE script response from server
@@ -19,7 +19,7 @@ pub struct OpCodeRequestBody {
 pub struct OpCodeResponseBody {
     /// The result of the script execution, if any
     #[serde_as(as = "Option<Hex<Lowercase>>")]
-    result: Option<Vec<u8>>,
+    pub result: Option<Vec<u8>>,
 }
 
 #[cfg(test)]
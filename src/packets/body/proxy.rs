@@ -77,11 +77,29 @@ pub enum ProxyResponseBody {
 pub struct HopInfo {
     /// Public keys or encapsulated secret for creating a shared secret
     #[multi_enum]
-    keys: Vec<KeyExhangeResponse>,
+    pub keys: Vec<KeyExhangeResponse>,
 
     /// Signatures of the request public key
     #[multi_enum]
-    signatures: Vec<CryptoSignature>,
+    pub signatures: Vec<CryptoSignature>,
+}
+
+impl HopInfo {
+    /// Data a hop signs with its server certificate: the raw request keys of the client, followed by the raw response keys of the hop.
+    /// Signing the response keys too prevents a man-in-the-middle from replacing them.
+    pub fn signature_data(
+        request_keys: &[KeyExhangeRequest],
+        response_keys: &[KeyExhangeResponse],
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        for key in request_keys {
+            data.extend_from_slice(key.as_bytes());
+        }
+        for key in response_keys {
+            data.extend_from_slice(key.as_bytes());
+        }
+        data
+    }
 }
 
 #[cfg(test)]
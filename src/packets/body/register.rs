@@ -1,24 +1,68 @@
 use binary_codec::{FromBytes, ToBytes};
 use serde::{Deserialize, Serialize};
 
-use crate::crypto::algorithm::VerificationKey;
+use crate::{core::Claims, crypto::algorithm::VerificationKey, packets::body::error::PlabbleError};
 
 /// Register a new identity on the server, which can be used for authentication in future sessions.
 #[derive(FromBytes, ToBytes, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct RegisterRequestBody {
     /// Public keys (new generated) for all algorithms specified in `crypto_settings` in the packet base.
     #[multi_enum]
-    keys: Vec<VerificationKey>,
+    pub keys: Vec<VerificationKey>,
 
-    /// Claims to register, key-value, UTF-8. Separated by ;, like `USERNAME=henk;AGE=12`
-    claims: String,
+    /// Claims to register, key-value, UTF-8. Separated by ;, like `USERNAME=henk;AGE=12`. See [`Claims`].
+    pub claims: Claims,
+}
+
+impl RegisterRequestBody {
+    /// Parse the claims into key-value pairs (in order).
+    /// Fails with `PlabbleError::InvalidClaims` if there are no claims, or if they are not well-formed (see [`Claims`]):
+    /// if a key is empty, not uppercase alphanumeric (or `_`) or used twice, or if a value is empty or contains an unescaped `=`.
+    pub fn parse_claims(&self) -> Result<Vec<(&str, &str)>, PlabbleError> {
+        if self.claims.is_empty() || !self.claims.is_well_formed() {
+            return Err(PlabbleError::InvalidClaims);
+        }
+
+        Ok(self.claims.iter().collect())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use binary_codec::{BinaryDeserializer, BinarySerializer};
 
-    use crate::packets::request::PlabbleRequestPacket;
+    use crate::packets::{
+        body::{error::PlabbleError, register::RegisterRequestBody},
+        request::PlabbleRequestPacket,
+    };
+
+    #[test]
+    fn can_parse_and_validate_claims() {
+        let body = |claims: &str| RegisterRequestBody {
+            keys: vec![],
+            claims: toml::Value::String(claims.to_string()).try_into().unwrap(),
+        };
+
+        assert_eq!(
+            Ok(vec![("USERNAME", "henk"), ("AGE", "24")]),
+            body("USERNAME=henk;AGE=24").parse_claims()
+        );
+
+        for invalid in [
+            "",
+            "USERNAME",
+            "username=henk",
+            "A=1;A=2",
+            "A=",
+            "A=1=2",
+            "A=1;",
+        ] {
+            assert_eq!(
+                Err(PlabbleError::InvalidClaims),
+                body(invalid).parse_claims()
+            );
+        }
+    }
 
     #[test]
     fn can_serialize_and_deserialize_register_request() {
@@ -4,9 +4,15 @@ use serde::{Deserialize, Serialize};
 use crate::{
     crypto::certificate::Certificate,
     packets::body::{
-        bucket::BucketBody, certificate::CertificateResponseBody, custom::CustomBody,
-        error::PlabbleError, opcode::OpCodeResponseBody, proxy::ProxyResponseBody,
-        session::SessionResponseBody, stream::StreamResponseBody, whisper::WhisperResponseBody,
+        bucket::{BucketBody, PutResponseBody, SubscriptionUpdate},
+        certificate::CertificateResponseBody,
+        custom::CustomBody,
+        error::PlabbleError,
+        opcode::OpCodeResponseBody,
+        proxy::ProxyResponseBody,
+        session::SessionResponseBody,
+        stream::StreamResponseBody,
+        whisper::WhisperResponseBody,
     },
 };
 
@@ -22,9 +28,9 @@ use crate::{
 /// - `Stream`: Represents a stream response body.
 /// - `Post`: Represents a post response body.
 /// - `Patch`: Represents a patch response body.
-/// - `Put`: Represents a put response body.
+/// - `Put`: Represents a put response body, with the appended slots in append mode.
 /// - `Delete`: Represents a delete response body.
-/// - `Subscribe`: Represents a subscribe/unsubscribe response body.
+/// - `Subscribe`: Represents a subscribe/unsubscribe response body, or a subscription update.
 /// - `Whisper`: Represents a whisper response body.
 /// - `Register`: Represents a register response body.
 /// - `Identity`: Represents an identity response body.
@@ -43,13 +49,13 @@ pub enum PlabbleResponseBody {
     Stream(StreamResponseBody) = 3,
     Post = 4,
     Patch = 5,
-    Put = 6,
+    Put(PutResponseBody) = 6,
     Delete(
         #[toggled_by = "return_deleted"]
         #[variant_by = "binary_keys"]
         Option<BucketBody>,
     ) = 7,
-    Subscribe = 8,
+    Subscribe(#[toggled_by = "update"] Option<SubscriptionUpdate>) = 8,
     Whisper(#[variant_by = "type"] WhisperResponseBody) = 9,
     Register(Certificate) = 10,
     Identity = 11,
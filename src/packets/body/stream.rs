@@ -4,6 +4,8 @@ use serde_with::base64::{Base64, UrlSafe};
 use serde_with::formats::Unpadded;
 use serde_with::serde_as;
 
+use crate::packets::body::bucket::{BucketBody, BucketRange};
+
 /// Request body for stream operations, which can be either read or write (append) operations on a slot.
 #[serde_as]
 #[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone)]
@@ -13,12 +15,12 @@ pub struct StreamRequestBody {
     #[dyn_length]
     #[toggled_by = "write_mode"]
     #[serde_as(as = "Option<Base64<UrlSafe, Unpadded>>")]
-    data: Option<Vec<u8>>,
+    pub data: Option<Vec<u8>>,
 
     /// The slot to be streamed, which can be identified by a numeric ID or a binary key
     /// plus the range of bytes to select
     #[variant_by = "binary_keys"]
-    range: SlotRange,
+    pub range: SlotRange,
 }
 
 /// Response body for stream operations, which can include either the new size of the slot (for writes) or the data read from the slot (for reads).
@@ -29,16 +31,19 @@ pub struct StreamResponseBody {
     #[serde(default)]
     #[dyn_int]
     #[toggled_by = "write_mode"]
-    new_size: Option<u64>,
+    pub new_size: Option<u64>,
 
     /// Optional data read from the slot, present only in read mode
     #[serde(default)]
     #[toggled_by = "!write_mode"]
     #[serde_as(as = "Option<Base64<UrlSafe, Unpadded>>")]
-    data: Option<Vec<u8>>,
+    pub data: Option<Vec<u8>>,
 }
 
 /// Range of bytes within a slot to be streamed. Can be binary or numeric depending on the slot type
+///
+/// The byte range is inclusive on both ends, a missing bound means the range is open on that side.
+/// In write mode, the start of the range is the byte offset to write at (the data is appended if it is missing).
 #[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone)]
 #[no_discriminator]
 pub enum SlotRange {
@@ -62,11 +67,110 @@ pub enum SlotRange {
     ),
 }
 
+/// Key of a single slot, either a numeric slot or a binary key
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum SlotKey {
+    Numeric(u32),
+    Binary(String),
+}
+
+impl SlotKey {
+    /// Create a range of bytes within this slot
+    pub fn range(&self, from: Option<u64>, to: Option<u64>) -> SlotRange {
+        match self {
+            SlotKey::Numeric(slot) => SlotRange::Numeric(*slot, from, to),
+            SlotKey::Binary(key) => SlotRange::Binary(key.clone(), from, to),
+        }
+    }
+}
+
+impl SlotRange {
+    /// Get the (single-slot) bucket range of the slot that is streamed
+    pub fn slot(&self) -> BucketRange {
+        match self {
+            SlotRange::Numeric(slot, ..) => BucketRange::Numeric(Some(*slot), Some(*slot)),
+            SlotRange::Binary(key, ..) => BucketRange::Binary(Some(key.clone()), Some(key.clone())),
+        }
+    }
+
+    /// Create a bucket body with the value in the slot that is streamed
+    pub fn body(&self, value: Vec<u8>) -> BucketBody {
+        match self {
+            SlotRange::Numeric(slot, ..) => BucketBody::Numeric([(*slot, value)].into()),
+            SlotRange::Binary(key, ..) => BucketBody::Binary([(key.clone(), value)].into()),
+        }
+    }
+
+    /// Get the (inclusive) start and end bounds of the byte range
+    pub fn bounds(&self) -> (Option<u64>, Option<u64>) {
+        match self {
+            SlotRange::Numeric(_, from, to) | SlotRange::Binary(_, from, to) => (*from, *to),
+        }
+    }
+
+    /// Apply the `range_mode_until` header flag to the byte range.
+    /// If set, a range with only a start bound is turned into a range with only an end bound (until).
+    pub fn with_range_mode(self, range_mode_until: bool) -> Self {
+        match self {
+            SlotRange::Numeric(slot, Some(end), None) if range_mode_until => {
+                SlotRange::Numeric(slot, None, Some(end))
+            }
+            SlotRange::Binary(key, Some(end), None) if range_mode_until => {
+                SlotRange::Binary(key, None, Some(end))
+            }
+            range => range,
+        }
+    }
+
+    /// Select the bytes within the range from the value of the slot.
+    /// Bounds beyond the end of the value are clamped, so the selection may be empty.
+    pub fn select<'a>(&self, value: &'a [u8]) -> &'a [u8] {
+        let len = value.len() as u64;
+        let (from, to) = self.bounds();
+        let start = from.unwrap_or(0).min(len);
+        let end = to.map_or(len, |to| to.saturating_add(1).min(len));
+
+        if start >= end {
+            return &[];
+        }
+
+        &value[start as usize..end as usize]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use binary_codec::{BinaryDeserializer, BinarySerializer};
 
-    use crate::packets::{request::PlabbleRequestPacket, response::PlabbleResponsePacket};
+    use crate::packets::{
+        body::{bucket::BucketRange, stream::SlotRange},
+        request::PlabbleRequestPacket,
+        response::PlabbleResponsePacket,
+    };
+
+    #[test]
+    fn can_select_byte_range_from_slot() {
+        let value = b"0123456789";
+
+        let range = SlotRange::Numeric(7, Some(2), Some(4));
+        assert_eq!(BucketRange::Numeric(Some(7), Some(7)), range.slot());
+        assert_eq!(b"234", range.select(value));
+
+        let range = SlotRange::Binary("file".into(), Some(8), None);
+        assert_eq!(b"89", range.select(value));
+        assert_eq!(b"012345678", range.with_range_mode(true).select(value));
+
+        assert!(
+            SlotRange::Numeric(7, Some(20), None)
+                .select(value)
+                .is_empty()
+        );
+        assert!(
+            SlotRange::Numeric(7, Some(4), Some(2))
+                .select(value)
+                .is_empty()
+        );
+    }
 
     #[test]
     fn can_serialize_and_deserialize_stream_get_request() {
@@ -1,10 +1,16 @@
 use std::sync::Arc;
 
+#[cfg(feature = "protocol")]
+use crate::protocol::custom::SubProtocolRegistry;
+#[cfg(all(feature = "server", feature = "implementation"))]
+use crate::protocol::server::{
+    options::ServerOptions, proxy::TunnelRegistry, subscriptions::Subscriber,
+};
 use crate::{
     core::BucketId,
     crypto::{derive_key, hash_256, hash_512},
     packets::base::{PlabblePacketBase, settings::CryptoSettings},
-    providers::KeyProvider,
+    providers::{BucketStore, CertificateProvider, KeyProvider, ProxyRelay},
 };
 
 /// Connection context for cryptography, counters, session etc.
@@ -14,6 +20,31 @@ pub struct PlabbleConnectionContext {
     /// Key provider for looking up bucket keys/PSKs
     pub key_provider: Option<Arc<dyn KeyProvider>>,
 
+    /// Certificate provider for looking up the server certificate and other certificates
+    pub certificate_provider: Option<Arc<dyn CertificateProvider>>,
+
+    /// Bucket store for storing buckets and their slots (server only)
+    pub bucket_store: Option<Arc<dyn BucketStore>>,
+
+    /// Relay for reaching other servers as hop or target of PROXY tunnels (server only)
+    pub proxy_relay: Option<Arc<dyn ProxyRelay>>,
+
+    /// Registry of the PROXY tunnels through the server (server only)
+    #[cfg(all(feature = "server", feature = "implementation"))]
+    pub tunnels: Option<Arc<TunnelRegistry>>,
+
+    /// Subscriber handle of this connection in the subscription registry of the server (server only)
+    #[cfg(all(feature = "server", feature = "implementation"))]
+    pub subscriber: Option<Subscriber>,
+
+    /// Options of the server this connection belongs to (server only)
+    #[cfg(all(feature = "server", feature = "implementation"))]
+    pub server_options: ServerOptions,
+
+    /// Handlers of the sub-protocols that run on CUSTOM packets
+    #[cfg(feature = "protocol")]
+    pub sub_protocols: Option<Arc<SubProtocolRegistry>>,
+
     /// Session key, if in a session
     pub session_key: Option<[u8; 64]>,
 
@@ -33,6 +64,12 @@ pub struct PlabbleConnectionContext {
     /// When sending a packet, whether to include the bucket key in the authenticated data (for MAC and encryption).
     pub include_bucket_key_in_auth_data: bool,
 
+    /// Whether the last received request was authenticated with the bucket key in the authenticated data (server only)
+    pub bucket_key_authenticated: bool,
+
+    /// ID of the certificate the client proved its identity with using IDENTIFY, if any (server only)
+    pub identity: Option<[u8; 16]>,
+
     /// PSK used in the current connection
     pub session_psk: Option<[u8; 64]>,
 
@@ -51,12 +88,25 @@ impl PlabbleConnectionContext {
     pub fn new() -> Self {
         Self {
             key_provider: None,
+            certificate_provider: None,
+            bucket_store: None,
+            proxy_relay: None,
+            #[cfg(all(feature = "server", feature = "implementation"))]
+            tunnels: None,
+            #[cfg(all(feature = "server", feature = "implementation"))]
+            subscriber: None,
+            #[cfg(all(feature = "server", feature = "implementation"))]
+            server_options: ServerOptions::default(),
+            #[cfg(feature = "protocol")]
+            sub_protocols: None,
             session_key: None,
             crypto_settings: None,
             full_encryption: false,
             client_counter: 0,
             server_counter: 0,
             include_bucket_key_in_auth_data: false,
+            bucket_key_authenticated: false,
+            identity: None,
             session_psk: None,
             session_salt: None,
         }
@@ -83,28 +133,40 @@ impl PlabbleConnectionContext {
     ///
     /// # Parameters
     /// - `raw_base_and_header`: The raw bytes of the packet base and header,
-    /// - `bucket_id`: The bucket ID, used to retrieve the bucket key if available. If not given or not found, the bucket key is not included in the authenticated data.
+    /// - `bucket_id`: The bucket ID, used to retrieve the bucket key if available (from the bucket store, or else the key provider).
+    ///   If not given or not found, the bucket key is not included in the authenticated data.
     ///
     /// # Returns
-    /// The authenticated data as a 32-byte array, or None if hashing failed (when blake3 is requested but not supported by server).
+    /// The authenticated data as a 32-byte array, or None if the derived bucket key of the key provider is not 64 bytes long.
     pub fn create_authenticated_data(
         &self,
         raw_base_and_header: &[u8],
         bucket_id: Option<&BucketId>,
-    ) -> [u8; 32] {
-        let bucket_key = bucket_id.and_then(|id| {
-            self.key_provider
+    ) -> Option<[u8; 32]> {
+        let mut bucket_key = None;
+        if let Some(id) = bucket_id {
+            if let Some(key) = self
+                .bucket_store
                 .as_ref()
-                .and_then(|provider| provider.get_bucket_key(&id.data))
-        });
+                .and_then(|store| store.get_bucket_key(id).ok())
+            {
+                bucket_key = Some(key.to_vec());
+            } else if let Some(provider) = &self.key_provider {
+                bucket_key = match provider.get_derived_bucket_key(&id.data) {
+                    Some(key) if key.len() != 64 => return None,
+                    Some(key) => Some(key),
+                    None => provider.get_bucket_key(&id.data).map(|key| key.to_vec()),
+                };
+            }
+        }
 
         let mut data = Vec::new();
         data.push(raw_base_and_header);
         if let Some(ref bucket_key) = bucket_key {
-            data.push(bucket_key);
+            data.push(bucket_key.as_slice());
         }
 
-        hash_256(self.use_blake3(), data)
+        Some(hash_256(self.use_blake3(), data))
     }
 
     /// Create a cryptographic key based on the context and packet base for authentication or encryption
@@ -218,6 +280,24 @@ pub mod helpers {
             // Do nothing for testing
         }
     }
+
+    /// Key provider that only knows a derived bucket key (of any length, for testing)
+    pub struct DerivedKeyProvider(pub Vec<u8>);
+    impl KeyProvider for DerivedKeyProvider {
+        fn get_bucket_key(&self, _bucket_id: &[u8; 16]) -> Option<[u8; 32]> {
+            Some([0; 32])
+        }
+
+        fn get_derived_bucket_key(&self, _bucket_id: &[u8; 16]) -> Option<Vec<u8>> {
+            Some(self.0.clone())
+        }
+
+        fn get_psk(&self, _psk_id: &[u8; 12]) -> Option<[u8; 64]> {
+            None
+        }
+
+        fn store_psk(&self, _psk_id: [u8; 12], _psk: [u8; 64], _expiration: Option<u32>) {}
+    }
 }
 
 #[cfg(test)]
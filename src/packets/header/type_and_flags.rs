@@ -266,7 +266,12 @@ pub enum ResponsePacketType {
     /// Response to a patch request.
     Patch = 5,
     /// Response to a put request.
-    Put = 6,
+    /// - append: Indicates the response body contains the slots the values were appended to (matches the request's `append` flag).
+    Put {
+        #[serde(default)]
+        #[toggles("append")]
+        append: bool,
+    } = 6,
     /// Response to a delete request.
     ///
     /// - return_deleted: Indicates the response body contains the deleted entries (matches the request's `return_deleted` flag).
@@ -280,8 +285,27 @@ pub enum ResponsePacketType {
         #[toggles("binary_keys")]
         binary_keys: bool,
     } = 7,
-    /// Response to a subscribe/unsubscribe request.
-    Subscribe = 8,
+    /// Response to a subscribe/unsubscribe request, or a subscription update pushed by the server.
+    /// - binary_keys: Keys in the update are in binary format (only if `update` is set).
+    /// - update: Indicates this is a (fire-and-forget) subscription update containing the changed slots.
+    /// - deleted: Indicates the slots in the update were deleted (only if `update` is set).
+    /// - stream: Indicates the update only contains the bytes that were streamed into a slot, at an offset (only if `update` is set).
+    Subscribe {
+        #[serde(default)]
+        #[toggles("binary_keys")]
+        binary_keys: bool,
+
+        #[serde(default)]
+        #[toggles("update")]
+        update: bool,
+
+        #[serde(default)]
+        deleted: bool,
+
+        #[serde(default)]
+        #[toggles("stream")]
+        stream: bool,
+    } = 8,
     /// Whisper response (e.g., for server<->server replication or custom server<->server communication)
     /// - whisper_type: Type of whisper message
     Whisper {
@@ -70,7 +70,8 @@ impl BinarySerializer<PlabbleConnectionContext, SerializationError> for PlabbleR
                         } else {
                             None
                         },
-                    ),
+                    )
+                    .ok_or(SerializationError::InvalidBucketKey)?,
                 )
                 .ok_or(SerializationError::EncryptionFailed)?;
         }
@@ -91,14 +92,17 @@ impl BinarySerializer<PlabbleConnectionContext, SerializationError> for PlabbleR
                 ctx.use_blake3(),
                 &mac_key,
                 &body_bytes,
-                Some(&ctx.create_authenticated_data(
-                    &raw_base_and_header,
-                    if ctx.include_bucket_key_in_auth_data {
-                        self.header.id.as_ref()
-                    } else {
-                        None
-                    },
-                )),
+                Some(
+                    &ctx.create_authenticated_data(
+                        &raw_base_and_header,
+                        if ctx.include_bucket_key_in_auth_data {
+                            self.header.id.as_ref()
+                        } else {
+                            None
+                        },
+                    )
+                    .ok_or(SerializationError::InvalidBucketKey)?,
+                ),
             );
             stream.write_bytes(&mac);
         }
@@ -127,24 +131,33 @@ impl BinaryDeserializer<PlabbleConnectionContext, DeserializationError> for Plab
         // Read body bytes from stream
         let mut body_bytes = stream.read_bytes(stream.bytes_left())?.to_owned();
 
+        // Keep track of whether the request was authenticated using the bucket key
+        let mut bucket_key_authenticated = false;
+
         // Decrypt the body if that is needed (and context is provided), first without bucket key then with bucket key as AAD
         if base.use_encryption
             && let Some(ctx) = &config.data
         {
-            body_bytes = ctx
-                .decrypt(
-                    &base,
-                    true,
-                    &body_bytes,
-                    &ctx.create_authenticated_data(&raw_base_and_header, None),
-                )
-                .or(ctx.decrypt(
-                    &base,
-                    true,
-                    &body_bytes,
-                    &ctx.create_authenticated_data(&raw_base_and_header, header.id.as_ref()),
-                ))
-                .ok_or(DeserializationError::DecryptionFailed)?;
+            body_bytes = match ctx.decrypt(
+                &base,
+                true,
+                &body_bytes,
+                &ctx.create_authenticated_data(&raw_base_and_header, None)
+                    .ok_or(DeserializationError::InvalidBucketKey)?,
+            ) {
+                Some(plain) => plain,
+                None => {
+                    bucket_key_authenticated = true;
+                    ctx.decrypt(
+                        &base,
+                        true,
+                        &body_bytes,
+                        &ctx.create_authenticated_data(&raw_base_and_header, header.id.as_ref())
+                            .ok_or(DeserializationError::InvalidBucketKey)?,
+                    )
+                    .ok_or(DeserializationError::DecryptionFailed)?
+                }
+            };
         }
 
         let body = PlabbleRequestBody::from_bytes(&body_bytes, Some(config))?;
@@ -167,7 +180,10 @@ impl BinaryDeserializer<PlabbleConnectionContext, DeserializationError> for Plab
                 ctx.use_blake3(),
                 &mac_key,
                 &body_bytes,
-                Some(&ctx.create_authenticated_data(&raw_base_and_header, None)),
+                Some(
+                    &ctx.create_authenticated_data(&raw_base_and_header, None)
+                        .ok_or(DeserializationError::InvalidBucketKey)?,
+                ),
             );
 
             if mac1 != expected {
@@ -175,14 +191,25 @@ impl BinaryDeserializer<PlabbleConnectionContext, DeserializationError> for Plab
                     ctx.use_blake3(),
                     &mac_key,
                     &body_bytes,
-                    Some(&ctx.create_authenticated_data(&raw_base_and_header, header.id.as_ref())),
+                    Some(
+                        &ctx.create_authenticated_data(&raw_base_and_header, header.id.as_ref())
+                            .ok_or(DeserializationError::InvalidBucketKey)?,
+                    ),
                 );
                 if mac2 != expected {
                     return Err(DeserializationError::IntegrityFailed);
                 }
+
+                bucket_key_authenticated = true;
             }
         }
 
+        // If there is no bucket key, the authenticated data is the same in both attempts,
+        // so the second attempt can only succeed if the bucket key was really used
+        if let Some(ctx) = config.data.as_mut() {
+            ctx.bucket_key_authenticated = bucket_key_authenticated;
+        }
+
         Ok(Self { base, header, body })
     }
 }
@@ -271,10 +298,13 @@ mod tests {
     use std::sync::Arc;
 
     use crate::{
-        errors::DeserializationError,
+        errors::{DeserializationError, SerializationError},
         packets::{
             base::settings::CryptoSettings,
-            context::{PlabbleConnectionContext, helpers::ExampleKeyProvider},
+            context::{
+                PlabbleConnectionContext,
+                helpers::{DerivedKeyProvider, ExampleKeyProvider},
+            },
             request::PlabbleRequestPacket,
         },
     };
@@ -334,6 +364,7 @@ mod tests {
         // Should automatically fall back on the bucket key if first decryption fails
         let decrypted = PlabbleRequestPacket::from_bytes(&encrypted, Some(&mut config)).unwrap();
         assert_eq!(packet, decrypted);
+        assert!(config.data.as_ref().unwrap().bucket_key_authenticated);
     }
 
     #[test]
@@ -379,6 +410,7 @@ mod tests {
         .unwrap();
 
         assert_eq!(packet, deserialized);
+        assert!(!config.data.as_ref().unwrap().bucket_key_authenticated);
 
         // Not possible to decode with wrong MAC
         let wrong = PlabbleRequestPacket::from_bytes(
@@ -413,6 +445,7 @@ mod tests {
         let deserialized =
             PlabbleRequestPacket::from_bytes(&serialized, Some(&mut config)).unwrap();
         assert_eq!(packet, deserialized);
+        assert!(config.data.as_ref().unwrap().bucket_key_authenticated);
 
         // Deserialization fails if bucket key getter is not provided and bucket key is included in AAD
         let context = config.data.as_mut().unwrap();
@@ -422,6 +455,36 @@ mod tests {
         let wrong = PlabbleRequestPacket::from_bytes(&serialized, Some(&mut config));
         assert_eq!(Err(DeserializationError::IntegrityFailed), wrong);
 
+        // The derived (64-byte) bucket key of a bucket created with POST takes precedence over the 32-byte bucket key
+        let context = config.data.as_mut().unwrap();
+        context.key_provider = Some(Arc::new(DerivedKeyProvider(vec![0; 64])));
+        context.include_bucket_key_in_auth_data = true;
+
+        let derived = packet.to_bytes(Some(&mut config)).unwrap();
+        let mac3 = "d0cd5fa8139c0b0503ea568b22e511b6";
+        assert_eq!(format!("{}{}", packet_b, mac3), hex::encode(&derived));
+        assert_eq!(
+            packet,
+            PlabbleRequestPacket::from_bytes(&derived, Some(&mut config)).unwrap()
+        );
+        assert!(config.data.as_ref().unwrap().bucket_key_authenticated);
+
+        // A derived bucket key of the wrong length is an error
+        let context = config.data.as_mut().unwrap();
+        context.key_provider = Some(Arc::new(DerivedKeyProvider(vec![0; 32])));
+        assert_eq!(
+            Err(SerializationError::InvalidBucketKey),
+            packet.to_bytes(Some(&mut config))
+        );
+        assert_eq!(
+            Err(DeserializationError::InvalidBucketKey),
+            PlabbleRequestPacket::from_bytes(&derived, Some(&mut config))
+        );
+
+        let context = config.data.as_mut().unwrap();
+        context.key_provider = None;
+        context.include_bucket_key_in_auth_data = false;
+
         // With full packet encryption, the header and MAC are encrypted (this example uses double cipher)
         let context = config.data.as_mut().unwrap();
         context.full_encryption = true;
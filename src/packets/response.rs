@@ -61,7 +61,8 @@ impl BinarySerializer<PlabbleConnectionContext, SerializationError> for PlabbleR
                     &self.base,
                     false,
                     &body_bytes,
-                    &ctx.create_authenticated_data(&raw_base_and_header, None),
+                    &ctx.create_authenticated_data(&raw_base_and_header, None)
+                        .ok_or(SerializationError::InvalidBucketKey)?,
                 )
                 .ok_or(SerializationError::EncryptionFailed)?;
         }
@@ -83,7 +84,10 @@ impl BinarySerializer<PlabbleConnectionContext, SerializationError> for PlabbleR
                 ctx.use_blake3(),
                 &mac_key,
                 &body_bytes,
-                Some(&ctx.create_authenticated_data(&raw_base_and_header, None)),
+                Some(
+                    &ctx.create_authenticated_data(&raw_base_and_header, None)
+                        .ok_or(SerializationError::InvalidBucketKey)?,
+                ),
             );
             stream.write_bytes(&mac);
         }
@@ -121,7 +125,8 @@ impl BinaryDeserializer<PlabbleConnectionContext, DeserializationError> for Plab
                     &base,
                     false,
                     &body_bytes,
-                    &ctx.create_authenticated_data(&raw_base_and_header, None),
+                    &ctx.create_authenticated_data(&raw_base_and_header, None)
+                        .ok_or(DeserializationError::InvalidBucketKey)?,
                 )
                 .ok_or(DeserializationError::DecryptionFailed)?;
         }
@@ -146,7 +151,10 @@ impl BinaryDeserializer<PlabbleConnectionContext, DeserializationError> for Plab
                 ctx.use_blake3(),
                 &mac_key,
                 &body_bytes,
-                Some(&ctx.create_authenticated_data(&raw_base_and_header, None)),
+                Some(
+                    &ctx.create_authenticated_data(&raw_base_and_header, None)
+                        .ok_or(DeserializationError::InvalidBucketKey)?,
+                ),
             );
             if mac != expected {
                 return Err(DeserializationError::IntegrityFailed);
@@ -189,11 +197,15 @@ impl<'de> Deserialize<'de> for PlabbleResponsePacket {
             }
             ResponsePacketType::Post => PlabbleResponseBody::Post,
             ResponsePacketType::Patch => PlabbleResponseBody::Patch,
-            ResponsePacketType::Put => PlabbleResponseBody::Put,
+            ResponsePacketType::Put { .. } => {
+                PlabbleResponseBody::Put(raw.body.deserialize_into().unwrap())
+            }
             ResponsePacketType::Delete { .. } => {
                 PlabbleResponseBody::Delete(raw.body.deserialize_into().unwrap())
             }
-            ResponsePacketType::Subscribe => PlabbleResponseBody::Subscribe,
+            ResponsePacketType::Subscribe { update, .. } => {
+                PlabbleResponseBody::Subscribe(update.then(|| raw.body.deserialize_into().unwrap()))
+            }
             ResponsePacketType::Whisper { .. } => {
                 let body: WhisperResponseBody = raw.body.deserialize_into().unwrap();
                 raw.header.packet_type = ResponsePacketType::Whisper {
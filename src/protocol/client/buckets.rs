@@ -0,0 +1,358 @@
+use crate::{
+    core::BucketId,
+    packets::{
+        base::PlabblePacketBase,
+        body::{
+            bucket::{BucketBody, BucketQuery, BucketRange, PutRequestBody},
+            patch::PatchRequestBody,
+            post::{BucketPermissions, BucketSettings, PostRequestBody},
+            request_body::PlabbleRequestBody,
+            response_body::PlabbleResponseBody,
+        },
+        header::{request_header::PlabbleRequestHeader, type_and_flags::RequestPacketType},
+        request::PlabbleRequestPacket,
+    },
+    protocol::{PlabbleConnection, error::PlabbleProtocolError},
+};
+
+/// Typed bucket operations for [`PlabbleConnection`]
+impl PlabbleConnection {
+    /// Read the slots within the range of a bucket, at most `limit` slots if set
+    pub async fn get(
+        &mut self,
+        id: BucketId,
+        range: BucketRange,
+        limit: Option<u32>,
+    ) -> Result<BucketBody, PlabbleProtocolError> {
+        let (range, range_mode_until) = range.to_range_mode();
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Get {
+                    binary_keys: matches!(range, BucketRange::Binary(..)),
+                    subscribe: false,
+                    range_mode_until,
+                    with_limit: limit.is_some(),
+                },
+                Some(id),
+            ),
+            body: PlabbleRequestBody::Get(BucketQuery { limit, range }),
+        };
+
+        match self.send_and_recv(req).await?.body {
+            PlabbleResponseBody::Get(body) => Ok(body),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Write the slots to a bucket, overwriting existing slots with the same keys
+    pub async fn put(
+        &mut self,
+        id: BucketId,
+        body: BucketBody,
+    ) -> Result<(), PlabbleProtocolError> {
+        let req = Self::put_request(id, body, false);
+        match self.send_and_recv(req).await?.body {
+            PlabbleResponseBody::Put(_) => Ok(()),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Append the values to a bucket with numeric keys (in order), returns the slots they were appended to
+    pub async fn append(
+        &mut self,
+        id: BucketId,
+        values: Vec<Vec<u8>>,
+    ) -> Result<Vec<u32>, PlabbleProtocolError> {
+        let count = values.len();
+        let body = BucketBody::Numeric((0..).zip(values).collect());
+        let req = Self::put_request(id, body, true);
+        match self.send_and_recv(req).await?.body {
+            PlabbleResponseBody::Put(body) => body
+                .slots
+                .filter(|slots| slots.len() == count)
+                .ok_or(PlabbleProtocolError::FailedToProcessResponse),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Delete the slots within the range of a bucket, at most `limit` slots if set.
+    /// Returns the deleted slots if `return_deleted` is set.
+    pub async fn delete(
+        &mut self,
+        id: BucketId,
+        range: BucketRange,
+        limit: Option<u32>,
+        return_deleted: bool,
+    ) -> Result<Option<BucketBody>, PlabbleProtocolError> {
+        let (range, range_mode_until) = range.to_range_mode();
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Delete {
+                    binary_keys: matches!(range, BucketRange::Binary(..)),
+                    range_mode_until,
+                    with_limit: limit.is_some(),
+                    return_deleted,
+                },
+                Some(id),
+            ),
+            body: PlabbleRequestBody::Delete(BucketQuery { limit, range }),
+        };
+
+        match self.send_and_recv(req).await?.body {
+            PlabbleResponseBody::Delete(body) => Ok(body),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Create a new bucket with the settings. Requires a session, because the bucket key is derived from the session key.
+    /// If `do_not_persist` is set, the server only keeps the bucket in memory.
+    pub async fn create_bucket(
+        &mut self,
+        id: BucketId,
+        settings: BucketSettings,
+        do_not_persist: bool,
+    ) -> Result<(), PlabbleProtocolError> {
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Post {
+                    binary_keys: false,
+                    subscribe: false,
+                    range_mode_until: false,
+                    do_not_persist,
+                },
+                None,
+            ),
+            body: PlabbleRequestBody::Post(PostRequestBody {
+                id,
+                settings,
+                range: None,
+            }),
+        };
+
+        match self.send_and_recv(req).await?.body {
+            PlabbleResponseBody::Post => Ok(()),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Replace the permissions of a bucket
+    pub async fn patch_permissions(
+        &mut self,
+        id: BucketId,
+        permissions: BucketPermissions,
+    ) -> Result<(), PlabbleProtocolError> {
+        self.send_patch(
+            id,
+            PatchRequestBody {
+                permissions: Some(permissions),
+                acl_add: None,
+                acl_del: None,
+            },
+        )
+        .await
+    }
+
+    /// Add the user (certificate) IDs to the ACL of a bucket
+    pub async fn acl_add(
+        &mut self,
+        id: BucketId,
+        users: Vec<[u8; 16]>,
+    ) -> Result<(), PlabbleProtocolError> {
+        self.send_patch(
+            id,
+            PatchRequestBody {
+                permissions: None,
+                acl_add: Some(users),
+                acl_del: None,
+            },
+        )
+        .await
+    }
+
+    /// Remove the user (certificate) IDs from the ACL of a bucket
+    pub async fn acl_remove(
+        &mut self,
+        id: BucketId,
+        users: Vec<[u8; 16]>,
+    ) -> Result<(), PlabbleProtocolError> {
+        self.send_patch(
+            id,
+            PatchRequestBody {
+                permissions: None,
+                acl_add: None,
+                acl_del: Some(users),
+            },
+        )
+        .await
+    }
+
+    /// Create a PUT request, in append mode the keys of the body only determine the order of the values
+    pub(crate) fn put_request(
+        id: BucketId,
+        body: BucketBody,
+        append: bool,
+    ) -> PlabbleRequestPacket {
+        PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Put {
+                    binary_keys: matches!(body, BucketBody::Binary(..)),
+                    subscribe: false,
+                    assert_keys: false,
+                    append,
+                },
+                Some(id),
+            ),
+            body: PlabbleRequestBody::Put(PutRequestBody { body }),
+        }
+    }
+
+    /// Send a PATCH request, the flags are set according to the parts of the body that are present
+    async fn send_patch(
+        &mut self,
+        id: BucketId,
+        body: PatchRequestBody,
+    ) -> Result<(), PlabbleProtocolError> {
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Patch {
+                    update_permissions: body.permissions.is_some(),
+                    add_to_acl: body.acl_add.is_some(),
+                    remove_from_acl: body.acl_del.is_some(),
+                },
+                Some(id),
+            ),
+            body: PlabbleRequestBody::Patch(body),
+        };
+
+        match self.send_and_recv(req).await?.body {
+            PlabbleResponseBody::Patch => Ok(()),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+
+    use crate::{
+        core::BucketId,
+        packets::body::{
+            bucket::{BucketBody, BucketRange},
+            error::PlabbleError,
+            post::{BucketPermissions, BucketSettings},
+        },
+        protocol::{client::helpers::TestServer, error::PlabbleProtocolError},
+        providers::{BucketStore, KeyProvider},
+    };
+
+    /// Key provider that knows the (derived) key of a single bucket
+    struct BucketKeyProvider([u8; 64]);
+
+    impl KeyProvider for BucketKeyProvider {
+        fn get_bucket_key(&self, _bucket_id: &[u8; 16]) -> Option<[u8; 32]> {
+            None
+        }
+
+        fn get_derived_bucket_key(&self, _bucket_id: &[u8; 16]) -> Option<Vec<u8>> {
+            Some(self.0.to_vec())
+        }
+
+        fn get_psk(&self, _psk_id: &[u8; 12]) -> Option<[u8; 64]> {
+            None
+        }
+
+        fn store_psk(&self, _psk_id: [u8; 12], _psk: [u8; 64], _expiration: Option<u32>) {}
+    }
+
+    #[test]
+    fn can_manage_bucket_with_typed_requests() {
+        let server = TestServer::new();
+        let (mut client, serve) = server.connect();
+        let id = BucketId::parse("@typed").unwrap();
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                client
+                    .create_bucket(id.clone(), BucketSettings::default(), true)
+                    .await?;
+
+                // Authenticate the requests with the bucket key, for private access
+                let context = client.config.data.as_mut().unwrap();
+                let key = context
+                    .create_bucket_key(context.use_blake3(), &id.data)
+                    .unwrap();
+                context.key_provider = Some(Arc::new(BucketKeyProvider(key)));
+                context.include_bucket_key_in_auth_data = true;
+
+                client
+                    .put(id.clone(), BucketBody::Numeric([(1, b"a".to_vec())].into()))
+                    .await?;
+                let slots = client
+                    .append(id.clone(), vec![b"b".to_vec(), b"c".to_vec()])
+                    .await?;
+                let read = client
+                    .get(id.clone(), BucketRange::Numeric(None, None), None)
+                    .await?;
+                let deleted = client
+                    .delete(
+                        id.clone(),
+                        BucketRange::Numeric(Some(1), Some(1)),
+                        None,
+                        true,
+                    )
+                    .await?;
+
+                client.acl_add(id.clone(), vec![[1; 16], [2; 16]]).await?;
+                client.acl_remove(id.clone(), vec![[1; 16]]).await?;
+                client
+                    .patch_permissions(
+                        id.clone(),
+                        BucketPermissions {
+                            lock_acl: true,
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+                let locked = client.acl_add(id.clone(), vec![[3; 16]]).await;
+
+                drop(client);
+                Ok::<_, PlabbleProtocolError>((slots, read, deleted, locked))
+            },
+            serve,
+        ));
+
+        let (slots, read, deleted, locked) = result.unwrap();
+        assert_eq!(2, slots.len());
+        assert_eq!(
+            BucketBody::Numeric(
+                [
+                    (1, b"a".to_vec()),
+                    (slots[0], b"b".to_vec()),
+                    (slots[1], b"c".to_vec())
+                ]
+                .into()
+            ),
+            read
+        );
+        assert_eq!(
+            Some(BucketBody::Numeric([(1, b"a".to_vec())].into())),
+            deleted
+        );
+        assert!(matches!(
+            locked,
+            Err(PlabbleProtocolError::ProtocolError(PlabbleError::AclLocked))
+        ));
+
+        let settings = server.store.get_settings(&id).unwrap();
+        assert_eq!(vec![[2; 16]], settings.access_control_list);
+        assert!(settings.permissions.lock_acl);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    pin::pin,
+    sync::{Arc, Mutex},
+};
+
+use async_channel::{Receiver, Sender};
+use futures::future::{Either, select};
+
+use crate::{
+    crypto::certificate::Certificate,
+    packets::{
+        body::error::PlabbleError, context::PlabbleConnectionContext,
+        response::PlabbleResponsePacket,
+    },
+    protocol::{
+        PlabbleConnection, error::PlabbleProtocolError, server::subscriptions::SubscriptionRegistry,
+    },
+    providers::{CertificateProvider, memory::MemoryBucketStore},
+};
+
+/// Server for end-to-end tests of the client, with a bucket store, subscription registry and certificate provider shared by all connections
+pub struct TestServer {
+    pub store: Arc<MemoryBucketStore>,
+    pub registry: Arc<SubscriptionRegistry>,
+    pub certificates: Arc<TestCertificateProvider>,
+}
+
+impl TestServer {
+    /// Create a new server with an empty bucket store
+    pub fn new() -> Self {
+        Self {
+            store: Arc::new(MemoryBucketStore::new()),
+            registry: Arc::new(SubscriptionRegistry::new()),
+            certificates: Arc::new(TestCertificateProvider::new()),
+        }
+    }
+
+    /// Create a client connection that is linked to a new server connection, both within a session (with session key `[0; 64]`).
+    /// Returns the client and the future that serves its requests until the client is dropped.
+    pub fn connect(&self) -> (PlabbleConnection, impl Future<Output = ()>) {
+        self.connect_with(|_| {})
+    }
+
+    /// Same as [`Self::connect`], but `configure` can change the context of the server connection first
+    pub fn connect_with(
+        &self,
+        configure: impl FnOnce(&mut PlabbleConnectionContext),
+    ) -> (PlabbleConnection, impl Future<Output = ()>) {
+        let (client, _, serve) = self.connect_raw(configure);
+        (client, serve)
+    }
+
+    /// Same as [`Self::connect_with`], but also returns a sender for pushing raw packets to the client as the server
+    pub fn connect_raw(
+        &self,
+        configure: impl FnOnce(&mut PlabbleConnectionContext),
+    ) -> (PlabbleConnection, Sender<Vec<u8>>, impl Future<Output = ()>) {
+        let (client_tx, server_rx) = async_channel::unbounded();
+        let (server_tx, client_rx) = async_channel::unbounded();
+        let mut client = PlabbleConnection::new(client_tx, client_rx);
+        let mut server = PlabbleConnection::new(server_tx.clone(), server_rx);
+
+        client.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+
+        let (subscriber, updates) = self.registry.create_subscriber();
+        let context = server.config.data.as_mut().unwrap();
+        context.session_key = Some([0; 64]);
+        context.bucket_store = Some(self.store.clone());
+        context.subscriber = Some(subscriber);
+        context.certificate_provider = Some(self.certificates.clone());
+        configure(context);
+
+        (client, server_tx, serve(server, updates))
+    }
+}
+
+/// Certificate provider of the test server, with a server certificate that can issue certificates
+pub struct TestCertificateProvider {
+    pub server: Certificate,
+    pub registered: Mutex<Vec<Certificate>>,
+}
+
+impl TestCertificateProvider {
+    fn new() -> Self {
+        let server = toml::from_str(
+            r#"
+            id = "AQEBAQEBAQEBAQEBAQEBAQ"
+            uri = "plabble:localhost/server.crt"
+            valid_from = "2025-05-15T12:30:00+00:00"
+            valid_until = "2161-02-07T06:28:15+00:00"
+            issuer_uri = "plabble:localhost/root.crt"
+            data = "CN=localhost"
+            with_secret_keys = true
+
+            [[keys]]
+            Ed25519 = "PXCMlNZIKU-TI8BWCB8QsNSJp0bLdB1jDzhlSiLXxas"
+
+            [[signatures]]
+            Ed25519 = "GLr1ep-8O70YihvouWdnsFBAPP6poaAVC3TFyz1Lu60MjK_n5D29lYDWmUWit4JaSiN8SpNSpBNmdlFMu8gODQ"
+
+            [[secret_keys]]
+            Ed25519 = "r91Qx-o5PetYDFuO1T6NPW2Q4w1yL13fKmIj2vRQU9g"
+        "#,
+        )
+        .unwrap();
+
+        Self {
+            server,
+            registered: Mutex::default(),
+        }
+    }
+}
+
+impl CertificateProvider for TestCertificateProvider {
+    fn get_server_certificate(&self) -> Option<Certificate> {
+        Some(self.server.clone())
+    }
+
+    fn get_certificate(&self, id: &[u8; 16]) -> Option<Certificate> {
+        let registered = self.registered.lock().unwrap();
+        registered.iter().find(|c| c.id() == id).cloned()
+    }
+
+    fn get_certificate_by_uri(&self, uri: &str) -> Option<Certificate> {
+        let registered = self.registered.lock().unwrap();
+        registered.iter().find(|c| c.uri() == uri).cloned()
+    }
+
+    fn store_certificate(&self, certificate: Certificate) -> Result<(), PlabbleProtocolError> {
+        let mut registered = self.registered.lock().unwrap();
+        if registered
+            .iter()
+            .any(|c| c.body().map(|b| b.data()) == certificate.body().map(|b| b.data()))
+        {
+            return Err(PlabbleError::ClaimsAlreadyRegistered.into());
+        }
+
+        registered.push(certificate);
+        Ok(())
+    }
+}
+
+/// Handle requests on the server connection until the client connection is closed,
+/// and push the subscription updates for the connection in between
+async fn serve(mut server: PlabbleConnection, updates: Receiver<PlabbleResponsePacket>) {
+    loop {
+        let next = match select(pin!(server.recv_request()), pin!(updates.recv())).await {
+            Either::Left((req, _)) => Either::Left(req),
+            Either::Right((update, _)) => Either::Right(update),
+        };
+
+        let res = match next {
+            Either::Left(Ok(req)) => server.handle_request(req).await.unwrap(),
+            Either::Right(Ok(update)) => update,
+            _ => break,
+        };
+        server.send_response(res).await.unwrap();
+    }
+}
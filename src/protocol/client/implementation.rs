@@ -1,10 +1,18 @@
+use binary_codec::{BinaryDeserializer, BinarySerializer, SerializerConfig};
+
 use crate::{
-    core::PlabbleDateTime,
-    crypto::KeyExchange,
+    core::{BucketId, Claims, PlabbleDateTime},
+    crypto::{KeyExchange, algorithm::SigningKey, certificate::Certificate},
+    network::node_info::NodeInfo,
     packets::{
         base::{PlabblePacketBase, settings::CryptoSettings},
         body::{
-            request_body::PlabbleRequestBody, response_body::PlabbleResponseBody,
+            bucket::{BucketQuery, BucketRange},
+            custom::CustomBody,
+            identify::IdentifyRequestBody,
+            register::RegisterRequestBody,
+            request_body::PlabbleRequestBody,
+            response_body::PlabbleResponseBody,
             session::SessionRequestBody,
         },
         header::{
@@ -15,7 +23,15 @@ use crate::{
     },
     protocol::{
         PlabbleConnection,
-        client::options::{SessionOptions, get_key_exchange_algorithms, set_crypto_settings},
+        client::{
+            options::{
+                SessionOptions, get_key_exchange_algorithms, get_signature_algorithms,
+                set_crypto_settings,
+            },
+            proxy::{ProxyRoute, ProxyTunnel},
+            subscriptions::SubscriptionStream,
+        },
+        custom::{CustomFlags, SubProtocol},
         error::PlabbleProtocolError,
     },
 };
@@ -123,4 +139,761 @@ impl PlabbleConnection {
 
         Err(PlabbleProtocolError::UnexpectedResponse)
     }
+
+    /// Register a new identity on the server with the claims (like `USERNAME=henk;AGE=24`).
+    /// The server orders the claims canonically in the issued certificate.
+    /// A key pair is generated for every signature algorithm of the session, the server issues a certificate for the public keys.
+    /// Returns the certificate including the secret keys, which is also stored with the certificate provider (if any).
+    ///
+    /// - `server_certificate` is the (full) server certificate, as obtained with a CERTIFICATE request.
+    ///   The issued certificate must be valid now and issued by it, otherwise it is rejected.
+    pub async fn register(
+        &mut self,
+        claims: Claims,
+        server_certificate: &Certificate,
+    ) -> Result<Certificate, PlabbleProtocolError> {
+        let context = self.config.data.as_ref().unwrap();
+        let settings = context.crypto_settings.unwrap_or_default();
+        let secret_keys: Vec<SigningKey> = get_signature_algorithms(&settings)
+            .into_iter()
+            .map(SigningKey::generate)
+            .collect();
+
+        let keys = secret_keys
+            .iter()
+            .map(|key| key.verification_key())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(PlabbleProtocolError::FailedToProcessRequest)?;
+
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase {
+                use_encryption: true,
+                ..Default::default()
+            },
+            header: PlabbleRequestHeader::new(RequestPacketType::Register, None),
+            body: PlabbleRequestBody::Register(RegisterRequestBody {
+                keys: keys.clone(),
+                claims,
+            }),
+        };
+
+        let res = self.send_and_recv(req).await?;
+        match res.body {
+            PlabbleResponseBody::Register(certificate) => {
+                if certificate.body().map(|b| b.keys()) != Some(&keys[..])
+                    || !certificate.is_valid_at(&PlabbleDateTime::from_now(0))
+                    || !certificate.is_issued_by(server_certificate)
+                {
+                    return Err(PlabbleProtocolError::FailedToProcessResponse);
+                }
+
+                let certificate = certificate.with_secret_keys(secret_keys);
+                let context = self.config.data.as_ref().unwrap();
+                if let Some(provider) = &context.certificate_provider {
+                    provider.store_certificate(certificate.clone())?;
+                }
+
+                Ok(certificate)
+            }
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Prove the identity of a certificate (including its secret keys) to the server, binding it to the current session.
+    /// The server then gives protected access to buckets with the certificate on their ACL.
+    ///
+    /// - `server_id` is the ID of the server certificate, as obtained with a CERTIFICATE request
+    pub async fn identify(
+        &mut self,
+        certificate: &Certificate,
+        server_id: &[u8; 16],
+    ) -> Result<(), PlabbleProtocolError> {
+        let context = self.config.data.as_ref().unwrap();
+        let session_key = context
+            .session_key
+            .ok_or(PlabbleProtocolError::FailedToProcessRequest)?;
+
+        let timestamp = PlabbleDateTime::from_now(0);
+        let data = IdentifyRequestBody::signature_data(&timestamp, server_id, &session_key);
+        let signatures = get_signature_algorithms(&context.crypto_settings.unwrap_or_default())
+            .into_iter()
+            .map(|alg| certificate.get_signing_key(alg)?.sign(&data))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(PlabbleProtocolError::FailedToProcessRequest)?;
+
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(RequestPacketType::Identify, None),
+            body: PlabbleRequestBody::Identify(IdentifyRequestBody {
+                timestamp,
+                signatures,
+                certificates: vec![certificate.without_secret_keys()],
+            }),
+        };
+
+        let res = self.send_and_recv(req).await?;
+        match res.body {
+            PlabbleResponseBody::Identity => Ok(()),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Subscribe to changes of the slots within the range of a bucket.
+    /// Returns a stream of the change events, which are received while calling [`Self::recv_response`].
+    pub async fn subscribe(
+        &mut self,
+        id: BucketId,
+        range: BucketRange,
+    ) -> Result<SubscriptionStream, PlabbleProtocolError> {
+        // Register the stream before sending the request, so no update can be missed
+        let stream = self.subscriptions.register(&id.data, range.clone());
+        if let Err(e) = self.send_subscribe(id.clone(), &range, false).await {
+            self.subscriptions.remove(&id.data, &range);
+            return Err(e);
+        }
+
+        Ok(stream)
+    }
+
+    /// Unsubscribe from exactly the given range of a bucket, ending the streams of that range
+    pub async fn unsubscribe(
+        &mut self,
+        id: BucketId,
+        range: BucketRange,
+    ) -> Result<(), PlabbleProtocolError> {
+        self.subscriptions.remove(&id.data, &range);
+        self.send_subscribe(id, &range, true).await
+    }
+
+    /// Send a SUBSCRIBE request and wait for the response
+    async fn send_subscribe(
+        &mut self,
+        id: BucketId,
+        range: &BucketRange,
+        unsubscribe: bool,
+    ) -> Result<(), PlabbleProtocolError> {
+        let (range, range_mode_until) = range.to_range_mode();
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Subscribe {
+                    binary_keys: matches!(range, BucketRange::Binary(..)),
+                    range_mode_until,
+                    unsubscribe,
+                },
+                Some(id),
+            ),
+            body: PlabbleRequestBody::Subscribe(BucketQuery { limit: None, range }),
+        };
+
+        let res = self.send_and_recv(req).await?;
+        match res.body {
+            PlabbleResponseBody::Subscribe(_) => Ok(()),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Send a CUSTOM request with the raw data to a sub-protocol of the server and wait for the response body.
+    /// Fails with `PlabbleError::UnsupportedSubProtocol` if the server does not support the sub-protocol.
+    pub async fn send_custom_raw(
+        &mut self,
+        protocol: u16,
+        flags: CustomFlags,
+        data: Vec<u8>,
+    ) -> Result<CustomBody, PlabbleProtocolError> {
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(flags.request_type(), None),
+            body: PlabbleRequestBody::Custom(CustomBody { protocol, data }),
+        };
+
+        let res = self.send_and_recv(req).await?;
+        match res.body {
+            PlabbleResponseBody::Custom(body) if body.protocol == protocol => Ok(body),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+
+    /// Send a typed request of the sub-protocol `P` to the server and wait for its typed response.
+    /// Fails with `FailedToProcessResponse` if the response can not be parsed as `P::Response`.
+    pub async fn send_custom<P: SubProtocol>(
+        &mut self,
+        flags: CustomFlags,
+        request: &P::Request,
+    ) -> Result<P::Response, PlabbleProtocolError> {
+        let body = P::encode_request(request)?;
+        let res = self
+            .send_custom_raw(body.protocol, flags, body.data)
+            .await?;
+        P::decode_response(&res).map_err(|_| PlabbleProtocolError::FailedToProcessResponse)
+    }
+
+    /// Open a PROXY tunnel to the target through the hops (in route order), starting with the server of this connection.
+    /// The tunnel is extended hop by hop with new keys for every hop (see [`ProxyRoute`]).
+    /// The hops are verified with their verification keys, so only the nodes of the route can read or modify the tunnel.
+    /// Use [`ProxyTunnel::connect`] to run a nested connection through the tunnel.
+    pub async fn open_tunnel(
+        &mut self,
+        target: &str,
+        hops: Vec<NodeInfo>,
+    ) -> Result<ProxyTunnel, PlabbleProtocolError> {
+        let settings = self
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .crypto_settings
+            .unwrap_or_default();
+        let mut route = ProxyRoute::new(target, hops, settings)?;
+
+        let res = self.send_and_recv(route.request()?).await?;
+        let mut tunnel = match res.body {
+            PlabbleResponseBody::Proxy(body) => route.process_response(body)?,
+            _ => return Err(PlabbleProtocolError::UnexpectedResponse),
+        };
+
+        while !route.is_complete() {
+            let req = route.request()?.to_bytes(None)?;
+            let reply = self.send_tunnel_frame(&mut tunnel, &req, true).await?;
+            route.process_extension(&mut tunnel, &reply)?;
+        }
+
+        Ok(tunnel)
+    }
+
+    /// Send the data as one frame through the tunnel and wait for the data the target replied with.
+    /// Without `keep_connection`, the hops close the tunnel after this frame.
+    pub async fn send_tunnel_frame(
+        &mut self,
+        tunnel: &mut ProxyTunnel,
+        data: &[u8],
+        keep_connection: bool,
+    ) -> Result<Vec<u8>, PlabbleProtocolError> {
+        let req = tunnel.frame_request(data, keep_connection)?;
+        let res = self.send_and_recv(req).await?;
+        tunnel.process_frame_response(res)
+    }
+
+    /// Relay the packets of the nested connection of the tunnel (see [`ProxyTunnel::connect`]) until it is dropped.
+    /// Every packet is sent as one frame, the reply of the target is received by the nested connection.
+    /// The target does not reply to fire-and-forget packets, the last hop completes their frames with an empty reply instead.
+    pub async fn relay_tunnel(
+        &mut self,
+        tunnel: &mut ProxyTunnel,
+    ) -> Result<(), PlabbleProtocolError> {
+        let (outgoing, incoming) = tunnel
+            .channel()
+            .ok_or(PlabbleProtocolError::FailedToProcessRequest)?;
+
+        while let Ok(packet) = outgoing.recv().await {
+            let fire_and_forget =
+                PlabblePacketBase::from_bytes(&packet, None::<&mut SerializerConfig>)
+                    .is_ok_and(|base| base.fire_and_forget);
+
+            let reply = self.send_tunnel_frame(tunnel, &packet, true).await?;
+            if !fire_and_forget {
+                incoming
+                    .send(reply)
+                    .await
+                    .map_err(|_| PlabbleProtocolError::SenderError)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{Ipv4Addr, SocketAddrV4},
+        pin::pin,
+        sync::{Arc, Mutex},
+    };
+
+    use binary_codec::{BinaryDeserializer, BinarySerializer, FromBytes, ToBytes};
+    use futures::{
+        executor::block_on,
+        future::{Either, join, select},
+    };
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        core::{BucketId, Claims, PlabbleDateTime, node_address::NodeAddress},
+        crypto::{SignatureAlgorithm, algorithm::SigningKey, certificate::Certificate},
+        network::node_info::NodeInfo,
+        packets::{
+            base::settings::CryptoSettings,
+            body::{
+                bucket::{BucketBody, BucketRange},
+                custom::CustomBody,
+                error::PlabbleError,
+                post::{BucketPermissions, BucketSettings},
+            },
+            request::PlabbleRequestPacket,
+        },
+        protocol::{
+            PlabbleConnection,
+            client::{
+                helpers::{TestCertificateProvider, TestServer},
+                subscriptions::SubscriptionEvent,
+            },
+            custom::{CustomFlags, SubProtocol, SubProtocolRegistry},
+            error::PlabbleProtocolError,
+            server::proxy::TunnelRegistry,
+        },
+        providers::{BucketStore, CertificateProvider, ProviderFuture, ProxyLink, ProxyRelay},
+    };
+
+    #[test]
+    fn can_subscribe_to_changes_of_other_clients() {
+        let server = TestServer::new();
+        let id = BucketId::parse("@subscribe").unwrap();
+        let settings = BucketSettings {
+            permissions: BucketPermissions {
+                public_write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        server
+            .store
+            .create_bucket(&id, settings, [0; 64], true)
+            .unwrap();
+
+        let (mut reader, serve_reader) = server.connect();
+        let (mut writer, serve_writer) = server.connect();
+        let range = BucketRange::Numeric(Some(5), None);
+        let slots = BucketBody::Numeric([(1, vec![1]), (7, vec![7])].into());
+
+        let (result, _, _) = block_on(futures::future::join3(
+            async {
+                let stream = reader.subscribe(id.clone(), range.clone()).await?;
+                writer.put(id.clone(), slots).await?;
+
+                // The update is received while waiting for a response
+                reader.recv_response().await?;
+                let event = stream.next_event().await;
+
+                reader.unsubscribe(id.clone(), range).await?;
+                let ended = stream.next_event().await;
+
+                drop((reader, writer));
+                Ok::<_, PlabbleProtocolError>((event, ended))
+            },
+            serve_reader,
+            serve_writer,
+        ));
+
+        let (event, ended) = result.unwrap();
+        assert_eq!(
+            Some(SubscriptionEvent::Written(BucketBody::Numeric(
+                [(7, vec![7])].into()
+            ))),
+            event
+        );
+        assert_eq!(None, ended);
+    }
+
+    #[test]
+    fn can_register_identity_with_claims() {
+        let server = TestServer::new();
+        let (mut client, serve) = server.connect();
+        let claims: Claims = "USERNAME=henk;AGE=24".parse().unwrap();
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let certificate = client
+                    .register(claims.clone(), &server.certificates.server)
+                    .await?;
+                let again = client.register(claims, &server.certificates.server).await;
+                drop(client);
+                Ok::<_, PlabbleProtocolError>((certificate, again))
+            },
+            serve,
+        ));
+
+        let (certificate, again) = result.unwrap();
+        let body = certificate.body().unwrap();
+        assert_eq!("AGE=24;USERNAME=henk", body.data().to_string());
+        assert!(certificate.is_issued_by(&server.certificates.server));
+        assert_eq!(
+            Some(certificate.without_secret_keys()),
+            server.certificates.get_certificate(certificate.id())
+        );
+
+        // The certificate includes the secret keys, so the client can identify with it
+        assert!(
+            certificate
+                .get_signing_key(SignatureAlgorithm::Ed25519)
+                .is_some()
+        );
+
+        assert!(matches!(
+            again,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::ClaimsAlreadyRegistered
+            ))
+        ));
+    }
+
+    #[test]
+    fn register_rejects_certificate_not_issued_by_server() {
+        let server = TestServer::new();
+        let (mut client, serve) = server.connect();
+
+        // The client expects another server certificate than the one that issues the certificate
+        let other = Certificate::issue(
+            &server.certificates.server,
+            "plabble:localhost/other.crt",
+            PlabbleDateTime::from_now(0),
+            PlabbleDateTime::from_now(60 * 60),
+            "CN=other".parse().unwrap(),
+            vec![
+                SigningKey::generate(SignatureAlgorithm::Ed25519)
+                    .verification_key()
+                    .unwrap(),
+            ],
+        )
+        .unwrap();
+
+        let client_certificates = Arc::new(TestCertificateProvider {
+            server: other.clone(),
+            registered: Mutex::default(),
+        });
+        client.config.data.as_mut().unwrap().certificate_provider =
+            Some(client_certificates.clone());
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let result = client
+                    .register("USERNAME=henk".parse().unwrap(), &other)
+                    .await;
+                drop(client);
+                result
+            },
+            serve,
+        ));
+
+        assert!(matches!(
+            result,
+            Err(PlabbleProtocolError::FailedToProcessResponse)
+        ));
+        assert!(client_certificates.registered.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn can_identify_for_protected_access() {
+        let server = TestServer::new();
+        let (mut client, serve) = server.connect();
+        let id = BucketId::parse("@protected").unwrap();
+        let server_id = *server.certificates.server.id();
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let certificate = client
+                    .register(
+                        "USERNAME=henk".parse().unwrap(),
+                        &server.certificates.server,
+                    )
+                    .await?;
+
+                // Only the identified owner of the certificate on the ACL can write
+                let settings = BucketSettings {
+                    permissions: BucketPermissions {
+                        protected_write: true,
+                        ..Default::default()
+                    },
+                    access_control_list: vec![*certificate.id()],
+                };
+                server
+                    .store
+                    .create_bucket(&id, settings, [0; 64], true)
+                    .unwrap();
+
+                let slots = BucketBody::Numeric([(1, vec![1])].into());
+                let anonymous = client.put(id.clone(), slots.clone()).await;
+                client.identify(&certificate, &server_id).await?;
+                client.put(id.clone(), slots).await?;
+
+                drop(client);
+                Ok::<_, PlabbleProtocolError>(anonymous)
+            },
+            serve,
+        ));
+
+        assert!(matches!(
+            result.unwrap(),
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::PermissionDenied
+            ))
+        ));
+        assert_eq!(
+            BucketBody::Numeric([(1, vec![1])].into()),
+            server
+                .store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn can_send_raw_custom_requests_to_sub_protocols() {
+        let mut registry = SubProtocolRegistry::new();
+        registry.register(1, |flags: CustomFlags, mut data: Vec<u8>| {
+            if flags.flag1 {
+                data.reverse();
+            }
+            Ok(CustomBody { protocol: 1, data })
+        });
+
+        let server = TestServer::new();
+        let registry = Arc::new(registry);
+        let (mut client, serve) =
+            server.connect_with(|context| context.sub_protocols = Some(registry));
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let flags = CustomFlags {
+                    flag1: true,
+                    ..Default::default()
+                };
+                let reversed = client.send_custom_raw(1, flags, vec![1, 2, 3]).await?;
+                let unsupported = client.send_custom_raw(2, flags, vec![1]).await;
+
+                drop(client);
+                Ok::<_, PlabbleProtocolError>((reversed, unsupported))
+            },
+            serve,
+        ));
+
+        let (reversed, unsupported) = result.unwrap();
+        assert_eq!(
+            CustomBody {
+                protocol: 1,
+                data: vec![3, 2, 1]
+            },
+            reversed
+        );
+        assert!(matches!(
+            unsupported,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::UnsupportedSubProtocol
+            ))
+        ));
+    }
+
+    /// Sub-protocol that doubles a number
+    struct Double;
+
+    #[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone)]
+    struct Number {
+        value: u32,
+    }
+
+    impl SubProtocol for Double {
+        const PROTOCOL: u16 = 7;
+        type Request = Number;
+        type Response = Number;
+    }
+
+    #[test]
+    fn can_send_typed_custom_requests() {
+        let mut registry = SubProtocolRegistry::new();
+        registry.register_protocol::<Double>(|_, req| match req.value {
+            0 => Err(PlabbleError::InvalidRequest),
+            value => Ok(Number { value: value * 2 }),
+        });
+
+        let server = TestServer::new();
+        let registry = Arc::new(registry);
+        let (mut client, serve) =
+            server.connect_with(|context| context.sub_protocols = Some(registry));
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let flags = CustomFlags::default();
+                let doubled = client
+                    .send_custom::<Double>(flags, &Number { value: 21 })
+                    .await?;
+                let invalid = client
+                    .send_custom::<Double>(flags, &Number { value: 0 })
+                    .await;
+
+                drop(client);
+                Ok::<_, PlabbleProtocolError>((doubled, invalid))
+            },
+            serve,
+        ));
+
+        let (doubled, invalid) = result.unwrap();
+        assert_eq!(Number { value: 42 }, doubled);
+        assert!(matches!(
+            invalid,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::InvalidRequest
+            ))
+        ));
+    }
+
+    /// Proxy relay with links (by address) that are created up front
+    #[derive(Default)]
+    struct TestProxyRelay(Mutex<HashMap<String, Box<dyn ProxyLink>>>);
+
+    impl ProxyRelay for TestProxyRelay {
+        fn select_hop(&self, _exclude: &[&str]) -> Option<String> {
+            None
+        }
+
+        fn connect<'a>(&'a self, address: &'a str) -> ProviderFuture<'a, Box<dyn ProxyLink>> {
+            Box::pin(async move {
+                Ok(self
+                    .0
+                    .lock()
+                    .unwrap()
+                    .remove(address)
+                    .ok_or(PlabbleError::HopUnreachable)?)
+            })
+        }
+    }
+
+    /// Link to a hop, which handles the (session-less) requests of the previous hop directly
+    struct HopLink(PlabbleConnection);
+
+    impl ProxyLink for HopLink {
+        fn send_and_recv(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, Vec<u8>> {
+            Box::pin(async move {
+                // Hops are not in a session with each other, so the packets are (de)serialized without the connection context
+                let req = PlabbleRequestPacket::from_bytes(&packet, None)?;
+                self.0.config.data.as_mut().unwrap().increment(true);
+
+                let res = self.0.handle_request(req).await?;
+                self.0.config.data.as_mut().unwrap().increment(false);
+                Ok(res.to_bytes(None)?)
+            })
+        }
+
+        fn send(&mut self, _packet: Vec<u8>) -> ProviderFuture<'_, ()> {
+            Box::pin(async { Err(PlabbleProtocolError::UnexpectedRequest) })
+        }
+    }
+
+    /// Link to the target over the channels of a connection to the test server
+    struct ChannelLink(PlabbleConnection);
+
+    impl ProxyLink for ChannelLink {
+        fn send_and_recv(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, Vec<u8>> {
+            Box::pin(async move {
+                self.send(packet).await?;
+                Ok(self
+                    .0
+                    .rx
+                    .recv()
+                    .await
+                    .map_err(|_| PlabbleError::HopUnreachable)?)
+            })
+        }
+
+        fn send(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, ()> {
+            Box::pin(async move {
+                self.0
+                    .tx
+                    .send(packet)
+                    .await
+                    .map_err(|_| PlabbleError::HopUnreachable)?;
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn can_access_bucket_through_proxy_tunnel() {
+        let server = TestServer::new();
+        let relay = Arc::new(TestProxyRelay::default());
+        let (tunnels1, tunnels2) = (
+            Arc::new(TunnelRegistry::new()),
+            Arc::new(TunnelRegistry::new()),
+        );
+
+        let (mut client, serve_hop1) = server.connect_with(|context| {
+            context.tunnels = Some(tunnels1.clone());
+            context.proxy_relay = Some(relay.clone());
+        });
+
+        // The second hop is not in a session with the first, the target gets the packets of the client as they are
+        let (tx, rx) = async_channel::unbounded();
+        let mut hop2 = PlabbleConnection::new(tx, rx);
+        let context = hop2.config.data.as_mut().unwrap();
+        context.certificate_provider = Some(server.certificates.clone());
+        context.tunnels = Some(tunnels2.clone());
+        context.proxy_relay = Some(relay.clone());
+
+        let (target, serve_target) = server.connect();
+        relay.0.lock().unwrap().extend([
+            (
+                "127.0.0.1:1002".to_string(),
+                Box::new(HopLink(hop2)) as Box<dyn ProxyLink>,
+            ),
+            ("target".to_string(), Box::new(ChannelLink(target))),
+        ]);
+
+        let key = server.certificates.server.body().unwrap().keys()[0].clone();
+        let hops = [1001, 1002]
+            .map(|port| NodeInfo {
+                id: *server.certificates.server.id(),
+                address: NodeAddress::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)),
+                last_seen: PlabbleDateTime::from_now(0),
+                crypto_settings: CryptoSettings::default(),
+                verification_keys: vec![key.clone()],
+            })
+            .to_vec();
+
+        let id = BucketId::parse("@proxied").unwrap();
+        let settings = BucketSettings {
+            permissions: BucketPermissions {
+                public_read: true,
+                public_write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let test = async {
+            let mut tunnel = client.open_tunnel("target", hops).await?;
+            assert_eq!(1, tunnels1.count());
+            assert_eq!(1, tunnels2.count());
+
+            // The nested connection is in a session with the target, like the other connections to the test server
+            let mut nested = tunnel.connect(&client);
+            nested.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+            let (relayed, read) = join(client.relay_tunnel(&mut tunnel), async {
+                nested.create_bucket(id.clone(), settings, true).await?;
+                nested
+                    .put(
+                        id.clone(),
+                        BucketBody::Numeric([(1, b"hidden".to_vec())].into()),
+                    )
+                    .await?;
+                let read = nested
+                    .get(id.clone(), BucketRange::Numeric(None, None), None)
+                    .await;
+
+                drop(nested);
+                read
+            })
+            .await;
+
+            relayed?;
+            read
+        };
+
+        let read = match block_on(select(pin!(test), pin!(join(serve_hop1, serve_target)))) {
+            Either::Left((read, _)) => read.unwrap(),
+            Either::Right(_) => panic!("The servers stopped before the test was done"),
+        };
+
+        assert_eq!(BucketBody::Numeric([(1, b"hidden".to_vec())].into()), read);
+        assert!(server.store.get_settings(&id).is_ok());
+    }
 }
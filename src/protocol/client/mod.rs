@@ -1,14 +1,34 @@
+use async_channel::Receiver;
 use binary_codec::{BinaryDeserializer, BinarySerializer};
 
 use crate::{
-    packets::{request::PlabbleRequestPacket, response::PlabbleResponsePacket},
-    protocol::{PlabbleConnection, error::PlabbleProtocolError},
+    packets::{
+        base::PlabblePacketBase,
+        body::{request_body::PlabbleRequestBody, response_body::PlabbleResponseBody},
+        header::{
+            request_header::PlabbleRequestHeader,
+            type_and_flags::{RequestPacketType, ResponsePacketType},
+        },
+        request::PlabbleRequestPacket,
+        response::PlabbleResponsePacket,
+    },
+    protocol::{PlabbleConnection, custom::CustomFlags, error::PlabbleProtocolError},
 };
 
+#[cfg(feature = "implementation")]
+pub mod buckets;
+#[cfg(all(test, feature = "implementation", feature = "server"))]
+pub(crate) mod helpers;
 #[cfg(feature = "implementation")]
 pub mod implementation;
 #[cfg(feature = "implementation")]
 pub mod options;
+pub mod pipeline;
+#[cfg(feature = "implementation")]
+pub mod proxy;
+pub mod subscriptions;
+#[cfg(feature = "implementation")]
+pub mod transfer;
 
 /// Client-side implementation of [`PlabbleConnection`].
 impl PlabbleConnection {
@@ -32,23 +52,53 @@ impl PlabbleConnection {
     }
 
     /// Sends a request packet and waits for a response with the matching counter.
+    /// Responses are received (and routed) while waiting, like with [`Self::recv_response`].
+    /// To have multiple requests in flight at once, [`Self::split`] the connection instead.
+    ///
+    /// An error response is returned as [`PlabbleProtocolError::ProtocolError`],
+    /// a response of another packet type than the request as [`PlabbleProtocolError::UnexpectedResponse`].
     pub async fn send_and_recv(
         &mut self,
         packet: PlabbleRequestPacket,
     ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let request_type = packet.header.packet_type.clone();
+        let hook = self.send_with_hook(packet).await?;
+
+        let res = loop {
+            if let Ok(res) = hook.try_recv() {
+                break res;
+            }
+            self.recv_response().await?;
+        };
+
+        check_response(&request_type, res)
+    }
+
+    /// Send a request packet with a hook that receives its response.
+    /// The hook is removed again if the request can not be sent, so it does not wait for a response that never comes.
+    pub(crate) async fn send_with_hook(
+        &mut self,
+        packet: PlabbleRequestPacket,
+    ) -> Result<Receiver<PlabbleResponsePacket>, PlabbleProtocolError> {
         let counter = self.config.data.as_ref().unwrap().client_counter;
         let (tx, rx) = async_channel::bounded(1);
         self.hooks.insert(counter, tx);
-        self.send_request(packet).await?;
-        rx.recv()
-            .await
-            .map_err(|_| PlabbleProtocolError::ReceiverError)
+
+        if let Err(e) = self.send_request(packet).await {
+            self.hooks.remove(&counter);
+            return Err(e);
+        }
+
+        Ok(rx)
     }
 
     /// Receives and processes the next response packet.
     ///
     /// If the packet is not fire-and-forget, the internal counter is incremented
     /// and any registered hook for the matching request counter is notified.
+    /// Subscription updates (fire-and-forget) are routed to the subscription streams of their bucket.
+    /// CUSTOM packets pushed by the server (fire-and-forget) are dispatched to the sub-protocol registry in the connection context,
+    /// the reply of the handler is sent back as a fire-and-forget CUSTOM request.
     pub async fn recv_response(&mut self) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
         let bytes = self
             .rx
@@ -56,21 +106,143 @@ impl PlabbleConnection {
             .await
             .map_err(|_| PlabbleProtocolError::ReceiverError)?;
 
-        let packet = PlabbleResponsePacket::from_bytes(&bytes, Some(&mut self.config))?;
+        self.process_response(&bytes).await
+    }
+
+    /// Process a received response packet, see [`Self::recv_response`]
+    pub(crate) async fn process_response(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let packet = PlabbleResponsePacket::from_bytes(bytes, Some(&mut self.config))?;
         self.config.reset();
         if !packet.base.fire_and_forget {
             self.config.data.as_mut().unwrap().increment(false);
             let counter = packet.header.request_counter.expect("Expected counter");
-            if let Some(hook) = self.hooks.get(&counter) {
-                if hook.is_closed() || hook.is_full() {
-                    self.hooks.remove(&counter);
-                } else {
-                    hook.try_send(packet.clone())
-                        .map_err(|_| PlabbleProtocolError::SenderError)?;
-                }
+            // A hook is only used once, a waiter that is gone does not need the response anymore
+            if let Some(hook) = self.hooks.remove(&counter) {
+                let _ = hook.try_send(packet.clone());
+            }
+        } else if let ResponsePacketType::Subscribe { deleted, .. } = packet.header.packet_type
+            && let PlabbleResponseBody::Subscribe(Some(update)) = &packet.body
+        {
+            self.subscriptions.dispatch(update.clone(), deleted);
+        } else if let Some(flags) = CustomFlags::from_response_type(&packet.header.packet_type)
+            && let PlabbleResponseBody::Custom(body) = &packet.body
+            && let Some(registry) = self.config.data.as_ref().unwrap().sub_protocols.clone()
+        {
+            // Requests can not contain errors, so only successful replies are sent back
+            if let Ok(reply) = registry.dispatch(flags, body.clone()) {
+                self.send_request(PlabbleRequestPacket {
+                    base: PlabblePacketBase {
+                        fire_and_forget: true,
+                        ..Default::default()
+                    },
+                    header: PlabbleRequestHeader::new(flags.request_type(), None),
+                    body: PlabbleRequestBody::Custom(reply),
+                })
+                .await?;
             }
         }
 
         Ok(packet)
     }
 }
+
+/// Check the response to a request: an error body is returned as error, and the response must be of the same packet type as the request
+fn check_response(
+    request_type: &RequestPacketType,
+    res: PlabbleResponsePacket,
+) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+    if let PlabbleResponseBody::Error(error) = res.body {
+        return Err(error.into());
+    }
+
+    if res.header.packet_type.get_discriminator() != request_type.get_discriminator() {
+        return Err(PlabbleProtocolError::UnexpectedResponse);
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packets::{
+        base::PlabblePacketBase,
+        body::{error::PlabbleError, response_body::PlabbleResponseBody},
+        header::{
+            response_header::PlabbleResponseHeader,
+            type_and_flags::{RequestPacketType, ResponsePacketType},
+        },
+        response::PlabbleResponsePacket,
+    };
+    use crate::protocol::{PlabbleConnection, client::check_response, error::PlabbleProtocolError};
+
+    fn response(
+        packet_type: ResponsePacketType,
+        body: PlabbleResponseBody,
+    ) -> PlabbleResponsePacket {
+        PlabbleResponsePacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleResponseHeader::new(packet_type, Some(1)),
+            body,
+        }
+    }
+
+    #[test]
+    fn check_response_maps_errors_and_unexpected_types() {
+        let request_type = RequestPacketType::Patch {
+            update_permissions: true,
+            add_to_acl: false,
+            remove_from_acl: false,
+        };
+
+        let res = response(ResponsePacketType::Patch, PlabbleResponseBody::Patch);
+        assert_eq!(res.clone(), check_response(&request_type, res).unwrap());
+
+        let res = response(
+            ResponsePacketType::Error,
+            PlabbleResponseBody::Error(PlabbleError::BucketNotFound),
+        );
+        assert!(matches!(
+            check_response(&request_type, res),
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::BucketNotFound
+            ))
+        ));
+
+        let res = response(ResponsePacketType::Post, PlabbleResponseBody::Post);
+        assert!(matches!(
+            check_response(&request_type, res),
+            Err(PlabbleProtocolError::UnexpectedResponse)
+        ));
+    }
+
+    #[test]
+    fn hook_is_removed_if_request_can_not_be_sent() {
+        let (tx, _) = async_channel::unbounded();
+        let (_, rx) = async_channel::unbounded();
+        let mut connection = PlabbleConnection::new(tx, rx);
+        connection.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+        connection.tx.close();
+
+        let req = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Post"
+
+            [body]
+            id = "@test"
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            futures::executor::block_on(connection.send_and_recv(req)),
+            Err(PlabbleProtocolError::SenderError)
+        ));
+        assert!(connection.hooks.is_empty());
+    }
+}
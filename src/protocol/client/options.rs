@@ -3,7 +3,7 @@ use serde_with::base64::{Base64, UrlSafe};
 use serde_with::formats::Unpadded;
 use serde_with::serde_as;
 
-use crate::crypto::KeyExchangeAlgorithm;
+use crate::crypto::{KeyExchangeAlgorithm, SignatureAlgorithm};
 use crate::packets::base::settings::CryptoSettings;
 
 #[serde_as]
@@ -50,6 +50,41 @@ impl Default for SessionOptions {
     }
 }
 
+/// Options for chunked uploads and downloads over STREAM packets
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TransferOptions {
+    /// Maximum amount of bytes per STREAM packet
+    pub chunk_size: u64,
+
+    /// Maximum amount of chunk requests that are sent before waiting for their responses
+    pub max_in_flight: usize,
+
+    /// Byte offset within the slot to resume the transfer from.
+    /// When uploading, the bytes of the source before this offset are expected to be uploaded already.
+    pub offset: u64,
+
+    /// When uploading, read the slot back after the upload and compare its hash with the hash of the source
+    pub verify: bool,
+
+    /// When downloading, the expected hash of the downloaded bytes. The download fails if the hash does not match
+    #[serde_as(as = "Option<Base64<UrlSafe, Unpadded>>")]
+    pub expected_hash: Option<[u8; 32]>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions {
+            chunk_size: 16 * 1024,
+            max_in_flight: 4,
+            offset: 0,
+            verify: true,
+            expected_hash: None,
+        }
+    }
+}
+
 /// Set crypto settings based on algorithm string list
 pub fn set_crypto_settings(settings: &mut CryptoSettings, algorithms: Vec<String>) {
     for alg in algorithms {
@@ -95,3 +130,23 @@ pub fn get_key_exchange_algorithms(settings: &CryptoSettings) -> Vec<KeyExchange
     }
     algs
 }
+
+/// Get signature algorithms according to crypto settings
+pub fn get_signature_algorithms(settings: &CryptoSettings) -> Vec<SignatureAlgorithm> {
+    let mut algs = Vec::new();
+    if settings.sign_ed25519 {
+        algs.push(SignatureAlgorithm::Ed25519);
+    }
+    if settings.sign_ed448 {
+        algs.push(SignatureAlgorithm::Ed448);
+    }
+    if let Some(pq_settings) = settings.post_quantum_settings {
+        if pq_settings.sign_pqc_dsa_44 {
+            algs.push(SignatureAlgorithm::Dsa44);
+        }
+        if pq_settings.sign_pqc_dsa_65 {
+            algs.push(SignatureAlgorithm::Dsa65);
+        }
+    }
+    algs
+}
@@ -0,0 +1,291 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use async_channel::Receiver;
+use futures::lock::{Mutex, MutexGuard};
+use log::warn;
+
+use crate::{
+    packets::{request::PlabbleRequestPacket, response::PlabbleResponsePacket},
+    protocol::{PlabbleConnection, client::check_response, error::PlabbleProtocolError},
+};
+
+/// Cloneable handle for sending requests on a connection that was [split](PlabbleConnection::split),
+/// so many requests can be in flight at once. The responses are routed to the waiting requests by the [`PlabbleReader`].
+#[derive(Clone)]
+pub struct PlabbleSender {
+    connection: Arc<Mutex<PlabbleConnection>>,
+    reading: Arc<AtomicBool>,
+}
+
+/// Reader of a connection that was [split](PlabbleConnection::split), which must run in the background
+/// to route the responses to the requests of the [`PlabbleSender`]s (and the subscription updates to their streams)
+pub struct PlabbleReader {
+    connection: Arc<Mutex<PlabbleConnection>>,
+    reading: Arc<AtomicBool>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl PlabbleConnection {
+    /// Split the connection into a cloneable sender handle and a reader that routes the responses to the waiting requests.
+    ///
+    /// Methods that wait for a response while holding the connection, like [`Self::start_session`],
+    /// can only be used on the [locked](PlabbleSender::lock) connection before the reader runs.
+    pub fn split(self) -> (PlabbleSender, PlabbleReader) {
+        let rx = self.rx.clone();
+        let connection = Arc::new(Mutex::new(self));
+        let reading = Arc::new(AtomicBool::new(false));
+        (
+            PlabbleSender {
+                connection: connection.clone(),
+                reading: reading.clone(),
+            },
+            PlabbleReader {
+                connection,
+                reading,
+                rx,
+            },
+        )
+    }
+}
+
+impl PlabbleSender {
+    /// Sends a request packet without waiting for a response, see [`PlabbleConnection::send_request`]
+    pub async fn send_request(
+        &self,
+        packet: PlabbleRequestPacket,
+    ) -> Result<(), PlabbleProtocolError> {
+        self.connection.lock().await.send_request(packet).await
+    }
+
+    /// Sends a request packet and waits for the response, without blocking other requests while waiting.
+    /// Fails with [`PlabbleProtocolError::ReceiverError`] if the reader is not running (yet or anymore), or stopped before the response was received.
+    /// See [`PlabbleConnection::send_and_recv`] for the other errors.
+    pub async fn send_and_recv(
+        &self,
+        packet: PlabbleRequestPacket,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let request_type = packet.header.packet_type.clone();
+        let hook = self.send_with_hook(packet).await?;
+        let res = hook
+            .recv()
+            .await
+            .map_err(|_| PlabbleProtocolError::ReceiverError)?;
+        check_response(&request_type, res)
+    }
+
+    /// Send a request packet with a hook that receives its response from the reader, see [`Self::send_and_recv`]
+    pub(crate) async fn send_with_hook(
+        &self,
+        packet: PlabbleRequestPacket,
+    ) -> Result<Receiver<PlabbleResponsePacket>, PlabbleProtocolError> {
+        // The request is sent while the connection is locked, so the requests are sent in the order of their counters
+        let mut connection = self.connection.lock().await;
+        if !self.reading.load(Ordering::Acquire) {
+            return Err(PlabbleProtocolError::ReceiverError);
+        }
+
+        connection.send_with_hook(packet).await
+    }
+
+    /// Lock the connection, for methods that wait for a response while holding it (like [`PlabbleConnection::start_session`]).
+    /// Fails with [`PlabbleProtocolError::ReaderRunning`] while the reader runs, because it would race the reader for the responses.
+    pub async fn lock(&self) -> Result<MutexGuard<'_, PlabbleConnection>, PlabbleProtocolError> {
+        let connection = self.connection.lock().await;
+        if self.reading.load(Ordering::Acquire) {
+            return Err(PlabbleProtocolError::ReaderRunning);
+        }
+
+        Ok(connection)
+    }
+
+    /// Access the connection (like its context and subscriptions), also while the reader runs
+    pub async fn with_connection<R>(&self, f: impl FnOnce(&mut PlabbleConnection) -> R) -> R {
+        f(&mut *self.connection.lock().await)
+    }
+}
+
+impl PlabbleReader {
+    /// Route the incoming responses until the connection is closed, responses that can not be processed are skipped.
+    /// Requests that are still waiting (or sent afterwards) then fail with [`PlabbleProtocolError::ReceiverError`].
+    ///
+    /// The reader counts as running as soon as this is called, so requests can be sent before the returned future is polled.
+    pub fn run(self) -> impl Future<Output = ()> {
+        self.reading.store(true, Ordering::Release);
+
+        async move {
+            // The connection is locked before the first response is taken, so a locked connection is released first
+            drop(self.connection.lock().await);
+
+            while let Ok(bytes) = self.rx.recv().await {
+                if let Err(e) = self.connection.lock().await.process_response(&bytes).await {
+                    warn!("Skipped response that could not be processed: {}", e);
+                }
+            }
+
+            let mut connection = self.connection.lock().await;
+            connection.hooks.clear();
+            self.reading.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures::{
+        executor::block_on,
+        future::{Either, join, join_all, join3, select},
+    };
+
+    use crate::{
+        core::BucketId,
+        packets::{
+            base::PlabblePacketBase,
+            body::{
+                bucket::{BucketBody, BucketQuery, BucketRange},
+                error::PlabbleError,
+                post::BucketSettings,
+                request_body::PlabbleRequestBody,
+                response_body::PlabbleResponseBody,
+            },
+            header::{request_header::PlabbleRequestHeader, type_and_flags::RequestPacketType},
+            request::PlabbleRequestPacket,
+        },
+        protocol::{client::helpers::TestServer, error::PlabbleProtocolError},
+        providers::BucketStore,
+    };
+
+    /// Create a test server with bucket `@pipeline`, that has a value in slots 0 to 19
+    fn create_server() -> TestServer {
+        let server = TestServer::new();
+        let id = BucketId::parse("@pipeline").unwrap();
+        server
+            .store
+            .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+            .unwrap();
+        server
+            .store
+            .write(
+                &id,
+                BucketBody::Numeric((0..20).map(|slot| (slot, vec![slot as u8])).collect()),
+                true,
+            )
+            .unwrap();
+
+        server
+    }
+
+    fn get_request(id: &str, slot: u32) -> PlabbleRequestPacket {
+        PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Get {
+                    binary_keys: false,
+                    subscribe: false,
+                    range_mode_until: false,
+                    with_limit: false,
+                },
+                Some(BucketId::parse(id).unwrap()),
+            ),
+            body: PlabbleRequestBody::Get(BucketQuery {
+                limit: None,
+                range: BucketRange::Numeric(Some(slot), Some(slot)),
+            }),
+        }
+    }
+
+    #[test]
+    fn can_pipeline_concurrent_requests() {
+        let server = create_server();
+        let (client, raw, serve) = server.connect_raw(|_| {});
+        let (sender, reader) = client.split();
+
+        // Requests fail right away if the reader does not run
+        assert!(matches!(
+            block_on(sender.send_and_recv(get_request("@pipeline", 0))),
+            Err(PlabbleProtocolError::ReceiverError)
+        ));
+
+        // A response that can not be processed is skipped by the reader
+        raw.try_send(vec![0xFF; 3]).unwrap();
+        drop(raw);
+
+        let reader = reader.run();
+        let requests = async {
+            let responses = join_all((0..20).map(|slot| {
+                let sender = sender.clone();
+                async move { sender.send_and_recv(get_request("@pipeline", slot)).await }
+            }))
+            .await;
+
+            let missing = sender.send_and_recv(get_request("@missing", 0)).await;
+
+            // Closing the connection stops the server and then the reader
+            sender
+                .with_connection(|connection| connection.tx.close())
+                .await;
+            (responses, missing)
+        };
+
+        let ((responses, missing), _, _) = block_on(join3(requests, reader, serve));
+
+        for (slot, res) in responses.into_iter().enumerate() {
+            assert_eq!(
+                PlabbleResponseBody::Get(BucketBody::Numeric(
+                    [(slot as u32, vec![slot as u8])].into()
+                )),
+                res.unwrap().body
+            );
+        }
+
+        assert!(matches!(
+            missing,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::BucketNotFound
+            ))
+        ));
+
+        // The reader stopped, so no response can be received anymore
+        assert!(matches!(
+            block_on(sender.send_and_recv(get_request("@pipeline", 0))),
+            Err(PlabbleProtocolError::ReceiverError)
+        ));
+    }
+
+    #[test]
+    fn connection_can_not_be_locked_while_reader_runs() {
+        let server = create_server();
+        let (client, serve) = server.connect();
+        let (sender, reader) = client.split();
+
+        // Requests that wait for a response while holding the lock can be sent before the reader runs,
+        // the server keeps serving (and the connection stays open) until the end of the test
+        let mut serve = pin!(serve);
+        let before = match block_on(select(
+            pin!(async {
+                let mut connection = sender.lock().await?;
+                connection.send_and_recv(get_request("@pipeline", 1)).await
+            }),
+            serve.as_mut(),
+        )) {
+            Either::Left((res, _)) => res,
+            Either::Right(_) => panic!("The server stopped before the response was sent"),
+        };
+        assert!(before.is_ok());
+
+        // The reader runs as soon as it is started, even before it is polled
+        let (_, during) = block_on(join(reader.run(), async {
+            let during = sender.lock().await.map(|_| ());
+            sender
+                .with_connection(|connection| connection.rx.close())
+                .await;
+            during
+        }));
+
+        assert!(matches!(during, Err(PlabbleProtocolError::ReaderRunning)));
+    }
+}
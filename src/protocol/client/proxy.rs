@@ -0,0 +1,557 @@
+use async_channel::{Receiver, Sender};
+use binary_codec::BinaryDeserializer;
+
+use crate::{
+    crypto::{KeyExchange, algorithm::KeyExhangeRequest},
+    network::node_info::NodeInfo,
+    packets::{
+        base::{PlabblePacketBase, settings::CryptoSettings},
+        body::{
+            proxy::{HopInfo, ProxyRequestBody, ProxyResponseBody},
+            request_body::PlabbleRequestBody,
+            response_body::PlabbleResponseBody,
+        },
+        header::{request_header::PlabbleRequestHeader, type_and_flags::RequestPacketType},
+        request::PlabbleRequestPacket,
+        response::PlabbleResponsePacket,
+    },
+    protocol::{
+        PlabbleConnection, client::options::get_key_exchange_algorithms,
+        error::PlabbleProtocolError, proxy::TunnelLayer,
+    },
+};
+
+/// Route of a PROXY tunnel through known nodes, from the server the client is connected to up to the last hop before the target
+///
+/// The tunnel is initialized at the first hop and then extended hop by hop. For every hop the client generates new keys,
+/// one key exchange per key exchange algorithm of the crypto settings, so hops can not link the segments of a tunnel by its keys.
+/// The requests for the hops after the first are sent as frames through the tunnel, sealed by the layers of the hops before them.
+/// Every hop answers with its own keys and a signature over both, so the layer key of a hop can only be derived by the client and the hop itself.
+pub struct ProxyRoute {
+    target: String,
+    hops: Vec<NodeInfo>,
+    settings: CryptoSettings,
+
+    /// Index of the hop that is initialized next
+    next: usize,
+
+    /// Key exchanges and keys of the request for the hop that is initialized next
+    key_exchanges: Vec<KeyExchange>,
+    keys: Vec<KeyExhangeRequest>,
+}
+
+impl ProxyRoute {
+    /// Create a route to the target through the hops (in route order). The first hop must be the server the client is connected to.
+    /// Fails with `FailedToProcessRequest` if there are no hops or the crypto settings have no key exchange algorithms.
+    pub fn new(
+        target: impl Into<String>,
+        hops: Vec<NodeInfo>,
+        settings: CryptoSettings,
+    ) -> Result<Self, PlabbleProtocolError> {
+        if hops.is_empty()
+            || hops.len() > u8::MAX as usize
+            || get_key_exchange_algorithms(&settings).is_empty()
+        {
+            return Err(PlabbleProtocolError::FailedToProcessRequest);
+        }
+
+        Ok(Self {
+            target: target.into(),
+            hops,
+            settings,
+            next: 0,
+            key_exchanges: Vec::new(),
+            keys: Vec::new(),
+        })
+    }
+
+    /// True if all hops of the route are part of the tunnel
+    pub fn is_complete(&self) -> bool {
+        self.next == self.hops.len()
+    }
+
+    /// Create the PROXY request that initializes the tunnel at the next hop of the route, with new keys for that hop.
+    /// The request only contains the address of the hop and the hop after it, the target is only given to the last hop.
+    /// The request for the first hop is sent to the server, the requests for the other hops are sent (serialized without a session)
+    /// as frames through the tunnel. Fails with `FailedToProcessRequest` if the route is complete or a key exchange algorithm is not supported.
+    pub fn request(&mut self) -> Result<PlabbleRequestPacket, PlabbleProtocolError> {
+        if self.is_complete() {
+            return Err(PlabbleProtocolError::FailedToProcessRequest);
+        }
+
+        self.key_exchanges = get_key_exchange_algorithms(&self.settings)
+            .into_iter()
+            .map(KeyExchange::new)
+            .collect();
+        self.keys = self
+            .key_exchanges
+            .iter_mut()
+            .map(|kx| kx.make_request())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(PlabbleProtocolError::FailedToProcessRequest)?;
+
+        let mut base = PlabblePacketBase::default();
+        if self.settings != CryptoSettings::default() {
+            base.specify_crypto_settings = true;
+            base.crypto_settings = Some(self.settings);
+        }
+
+        // A hop only learns the next hop, and only the last hop learns the target
+        let via: Vec<String> = self.hops[self.next..]
+            .iter()
+            .take(2)
+            .map(|hop| hop.address.to_string())
+            .collect();
+        let target = if via.len() == 1 {
+            self.target.clone()
+        } else {
+            String::new()
+        };
+
+        Ok(PlabbleRequestPacket {
+            base,
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Proxy {
+                    init_session: true,
+                    keep_connection: true,
+                    select_random_hops: false,
+                },
+                None,
+            ),
+            body: PlabbleRequestBody::Proxy(ProxyRequestBody::Initialize {
+                target,
+                hop_count: via.len() as u8,
+                via: Some(via),
+                keys: self.keys.clone(),
+            }),
+        })
+    }
+
+    /// Process the response of the first hop to [`Self::request`], returning the tunnel.
+    /// The hop must have answered with signatures that are valid for its verification keys, fails with `FailedToProcessResponse` otherwise.
+    pub fn process_response(
+        &mut self,
+        body: ProxyResponseBody,
+    ) -> Result<ProxyTunnel, PlabbleProtocolError> {
+        if self.next != 0 {
+            return Err(PlabbleProtocolError::UnexpectedResponse);
+        }
+
+        let (tunnel_id, layer) = self.process_hop(body)?;
+        Ok(ProxyTunnel {
+            tunnel_id,
+            layers: vec![layer],
+            channel: None,
+        })
+    }
+
+    /// Process the reply of the next hop to the frame with [`Self::request`], adding the layer of the hop to the tunnel.
+    /// The hop must have answered with signatures that are valid for its verification keys, fails with `FailedToProcessResponse` otherwise.
+    pub fn process_extension(
+        &mut self,
+        tunnel: &mut ProxyTunnel,
+        reply: &[u8],
+    ) -> Result<(), PlabbleProtocolError> {
+        if self.next == 0 {
+            return Err(PlabbleProtocolError::UnexpectedResponse);
+        }
+
+        let res = PlabbleResponsePacket::from_bytes(reply, None)
+            .map_err(|_| PlabbleProtocolError::FailedToProcessResponse)?;
+        let PlabbleResponseBody::Proxy(body) = res.body else {
+            return Err(PlabbleProtocolError::UnexpectedResponse);
+        };
+
+        let (_, layer) = self.process_hop(body)?;
+        tunnel.layers.push(layer);
+        Ok(())
+    }
+
+    /// Verify the initialize response of the next hop and create its layer, returning the tunnel ID the hop gave the tunnel
+    fn process_hop(
+        &mut self,
+        body: ProxyResponseBody,
+    ) -> Result<(u32, TunnelLayer), PlabbleProtocolError> {
+        let ProxyResponseBody::Initialize { tunnel_id, hops } = body else {
+            return Err(PlabbleProtocolError::UnexpectedResponse);
+        };
+
+        let node = self
+            .hops
+            .get(self.next)
+            .ok_or(PlabbleProtocolError::UnexpectedResponse)?;
+        let info = hops
+            .get(&node.address.to_string())
+            .filter(|_| hops.len() == 1)
+            .ok_or(PlabbleProtocolError::FailedToProcessResponse)?;
+
+        let data = HopInfo::signature_data(&self.keys, &info.keys);
+        let verified = !info.signatures.is_empty()
+            && info.signatures.iter().all(|signature| {
+                node.verification_keys
+                    .iter()
+                    .any(|key| key.verify(&data, signature) == Some(true))
+            });
+
+        if !verified || info.keys.len() != self.key_exchanges.len() {
+            return Err(PlabbleProtocolError::FailedToProcessResponse);
+        }
+
+        let secrets = self
+            .key_exchanges
+            .iter()
+            .zip(&info.keys)
+            .map(|(kx, key)| kx.process_response(key))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(PlabbleProtocolError::FailedToProcessResponse)?;
+
+        self.next += 1;
+        Ok((tunnel_id, TunnelLayer::new(self.settings, secrets)))
+    }
+}
+
+/// Channel of a nested connection: the packets it sends and the sender for the packets it receives
+type TunnelChannel = (Receiver<Vec<u8>>, Sender<Vec<u8>>);
+
+/// An initialized PROXY tunnel of the client, holding the onion layers of all hops (in route order)
+///
+/// Frames are sealed with the layer of the last hop first, so every hop can only remove its own layer.
+/// After every frame all layers move on to new keys, so frames must be sent one by one.
+pub struct ProxyTunnel {
+    /// Tunnel ID given by the first hop
+    pub tunnel_id: u32,
+    layers: Vec<TunnelLayer>,
+    channel: Option<TunnelChannel>,
+}
+
+impl ProxyTunnel {
+    /// Create the PROXY request that sends the data as one frame through the tunnel.
+    /// Without `keep_connection`, the hops close the tunnel after this frame.
+    pub fn frame_request(
+        &self,
+        data: &[u8],
+        keep_connection: bool,
+    ) -> Result<PlabbleRequestPacket, PlabbleProtocolError> {
+        let packet = self
+            .layers
+            .iter()
+            .rev()
+            .try_fold(data.to_vec(), |data, layer| layer.seal(true, &data))
+            .ok_or(PlabbleProtocolError::FailedToProcessRequest)?;
+
+        Ok(PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Proxy {
+                    init_session: false,
+                    keep_connection,
+                    select_random_hops: false,
+                },
+                None,
+            ),
+            body: PlabbleRequestBody::Proxy(ProxyRequestBody::Tunnel {
+                tunnel_id: self.tunnel_id,
+                packet,
+            }),
+        })
+    }
+
+    /// Process the response to [`Self::frame_request`], returning the data the target replied with.
+    /// The layers move on to the keys of the next frame, even if the frame failed (in which case the hops closed the tunnel).
+    pub fn process_frame_response(
+        &mut self,
+        res: PlabbleResponsePacket,
+    ) -> Result<Vec<u8>, PlabbleProtocolError> {
+        let data = match res.body {
+            PlabbleResponseBody::Proxy(ProxyResponseBody::Tunnel { tunnel_id, packet })
+                if tunnel_id == self.tunnel_id =>
+            {
+                self.layers
+                    .iter()
+                    .try_fold(packet, |data, layer| layer.open(false, &data))
+                    .ok_or(PlabbleProtocolError::FailedToProcessResponse)
+            }
+            PlabbleResponseBody::Error(error) => Err(error.into()),
+            _ => Err(PlabbleProtocolError::UnexpectedResponse),
+        };
+
+        for layer in self.layers.iter_mut() {
+            layer.next_frame();
+        }
+
+        data
+    }
+
+    /// Create a nested connection that runs through the tunnel, sharing the providers of the outer connection.
+    /// The packets of the nested connection are relayed while calling [`PlabbleConnection::relay_tunnel`] on the outer connection,
+    /// so a session with the target can be started on it like on any other connection.
+    pub fn connect(&mut self, outer: &PlabbleConnection) -> PlabbleConnection {
+        let (tx, outgoing) = async_channel::unbounded();
+        let (incoming, rx) = async_channel::unbounded();
+        self.channel = Some((outgoing, incoming));
+
+        let mut connection = PlabbleConnection::new(tx, rx);
+        let (outer, nested) = (
+            outer.config.data.as_ref().unwrap(),
+            connection.config.data.as_mut().unwrap(),
+        );
+        nested.key_provider = outer.key_provider.clone();
+        nested.certificate_provider = outer.certificate_provider.clone();
+        nested.sub_protocols = outer.sub_protocols.clone();
+        connection
+    }
+
+    /// Channel of the nested connection, if any
+    pub(crate) fn channel(&self) -> Option<TunnelChannel> {
+        self.channel.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{Ipv4Addr, SocketAddrV4},
+    };
+
+    use binary_codec::BinarySerializer;
+
+    use crate::{
+        core::{PlabbleDateTime, node_address::NodeAddress},
+        crypto::{KeyExchange, KeyExchangeAlgorithm, SignatureAlgorithm, algorithm::SigningKey},
+        network::node_info::NodeInfo,
+        packets::{
+            base::{PlabblePacketBase, settings::CryptoSettings},
+            body::{
+                proxy::{HopInfo, ProxyRequestBody, ProxyResponseBody},
+                request_body::PlabbleRequestBody,
+                response_body::PlabbleResponseBody,
+            },
+            header::{response_header::PlabbleResponseHeader, type_and_flags::ResponsePacketType},
+            response::PlabbleResponsePacket,
+        },
+        protocol::{client::proxy::ProxyRoute, error::PlabbleProtocolError, proxy::TunnelLayer},
+    };
+
+    fn node(port: u16, key: &SigningKey) -> NodeInfo {
+        NodeInfo {
+            id: [port as u8; 16],
+            address: NodeAddress::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)),
+            last_seen: PlabbleDateTime::from_now(0),
+            crypto_settings: CryptoSettings::default(),
+            verification_keys: vec![key.verification_key().unwrap()],
+        }
+    }
+
+    fn proxy_response(init_session: bool, body: ProxyResponseBody) -> PlabbleResponsePacket {
+        PlabbleResponsePacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleResponseHeader::new(ResponsePacketType::Proxy { init_session }, Some(1)),
+            body: PlabbleResponseBody::Proxy(body),
+        }
+    }
+
+    /// Answer the initialize request like a hop would, returning the response body and the layer of the hop
+    fn answer(
+        request: &PlabbleRequestBody,
+        address: &str,
+        key: &SigningKey,
+    ) -> (ProxyResponseBody, TunnelLayer) {
+        let PlabbleRequestBody::Proxy(ProxyRequestBody::Initialize { keys, .. }) = request else {
+            panic!("Expected proxy initialize request");
+        };
+
+        let (secret, response) = KeyExchange::new(KeyExchangeAlgorithm::X25519)
+            .process_request(&keys[0])
+            .unwrap();
+        let data = HopInfo::signature_data(keys, std::slice::from_ref(&response));
+        let info = HopInfo {
+            keys: vec![response],
+            signatures: vec![key.sign(&data).unwrap()],
+        };
+
+        (
+            ProxyResponseBody::Initialize {
+                tunnel_id: 7,
+                hops: HashMap::from([(address.to_string(), info)]),
+            },
+            TunnelLayer::new(CryptoSettings::default(), vec![secret]),
+        )
+    }
+
+    #[test]
+    fn can_build_route_and_send_frames_through_tunnel() {
+        let (key1, key2) = (
+            SigningKey::generate(SignatureAlgorithm::Ed25519),
+            SigningKey::generate(SignatureAlgorithm::Ed25519),
+        );
+        let mut route = ProxyRoute::new(
+            "target",
+            vec![node(1001, &key1), node(1002, &key2)],
+            CryptoSettings::default(),
+        )
+        .unwrap();
+
+        let first = route.request().unwrap();
+        let PlabbleRequestBody::Proxy(ProxyRequestBody::Initialize {
+            target,
+            hop_count,
+            via,
+            ..
+        }) = &first.body
+        else {
+            panic!("Expected proxy initialize request");
+        };
+        assert_eq!("", target);
+        assert_eq!(2, *hop_count);
+        assert_eq!(
+            Some(vec![
+                "127.0.0.1:1001".to_string(),
+                "127.0.0.1:1002".to_string()
+            ]),
+            *via
+        );
+
+        let (body, layer) = answer(&first.body, "127.0.0.1:1001", &key1);
+        let mut tunnel = route.process_response(body).unwrap();
+        let mut hops = vec![layer];
+        assert_eq!(7, tunnel.tunnel_id);
+        assert!(!route.is_complete());
+
+        // The second hop gets its own keys, in a frame that is sealed by the first hop
+        let second = route.request().unwrap();
+        let PlabbleRequestBody::Proxy(ProxyRequestBody::Initialize {
+            target,
+            hop_count,
+            via,
+            keys,
+        }) = &second.body
+        else {
+            panic!("Expected proxy initialize request");
+        };
+        assert_eq!("target", target);
+        assert_eq!(1, *hop_count);
+        assert_eq!(Some(vec!["127.0.0.1:1002".to_string()]), *via);
+        let PlabbleRequestBody::Proxy(ProxyRequestBody::Initialize {
+            keys: first_keys, ..
+        }) = &first.body
+        else {
+            unreachable!();
+        };
+        assert_ne!(first_keys, keys);
+
+        let PlabbleRequestBody::Proxy(ProxyRequestBody::Tunnel { packet, .. }) = tunnel
+            .frame_request(&second.to_bytes(None).unwrap(), true)
+            .unwrap()
+            .body
+        else {
+            panic!("Expected proxy tunnel request");
+        };
+        assert_eq!(
+            second.to_bytes(None).unwrap(),
+            hops[0].open(true, &packet).unwrap()
+        );
+
+        let (body, layer) = answer(&second.body, "127.0.0.1:1002", &key2);
+        let reply = proxy_response(true, body).to_bytes(None).unwrap();
+        let response = proxy_response(
+            false,
+            ProxyResponseBody::Tunnel {
+                tunnel_id: 7,
+                packet: hops[0].seal(false, &reply).unwrap(),
+            },
+        );
+        let reply = tunnel.process_frame_response(response).unwrap();
+        hops[0].next_frame();
+        route.process_extension(&mut tunnel, &reply).unwrap();
+        hops.push(layer);
+        assert!(route.is_complete());
+        assert!(matches!(
+            route.request(),
+            Err(PlabbleProtocolError::FailedToProcessRequest)
+        ));
+
+        for _ in 0..2 {
+            let PlabbleRequestBody::Proxy(ProxyRequestBody::Tunnel { tunnel_id, packet }) =
+                tunnel.frame_request(b"ping", true).unwrap().body
+            else {
+                panic!("Expected proxy tunnel request");
+            };
+            assert_eq!(7, tunnel_id);
+
+            let peeled = hops[0].open(true, &packet).unwrap();
+            assert_eq!(b"ping".to_vec(), hops[1].open(true, &peeled).unwrap());
+
+            let packet = hops[0]
+                .seal(false, &hops[1].seal(false, b"pong").unwrap())
+                .unwrap();
+            let response = proxy_response(
+                false,
+                ProxyResponseBody::Tunnel {
+                    tunnel_id: 7,
+                    packet,
+                },
+            );
+            assert_eq!(
+                b"pong".to_vec(),
+                tunnel.process_frame_response(response).unwrap()
+            );
+
+            for layer in hops.iter_mut() {
+                layer.next_frame();
+            }
+        }
+    }
+
+    #[test]
+    fn route_rejects_missing_or_forged_hops() {
+        let (key1, key2) = (
+            SigningKey::generate(SignatureAlgorithm::Ed25519),
+            SigningKey::generate(SignatureAlgorithm::Ed25519),
+        );
+        let new_route = || {
+            ProxyRoute::new(
+                "target",
+                vec![node(1001, &key1), node(1002, &key2)],
+                CryptoSettings::default(),
+            )
+            .unwrap()
+        };
+
+        // The first hop signed with the key of another node
+        let mut route = new_route();
+        let request = route.request().unwrap();
+        let (body, _) = answer(&request.body, "127.0.0.1:1001", &key2);
+        assert!(matches!(
+            route.process_response(body),
+            Err(PlabbleProtocolError::FailedToProcessResponse)
+        ));
+
+        // Another hop than the next hop of the route answered
+        let mut route = new_route();
+        let request = route.request().unwrap();
+        let (body, _) = answer(&request.body, "127.0.0.1:1002", &key2);
+        assert!(matches!(
+            route.process_response(body),
+            Err(PlabbleProtocolError::FailedToProcessResponse)
+        ));
+
+        // The second hop signed with the key of the first hop
+        let mut route = new_route();
+        let request = route.request().unwrap();
+        let (body, _) = answer(&request.body, "127.0.0.1:1001", &key1);
+        let mut tunnel = route.process_response(body).unwrap();
+        let request = route.request().unwrap();
+        let (body, _) = answer(&request.body, "127.0.0.1:1002", &key1);
+        let reply = proxy_response(true, body).to_bytes(None).unwrap();
+        assert!(matches!(
+            route.process_extension(&mut tunnel, &reply),
+            Err(PlabbleProtocolError::FailedToProcessResponse)
+        ));
+
+        assert!(matches!(
+            ProxyRoute::new("target", vec![], CryptoSettings::default()),
+            Err(PlabbleProtocolError::FailedToProcessRequest)
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_channel::{Receiver, Sender};
+use futures_core::Stream;
+
+use crate::packets::body::bucket::{BucketBody, BucketRange, SubscriptionUpdate};
+
+/// Change event of a subscribed bucket range, pushed by the server
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionEvent {
+    /// Slots were written by another client, with their new values
+    Written(BucketBody),
+
+    /// Slots were deleted by another client, with their old values
+    Deleted(BucketBody),
+
+    /// Bytes were streamed into a slot by another client at the byte offset, with only the written bytes
+    Streamed { offset: u64, slot: BucketBody },
+}
+
+/// A single local subscription on a range of a bucket
+struct LocalSubscription {
+    range: BucketRange,
+    sender: Sender<SubscriptionEvent>,
+}
+
+/// Client-side subscriptions of a connection, used to route subscription updates by bucket ID
+#[derive(Default)]
+pub struct ClientSubscriptions {
+    subscriptions: HashMap<[u8; 16], Vec<LocalSubscription>>,
+}
+
+impl ClientSubscriptions {
+    /// Register a subscription on a range of a bucket and return the stream of its events
+    pub fn register(&mut self, bucket: &[u8; 16], range: BucketRange) -> SubscriptionStream {
+        let (sender, receiver) = async_channel::unbounded();
+        self.subscriptions
+            .entry(*bucket)
+            .or_default()
+            .push(LocalSubscription { range, sender });
+
+        SubscriptionStream {
+            receiver: Box::pin(receiver),
+        }
+    }
+
+    /// Remove all subscriptions on exactly the given range of a bucket, ending their streams
+    pub fn remove(&mut self, bucket: &[u8; 16], range: &BucketRange) {
+        if let Some(subscriptions) = self.subscriptions.get_mut(bucket) {
+            subscriptions.retain(|s| &s.range != range);
+            if subscriptions.is_empty() {
+                self.subscriptions.remove(bucket);
+            }
+        }
+    }
+
+    /// Route a subscription update to the streams of the bucket, each stream only receives the slots within its range.
+    /// Subscriptions of which the stream is dropped are removed.
+    pub fn dispatch(&mut self, update: SubscriptionUpdate, deleted: bool) {
+        let Some(subscriptions) = self.subscriptions.get_mut(&update.id.data) else {
+            return;
+        };
+
+        subscriptions.retain(|subscription| {
+            if subscription.sender.is_closed() {
+                return false;
+            }
+
+            if let Some(slots) = update
+                .slots
+                .filter(std::slice::from_ref(&subscription.range))
+            {
+                let event = match update.offset {
+                    Some(offset) => SubscriptionEvent::Streamed {
+                        offset,
+                        slot: slots,
+                    },
+                    None if deleted => SubscriptionEvent::Deleted(slots),
+                    None => SubscriptionEvent::Written(slots),
+                };
+
+                return subscription.sender.try_send(event).is_ok();
+            }
+
+            true
+        });
+
+        if subscriptions.is_empty() {
+            self.subscriptions.remove(&update.id.data);
+        }
+    }
+}
+
+/// Async stream of the change events of a subscription.
+/// The stream ends when the subscription is removed (unsubscribed).
+pub struct SubscriptionStream {
+    receiver: Pin<Box<Receiver<SubscriptionEvent>>>,
+}
+
+impl SubscriptionStream {
+    /// Wait for the next event, or None if the subscription is removed
+    pub async fn next_event(&self) -> Option<SubscriptionEvent> {
+        self.receiver.recv().await.ok()
+    }
+}
+
+impl Stream for SubscriptionStream {
+    type Item = SubscriptionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binary_codec::{BinarySerializer, SerializerConfig};
+    use futures::{StreamExt, executor::block_on};
+
+    use crate::{
+        core::BucketId,
+        packets::{
+            body::bucket::{BucketBody, BucketRange},
+            context::PlabbleConnectionContext,
+            response::PlabbleResponsePacket,
+        },
+        protocol::{PlabbleConnection, client::subscriptions::SubscriptionEvent},
+    };
+
+    #[test]
+    fn routes_subscription_updates_to_streams_by_bucket_and_range() {
+        let (tx, _) = async_channel::unbounded();
+        let (incoming, rx) = async_channel::unbounded();
+        let mut connection = PlabbleConnection::new(tx, rx);
+        connection.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+
+        let id = BucketId::parse("@test").unwrap();
+        let mut low = connection
+            .subscriptions
+            .register(&id.data, BucketRange::Numeric(None, Some(5)));
+        let mut high = connection
+            .subscriptions
+            .register(&id.data, BucketRange::Numeric(Some(6), None));
+        let mut other = connection.subscriptions.register(
+            &BucketId::parse("@other").unwrap().data,
+            BucketRange::Numeric(None, None),
+        );
+
+        let update: PlabbleResponsePacket = toml::from_str(
+            r#"
+            version = 1
+            fire_and_forget = true
+
+            [header]
+            packet_type = "Subscribe"
+            update = true
+            deleted = true
+
+            [body]
+            id = "@test"
+            slots.Numeric = { 1 = "AQ", 7 = "Bw" }
+        "#,
+        )
+        .unwrap();
+
+        let mut context = PlabbleConnectionContext::new();
+        context.session_key = Some([0; 64]);
+        let bytes = update
+            .to_bytes(Some(&mut SerializerConfig::new(Some(context))))
+            .unwrap();
+        incoming.try_send(bytes).unwrap();
+
+        let received = block_on(connection.recv_response()).unwrap();
+        assert_eq!(update, received);
+
+        assert_eq!(
+            Some(SubscriptionEvent::Deleted(BucketBody::Numeric(
+                [(1, vec![1])].into()
+            ))),
+            block_on(low.next())
+        );
+        assert_eq!(
+            Some(SubscriptionEvent::Deleted(BucketBody::Numeric(
+                [(7, vec![7])].into()
+            ))),
+            block_on(high.next())
+        );
+
+        // Streams end when their subscription is removed
+        connection
+            .subscriptions
+            .remove(&id.data, &BucketRange::Numeric(None, Some(5)));
+        assert_eq!(None, block_on(low.next()));
+        connection.subscriptions.remove(
+            &BucketId::parse("@other").unwrap().data,
+            &BucketRange::Numeric(None, None),
+        );
+        assert_eq!(None, block_on(other.next()));
+    }
+}
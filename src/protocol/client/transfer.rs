@@ -0,0 +1,620 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    iter,
+};
+
+use async_channel::Receiver;
+
+use crate::{
+    core::BucketId,
+    crypto::Hasher256,
+    packets::{
+        base::PlabblePacketBase,
+        body::{
+            request_body::PlabbleRequestBody,
+            response_body::PlabbleResponseBody,
+            stream::{SlotKey, SlotRange, StreamRequestBody, StreamResponseBody},
+        },
+        header::{request_header::PlabbleRequestHeader, type_and_flags::RequestPacketType},
+        request::PlabbleRequestPacket,
+        response::PlabbleResponsePacket,
+    },
+    protocol::{
+        PlabbleConnection,
+        client::{check_response, options::TransferOptions, pipeline::PlabbleSender},
+        error::PlabbleProtocolError,
+    },
+};
+
+/// Chunked uploads and downloads of slots over STREAM packets
+impl PlabbleConnection {
+    /// Upload the source into a slot of a bucket in chunks. Returns the hash of the source.
+    ///
+    /// Unless the upload is resumed (see [`TransferOptions::offset`]), the slot is replaced by an empty value first,
+    /// so it is created if it does not exist yet and a longer value that was in the slot before does not remain.
+    /// Every chunk is written at its own byte offset, so resuming or retrying an upload is safe.
+    /// If [`TransferOptions::verify`] is set, the slot is downloaded again and the upload fails with
+    /// [`PlabbleProtocolError::IntegrityCheckFailed`] if its hash does not match the hash of the source.
+    pub async fn upload(
+        &mut self,
+        id: BucketId,
+        slot: SlotKey,
+        source: impl Read,
+        options: TransferOptions,
+    ) -> Result<[u8; 32], PlabbleProtocolError> {
+        upload(self, id, slot, source, options).await
+    }
+
+    /// Download a slot of a bucket in chunks into the destination, starting at [`TransferOptions::offset`].
+    /// Returns the hash of the downloaded bytes.
+    ///
+    /// If [`TransferOptions::expected_hash`] is set, the download fails with
+    /// [`PlabbleProtocolError::IntegrityCheckFailed`] if it does not match the hash of the downloaded bytes.
+    pub async fn download(
+        &mut self,
+        id: BucketId,
+        slot: SlotKey,
+        destination: impl Write,
+        options: TransferOptions,
+    ) -> Result<[u8; 32], PlabbleProtocolError> {
+        download(self, id, slot, destination, options).await
+    }
+}
+
+/// Chunked uploads and downloads of slots over STREAM packets, for a connection that was [split](PlabbleConnection::split)
+impl PlabbleSender {
+    /// Upload the source into a slot of a bucket in chunks, see [`PlabbleConnection::upload`]
+    pub async fn upload(
+        &self,
+        id: BucketId,
+        slot: SlotKey,
+        source: impl Read,
+        options: TransferOptions,
+    ) -> Result<[u8; 32], PlabbleProtocolError> {
+        upload(&mut self.clone(), id, slot, source, options).await
+    }
+
+    /// Download a slot of a bucket in chunks into the destination, see [`PlabbleConnection::download`]
+    pub async fn download(
+        &self,
+        id: BucketId,
+        slot: SlotKey,
+        destination: impl Write,
+        options: TransferOptions,
+    ) -> Result<[u8; 32], PlabbleProtocolError> {
+        download(&mut self.clone(), id, slot, destination, options).await
+    }
+}
+
+/// Connection that the requests of a transfer are sent over
+trait Transfer {
+    /// Send a request packet with a hook that receives its response
+    async fn send(
+        &mut self,
+        packet: PlabbleRequestPacket,
+    ) -> Result<Receiver<PlabbleResponsePacket>, PlabbleProtocolError>;
+
+    /// Wait for the response on the hook of a request
+    async fn recv(
+        &mut self,
+        hook: Receiver<PlabbleResponsePacket>,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError>;
+
+    /// Check if BLAKE3 is used for hashing, based on the crypto settings of the connection
+    async fn use_blake3(&mut self) -> bool;
+}
+
+/// The connection receives the responses itself while waiting
+impl Transfer for PlabbleConnection {
+    async fn send(
+        &mut self,
+        packet: PlabbleRequestPacket,
+    ) -> Result<Receiver<PlabbleResponsePacket>, PlabbleProtocolError> {
+        self.send_with_hook(packet).await
+    }
+
+    async fn recv(
+        &mut self,
+        hook: Receiver<PlabbleResponsePacket>,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        loop {
+            if let Ok(res) = hook.try_recv() {
+                return Ok(res);
+            }
+            self.recv_response().await?;
+        }
+    }
+
+    async fn use_blake3(&mut self) -> bool {
+        self.config.data.as_ref().unwrap().use_blake3()
+    }
+}
+
+/// The responses are routed to the sender by the reader
+impl Transfer for PlabbleSender {
+    async fn send(
+        &mut self,
+        packet: PlabbleRequestPacket,
+    ) -> Result<Receiver<PlabbleResponsePacket>, PlabbleProtocolError> {
+        self.send_with_hook(packet).await
+    }
+
+    async fn recv(
+        &mut self,
+        hook: Receiver<PlabbleResponsePacket>,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        hook.recv()
+            .await
+            .map_err(|_| PlabbleProtocolError::ReceiverError)
+    }
+
+    async fn use_blake3(&mut self) -> bool {
+        self.with_connection(|connection| connection.config.data.as_ref().unwrap().use_blake3())
+            .await
+    }
+}
+
+/// Upload the source into a slot of a bucket in chunks, see [`PlabbleConnection::upload`]
+async fn upload(
+    connection: &mut impl Transfer,
+    id: BucketId,
+    slot: SlotKey,
+    mut source: impl Read,
+    options: TransferOptions,
+) -> Result<[u8; 32], PlabbleProtocolError> {
+    let chunk_size = options.chunk_size.max(1);
+    let mut hasher = Hasher256::new(connection.use_blake3().await);
+
+    if options.offset == 0 {
+        let req = PlabbleConnection::put_request(
+            id.clone(),
+            slot.range(None, None).body(Vec::new()),
+            false,
+        );
+        let request_type = req.header.packet_type.clone();
+        let hook = connection.send(req).await?;
+        check_response(&request_type, connection.recv(hook).await?)?;
+    }
+
+    // The part of the source before the offset was uploaded before, so it is only hashed
+    let mut offset = 0;
+    while offset < options.offset {
+        let chunk = read_chunk(&mut source, chunk_size.min(options.offset - offset))?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        hasher.update(&chunk);
+        offset += chunk.len() as u64;
+    }
+
+    // The source is read (and hashed) one chunk at a time, while the chunks are sent
+    let mut chunks = iter::from_fn(|| match read_chunk(&mut source, chunk_size) {
+        Ok(chunk) if chunk.is_empty() => None,
+        Ok(chunk) => {
+            hasher.update(&chunk);
+            let range = slot.range(Some(offset), None);
+            offset += chunk.len() as u64;
+            Some(Ok(StreamRequestBody {
+                data: Some(chunk),
+                range,
+            }))
+        }
+        Err(e) => Some(Err(e.into())),
+    });
+
+    stream_chunks(
+        connection,
+        &id,
+        true,
+        &mut chunks,
+        options.max_in_flight,
+        |_| Ok(true),
+    )
+    .await?;
+    let hash = hasher.finalize();
+
+    if options.verify {
+        let options = TransferOptions {
+            offset: 0,
+            expected_hash: Some(hash),
+            ..options
+        };
+        download(connection, id, slot, io::sink(), options).await?;
+    }
+
+    Ok(hash)
+}
+
+/// Download a slot of a bucket in chunks into the destination, see [`PlabbleConnection::download`]
+async fn download(
+    connection: &mut impl Transfer,
+    id: BucketId,
+    slot: SlotKey,
+    mut destination: impl Write,
+    options: TransferOptions,
+) -> Result<[u8; 32], PlabbleProtocolError> {
+    let chunk_size = options.chunk_size.max(1);
+    let mut chunks = (0..).map(|idx: u64| {
+        let from = options.offset + idx * chunk_size;
+        Ok(StreamRequestBody {
+            data: None,
+            range: slot.range(Some(from), Some(from + chunk_size - 1)),
+        })
+    });
+
+    // The size of the slot is unknown, so chunks are requested until one of them is not full.
+    // Chunks that were already requested after the end of the slot are ignored.
+    let mut hasher = Hasher256::new(connection.use_blake3().await);
+    let mut done = false;
+    stream_chunks(
+        connection,
+        &id,
+        false,
+        &mut chunks,
+        options.max_in_flight,
+        |res| {
+            if done {
+                return Ok(false);
+            }
+
+            let chunk = res.data.unwrap_or_default();
+            destination.write_all(&chunk)?;
+            hasher.update(&chunk);
+            done = (chunk.len() as u64) < chunk_size;
+            Ok(!done)
+        },
+    )
+    .await?;
+    destination.flush()?;
+
+    let hash = hasher.finalize();
+    if options
+        .expected_hash
+        .is_some_and(|expected| expected != hash)
+    {
+        return Err(PlabbleProtocolError::IntegrityCheckFailed);
+    }
+
+    Ok(hash)
+}
+
+/// Send STREAM requests for the chunks (failing if a chunk can not be created), keeping at most `max_in_flight` requests waiting for their response.
+/// The responses are handled in order, no more chunks are requested once the handler returns false.
+async fn stream_chunks(
+    connection: &mut impl Transfer,
+    id: &BucketId,
+    write_mode: bool,
+    chunks: &mut dyn Iterator<Item = Result<StreamRequestBody, PlabbleProtocolError>>,
+    max_in_flight: usize,
+    mut handle: impl FnMut(StreamResponseBody) -> Result<bool, PlabbleProtocolError>,
+) -> Result<(), PlabbleProtocolError> {
+    let mut pending = VecDeque::new();
+    let mut sending = true;
+
+    loop {
+        while sending && pending.len() < max_in_flight.max(1) {
+            let Some(body) = chunks.next().transpose()? else {
+                sending = false;
+                break;
+            };
+
+            let request_type = RequestPacketType::Stream {
+                binary_keys: matches!(body.range, SlotRange::Binary(..)),
+                subscribe: false,
+                range_mode_until: false,
+                write_mode,
+            };
+            let hook = connection
+                .send(PlabbleRequestPacket {
+                    base: PlabblePacketBase::default(),
+                    header: PlabbleRequestHeader::new(request_type.clone(), Some(id.clone())),
+                    body: PlabbleRequestBody::Stream(body),
+                })
+                .await?;
+            pending.push_back((request_type, hook));
+        }
+
+        let Some((request_type, hook)) = pending.pop_front() else {
+            return Ok(());
+        };
+
+        let res = check_response(&request_type, connection.recv(hook).await?)?;
+        match res.body {
+            PlabbleResponseBody::Stream(body) => sending &= handle(body)?,
+            _ => return Err(PlabbleProtocolError::UnexpectedResponse),
+        }
+    }
+}
+
+/// Read the next chunk of at most `size` bytes from the source, which is only shorter at the end of the source
+fn read_chunk(source: &mut impl Read, size: u64) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::new();
+    source.by_ref().take(size).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use futures::executor::block_on;
+
+    use crate::{
+        core::BucketId,
+        packets::body::{
+            bucket::{BucketBody, BucketRange},
+            post::BucketSettings,
+            stream::SlotKey,
+        },
+        protocol::{
+            client::{helpers::TestServer, options::TransferOptions},
+            error::PlabbleProtocolError,
+        },
+        providers::BucketStore,
+    };
+
+    /// Create a test server with the publicly writable bucket `@files`
+    fn create_server() -> TestServer {
+        let server = TestServer::new();
+        let mut settings = BucketSettings::default();
+        settings.permissions.public_write = true;
+        server
+            .store
+            .create_bucket(&BucketId::parse("@files").unwrap(), settings, [0; 64], true)
+            .unwrap();
+
+        server
+    }
+
+    #[test]
+    fn can_upload_and_download_in_chunks() {
+        let server = create_server();
+        let (mut client, serve) = server.connect();
+        let id = BucketId::parse("@files").unwrap();
+        let file: Vec<u8> = (0..100).collect();
+        let options = TransferOptions {
+            chunk_size: 8,
+            max_in_flight: 3,
+            ..Default::default()
+        };
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let uploaded = client
+                    .upload(
+                        id.clone(),
+                        SlotKey::Binary("file".into()),
+                        file.as_slice(),
+                        options.clone(),
+                    )
+                    .await?;
+
+                // Resume the download halfway
+                let mut downloaded = Vec::new();
+                let options = TransferOptions {
+                    offset: 50,
+                    ..options
+                };
+                client
+                    .download(
+                        id.clone(),
+                        SlotKey::Binary("file".into()),
+                        &mut downloaded,
+                        options,
+                    )
+                    .await?;
+
+                drop(client);
+                Ok::<_, PlabbleProtocolError>((uploaded, downloaded))
+            },
+            serve,
+        ));
+
+        let (hash, downloaded) = result.unwrap();
+        assert_eq!(crate::crypto::hash_256(false, vec![&file]), hash);
+        assert_eq!(file[50..], downloaded);
+        assert_eq!(
+            BucketBody::Binary([("file".to_string(), file)].into()),
+            server
+                .store
+                .read(&id, &BucketRange::Binary(None, None), None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn can_resume_upload_and_detects_corruption() {
+        let server = create_server();
+        let (mut client, serve) = server.connect();
+        let id = BucketId::parse("@files").unwrap();
+
+        // The first part of the file was uploaded before, but got corrupted
+        server
+            .store
+            .write(
+                &id,
+                BucketBody::Numeric([(1, b"hellO".to_vec())].into()),
+                true,
+            )
+            .unwrap();
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let options = TransferOptions {
+                    chunk_size: 4,
+                    offset: 5,
+                    ..Default::default()
+                };
+                let result = client
+                    .upload(
+                        id.clone(),
+                        SlotKey::Numeric(1),
+                        &b"hello world"[..],
+                        options,
+                    )
+                    .await;
+
+                drop(client);
+                result
+            },
+            serve,
+        ));
+
+        assert!(matches!(
+            result,
+            Err(PlabbleProtocolError::IntegrityCheckFailed)
+        ));
+        assert_eq!(
+            BucketBody::Numeric([(1, b"hellO world".to_vec())].into()),
+            server
+                .store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap()
+        );
+    }
+
+    /// Source that fails after the first `len` bytes
+    struct FailingSource {
+        len: usize,
+    }
+
+    impl Read for FailingSource {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.len == 0 {
+                return Err(io::Error::other("disk failure"));
+            }
+
+            let n = buf.len().min(self.len);
+            buf[..n].fill(1);
+            self.len -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn upload_sends_chunks_while_reading_the_source() {
+        let server = create_server();
+        let (mut client, serve) = server.connect();
+        let id = BucketId::parse("@files").unwrap();
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let options = TransferOptions {
+                    chunk_size: 4,
+                    max_in_flight: 1,
+                    ..Default::default()
+                };
+                let result = client
+                    .upload(
+                        id.clone(),
+                        SlotKey::Numeric(1),
+                        FailingSource { len: 10 },
+                        options,
+                    )
+                    .await;
+
+                drop(client);
+                result
+            },
+            serve,
+        ));
+
+        // The chunks before the failure were already uploaded
+        assert!(matches!(result, Err(PlabbleProtocolError::IoError(_))));
+        assert_eq!(
+            BucketBody::Numeric([(1, vec![1; 8])].into()),
+            server
+                .store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn upload_replaces_longer_slot_and_creates_empty_slot() {
+        let server = create_server();
+        let (mut client, serve) = server.connect();
+        let id = BucketId::parse("@files").unwrap();
+
+        let (result, _) = block_on(futures::future::join(
+            async {
+                let options = TransferOptions {
+                    chunk_size: 4,
+                    ..Default::default()
+                };
+                client
+                    .upload(
+                        id.clone(),
+                        SlotKey::Numeric(1),
+                        &b"hello world"[..],
+                        options.clone(),
+                    )
+                    .await?;
+
+                // Uploading a shorter file leaves no trailing bytes of the old one, so it can be verified
+                client
+                    .upload(id.clone(), SlotKey::Numeric(1), &b"hi"[..], options.clone())
+                    .await?;
+                client
+                    .upload(id.clone(), SlotKey::Numeric(2), io::empty(), options)
+                    .await?;
+
+                drop(client);
+                Ok::<_, PlabbleProtocolError>(())
+            },
+            serve,
+        ));
+
+        assert!(result.is_ok());
+        assert_eq!(
+            BucketBody::Numeric([(1, b"hi".to_vec()), (2, vec![])].into()),
+            server
+                .store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn can_transfer_over_split_connection() {
+        let server = create_server();
+        let (client, serve) = server.connect();
+        let (sender, reader) = client.split();
+        let id = BucketId::parse("@files").unwrap();
+        let file: Vec<u8> = (0..50).collect();
+
+        let reader = reader.run();
+        let (result, _, _) = block_on(futures::future::join3(
+            async {
+                let options = TransferOptions {
+                    chunk_size: 8,
+                    ..Default::default()
+                };
+                sender
+                    .upload(
+                        id.clone(),
+                        SlotKey::Numeric(1),
+                        file.as_slice(),
+                        options.clone(),
+                    )
+                    .await?;
+
+                let mut downloaded = Vec::new();
+                sender
+                    .download(id.clone(), SlotKey::Numeric(1), &mut downloaded, options)
+                    .await?;
+
+                sender
+                    .with_connection(|connection| connection.tx.close())
+                    .await;
+                Ok::<_, PlabbleProtocolError>(downloaded)
+            },
+            reader,
+            serve,
+        ));
+
+        assert_eq!(file, result.unwrap());
+    }
+}
@@ -0,0 +1,535 @@
+use std::{collections::HashMap, sync::Arc};
+
+use binary_codec::{BinaryDeserializer, BinarySerializer};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    errors::{DeserializationError, SerializationError},
+    packets::{
+        body::{custom::CustomBody, error::PlabbleError},
+        header::type_and_flags::{RequestPacketType, ResponsePacketType},
+    },
+};
+
+/// The four header flags of a CUSTOM packet, of which the meaning is defined by the sub-protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CustomFlags {
+    pub flag1: bool,
+    pub flag2: bool,
+    pub flag3: bool,
+    pub flag4: bool,
+}
+
+impl CustomFlags {
+    /// CUSTOM request packet type with these flags
+    pub fn request_type(self) -> RequestPacketType {
+        RequestPacketType::Custom {
+            flag1: self.flag1,
+            flag2: self.flag2,
+            flag3: self.flag3,
+            flag4: self.flag4,
+        }
+    }
+
+    /// CUSTOM response packet type with these flags
+    pub fn response_type(self) -> ResponsePacketType {
+        ResponsePacketType::Custom {
+            flag1: self.flag1,
+            flag2: self.flag2,
+            flag3: self.flag3,
+            flag4: self.flag4,
+        }
+    }
+
+    /// Flags of a CUSTOM response packet type, or None if it is another type
+    pub fn from_response_type(packet_type: &ResponsePacketType) -> Option<Self> {
+        match *packet_type {
+            ResponsePacketType::Custom {
+                flag1,
+                flag2,
+                flag3,
+                flag4,
+            } => Some(Self {
+                flag1,
+                flag2,
+                flag3,
+                flag4,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Handler of a sub-protocol that runs on CUSTOM packets
+pub trait SubProtocolHandler: Send + Sync {
+    /// Handle the raw data of a CUSTOM packet with the header flags, returning the body to respond with.
+    /// Fail with a [`PlabbleError`] to respond with an error instead.
+    fn handle(&self, flags: CustomFlags, data: Vec<u8>) -> Result<CustomBody, PlabbleError>;
+}
+
+impl<F> SubProtocolHandler for F
+where
+    F: Fn(CustomFlags, Vec<u8>) -> Result<CustomBody, PlabbleError> + Send + Sync,
+{
+    fn handle(&self, flags: CustomFlags, data: Vec<u8>) -> Result<CustomBody, PlabbleError> {
+        self(flags, data)
+    }
+}
+
+/// Message type of a typed sub-protocol, which is (de)serialized with the binary codec and serde (TOML/JSON)
+pub trait SubProtocolMessage:
+    BinarySerializer + BinaryDeserializer + Serialize + DeserializeOwned + Send + 'static
+{
+}
+
+impl<T> SubProtocolMessage for T where
+    T: BinarySerializer + BinaryDeserializer + Serialize + DeserializeOwned + Send + 'static
+{
+}
+
+/// TOML/JSON form of the CUSTOM body of a typed sub-protocol, with the message itself instead of its raw (base64) data
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TypedCustomBody<M> {
+    /// Protocol ID of the sub-protocol
+    pub protocol: u16,
+
+    /// Request or response message of the sub-protocol
+    pub message: M,
+}
+
+/// Typed sub-protocol, declaring its protocol ID and the types of its request and response messages.
+/// The messages are sent as the raw data of CUSTOM bodies.
+///
+/// In TOML/JSON the data of a CUSTOM body is an opaque base64 value, the `to_typed_*` and `from_typed_*` helpers
+/// convert a CUSTOM body from and to a [`TypedCustomBody`] that (de)serializes the message itself.
+pub trait SubProtocol {
+    /// Protocol ID ([`CustomBody::protocol`]) of the sub-protocol
+    const PROTOCOL: u16;
+
+    /// Message sent by the client
+    type Request: SubProtocolMessage;
+
+    /// Message the server responds with
+    type Response: SubProtocolMessage;
+
+    /// Create the CUSTOM body of a request
+    fn encode_request(request: &Self::Request) -> Result<CustomBody, SerializationError> {
+        encode::<Self, _>(request)
+    }
+
+    /// Parse the request from a CUSTOM body of this sub-protocol
+    fn decode_request(body: &CustomBody) -> Result<Self::Request, DeserializationError> {
+        decode::<Self, _>(body)
+    }
+
+    /// Create the CUSTOM body of a response
+    fn encode_response(response: &Self::Response) -> Result<CustomBody, SerializationError> {
+        encode::<Self, _>(response)
+    }
+
+    /// Parse the response from a CUSTOM body of this sub-protocol
+    fn decode_response(body: &CustomBody) -> Result<Self::Response, DeserializationError> {
+        decode::<Self, _>(body)
+    }
+
+    /// Convert the CUSTOM body of a request into its typed TOML/JSON form
+    fn to_typed_request(
+        body: &CustomBody,
+    ) -> Result<TypedCustomBody<Self::Request>, DeserializationError> {
+        Ok(TypedCustomBody {
+            protocol: Self::PROTOCOL,
+            message: Self::decode_request(body)?,
+        })
+    }
+
+    /// Convert the typed TOML/JSON form of a request into its CUSTOM body
+    fn from_typed_request(
+        typed: &TypedCustomBody<Self::Request>,
+    ) -> Result<CustomBody, SerializationError> {
+        check_protocol::<Self, _>(typed)?;
+        Self::encode_request(&typed.message)
+    }
+
+    /// Convert the CUSTOM body of a response into its typed TOML/JSON form
+    fn to_typed_response(
+        body: &CustomBody,
+    ) -> Result<TypedCustomBody<Self::Response>, DeserializationError> {
+        Ok(TypedCustomBody {
+            protocol: Self::PROTOCOL,
+            message: Self::decode_response(body)?,
+        })
+    }
+
+    /// Convert the typed TOML/JSON form of a response into its CUSTOM body
+    fn from_typed_response(
+        typed: &TypedCustomBody<Self::Response>,
+    ) -> Result<CustomBody, SerializationError> {
+        check_protocol::<Self, _>(typed)?;
+        Self::encode_response(&typed.message)
+    }
+}
+
+/// Check that the typed form of a body belongs to the sub-protocol
+fn check_protocol<P: SubProtocol + ?Sized, M>(
+    typed: &TypedCustomBody<M>,
+) -> Result<(), SerializationError> {
+    if typed.protocol != P::PROTOCOL {
+        return Err(SerializationError::InvalidData(format!(
+            "Expected sub-protocol {}, got {}",
+            P::PROTOCOL,
+            typed.protocol
+        )));
+    }
+
+    Ok(())
+}
+
+/// Serialize a message of the sub-protocol into a CUSTOM body
+fn encode<P: SubProtocol + ?Sized, M: SubProtocolMessage>(
+    message: &M,
+) -> Result<CustomBody, SerializationError> {
+    Ok(CustomBody {
+        protocol: P::PROTOCOL,
+        data: message.to_bytes(None)?,
+    })
+}
+
+/// Deserialize a message of the sub-protocol from a CUSTOM body, failing if the body belongs to another protocol
+fn decode<P: SubProtocol + ?Sized, M: SubProtocolMessage>(
+    body: &CustomBody,
+) -> Result<M, DeserializationError> {
+    if body.protocol != P::PROTOCOL {
+        return Err(DeserializationError::InvalidData(format!(
+            "Expected sub-protocol {}, got {}",
+            P::PROTOCOL,
+            body.protocol
+        )));
+    }
+
+    Ok(M::from_bytes(&body.data, None)?)
+}
+
+/// Registry of sub-protocol handlers by protocol ID ([`CustomBody::protocol`])
+///
+/// The server dispatches CUSTOM requests to the registry in its connection context.
+/// The client dispatches CUSTOM packets pushed by the server (fire-and-forget) to the registry in its connection context.
+#[derive(Default, Clone)]
+pub struct SubProtocolRegistry {
+    handlers: HashMap<u16, Arc<dyn SubProtocolHandler>>,
+}
+
+impl SubProtocolRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the handler of a sub-protocol, replacing the handler that was registered for the protocol before (if any)
+    pub fn register(&mut self, protocol: u16, handler: impl SubProtocolHandler + 'static) {
+        self.handlers.insert(protocol, Arc::new(handler));
+    }
+
+    /// Register the handler of a typed sub-protocol, which receives the parsed request and returns the response.
+    /// Requests that can not be parsed are rejected with `PlabbleError::InvalidRequest`.
+    pub fn register_protocol<P: SubProtocol>(
+        &mut self,
+        handler: impl Fn(CustomFlags, P::Request) -> Result<P::Response, PlabbleError>
+        + Send
+        + Sync
+        + 'static,
+    ) {
+        self.register(P::PROTOCOL, move |flags, data: Vec<u8>| {
+            let request =
+                P::Request::from_bytes(&data, None).map_err(|_| PlabbleError::InvalidRequest)?;
+            let response = handler(flags, request)?;
+            P::encode_response(&response).map_err(|_| PlabbleError::InternalServerError)
+        });
+    }
+
+    /// Remove the handler of a sub-protocol, returns true if there was one
+    pub fn unregister(&mut self, protocol: u16) -> bool {
+        self.handlers.remove(&protocol).is_some()
+    }
+
+    /// Check if a handler is registered for the sub-protocol
+    pub fn contains(&self, protocol: u16) -> bool {
+        self.handlers.contains_key(&protocol)
+    }
+
+    /// Dispatch a CUSTOM body to the handler of its sub-protocol.
+    /// Fails with `PlabbleError::UnsupportedSubProtocol` if no handler is registered for the protocol.
+    pub fn dispatch(
+        &self,
+        flags: CustomFlags,
+        body: CustomBody,
+    ) -> Result<CustomBody, PlabbleError> {
+        self.handlers
+            .get(&body.protocol)
+            .ok_or(PlabbleError::UnsupportedSubProtocol)?
+            .handle(flags, body.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use binary_codec::{
+        BinaryDeserializer, BinarySerializer, FromBytes, SerializerConfig, ToBytes,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        packets::{
+            body::{custom::CustomBody, error::PlabbleError, request_body::PlabbleRequestBody},
+            context::PlabbleConnectionContext,
+            request::PlabbleRequestPacket,
+            response::PlabbleResponsePacket,
+        },
+        protocol::{
+            PlabbleConnection,
+            custom::{CustomFlags, SubProtocol, SubProtocolRegistry, TypedCustomBody},
+        },
+    };
+
+    /// Sub-protocol that repeats a text
+    struct Repeat;
+
+    #[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone)]
+    struct RepeatRequest {
+        times: u8,
+        #[dyn_length]
+        text: String,
+    }
+
+    #[derive(Debug, FromBytes, ToBytes, Serialize, Deserialize, PartialEq, Clone)]
+    struct RepeatResponse {
+        #[dyn_length]
+        text: String,
+    }
+
+    impl SubProtocol for Repeat {
+        const PROTOCOL: u16 = 42;
+        type Request = RepeatRequest;
+        type Response = RepeatResponse;
+    }
+
+    #[test]
+    fn dispatches_to_handlers_by_protocol_id() {
+        let mut registry = SubProtocolRegistry::new();
+        registry.register(1, |flags: CustomFlags, mut data: Vec<u8>| {
+            if flags.flag1 {
+                data.reverse();
+            }
+            Ok(CustomBody { protocol: 1, data })
+        });
+        registry.register(2, |_, _| Err(PlabbleError::InvalidRequest));
+
+        let flags = CustomFlags {
+            flag1: true,
+            ..Default::default()
+        };
+        let body = |protocol| CustomBody {
+            protocol,
+            data: vec![1, 2, 3],
+        };
+
+        assert_eq!(
+            Ok(CustomBody {
+                protocol: 1,
+                data: vec![3, 2, 1]
+            }),
+            registry.dispatch(flags, body(1))
+        );
+        assert_eq!(
+            Err(PlabbleError::InvalidRequest),
+            registry.dispatch(flags, body(2))
+        );
+        assert_eq!(
+            Err(PlabbleError::UnsupportedSubProtocol),
+            registry.dispatch(flags, body(3))
+        );
+
+        assert!(registry.unregister(1));
+        assert!(!registry.contains(1));
+        assert_eq!(
+            Err(PlabbleError::UnsupportedSubProtocol),
+            registry.dispatch(flags, body(1))
+        );
+    }
+
+    #[test]
+    fn dispatches_typed_sub_protocol_messages() {
+        let mut registry = SubProtocolRegistry::new();
+        registry.register_protocol::<Repeat>(|flags, req| {
+            let separator = if flags.flag1 { " " } else { "" };
+            Ok(RepeatResponse {
+                text: vec![req.text; req.times as usize].join(separator),
+            })
+        });
+
+        let request: RepeatRequest = toml::from_str(
+            r#"
+            times = 3
+            text = "hi"
+        "#,
+        )
+        .unwrap();
+        let body = Repeat::encode_request(&request).unwrap();
+        assert_eq!(
+            "002a03026869",
+            hex::encode(BinarySerializer::<()>::to_bytes(&body, None).unwrap())
+        );
+        assert_eq!(request, Repeat::decode_request(&body).unwrap());
+
+        let flags = CustomFlags {
+            flag1: true,
+            ..Default::default()
+        };
+        let response = Repeat::decode_response(&registry.dispatch(flags, body).unwrap()).unwrap();
+        assert_eq!("hi hi hi", response.text);
+        assert_eq!(
+            response,
+            serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap()
+        );
+
+        // Requests that can not be parsed are rejected
+        assert_eq!(
+            Err(PlabbleError::InvalidRequest),
+            registry.dispatch(
+                flags,
+                CustomBody {
+                    protocol: 42,
+                    data: vec![3]
+                }
+            )
+        );
+
+        // Bodies of other sub-protocols are not parsed
+        let other = CustomBody {
+            protocol: 43,
+            data: Repeat::encode_request(&request).unwrap().data,
+        };
+        assert!(Repeat::decode_request(&other).is_err());
+    }
+
+    #[test]
+    fn typed_sub_protocol_bodies_round_trip_through_toml_and_json() {
+        let body = Repeat::encode_request(&RepeatRequest {
+            times: 3,
+            text: "hi".to_string(),
+        })
+        .unwrap();
+
+        let typed = Repeat::to_typed_request(&body).unwrap();
+        let toml = toml::to_string(&typed).unwrap();
+        assert_eq!(
+            typed,
+            toml::from_str(
+                r#"
+                protocol = 42
+
+                [message]
+                times = 3
+                text = "hi"
+            "#
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            body,
+            Repeat::from_typed_request(&toml::from_str(&toml).unwrap()).unwrap()
+        );
+
+        let response = Repeat::encode_response(&RepeatResponse {
+            text: "hihihi".to_string(),
+        })
+        .unwrap();
+        let json = serde_json::to_string(&Repeat::to_typed_response(&response).unwrap()).unwrap();
+        assert_eq!(r#"{"protocol":42,"message":{"text":"hihihi"}}"#, json);
+        assert_eq!(
+            response,
+            Repeat::from_typed_response(&serde_json::from_str(&json).unwrap()).unwrap()
+        );
+
+        // The typed form must belong to the sub-protocol
+        let other = TypedCustomBody {
+            protocol: 43,
+            message: typed.message,
+        };
+        assert!(Repeat::from_typed_request(&other).is_err());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn client_replies_to_pushed_custom_packets() {
+        let (tx, outgoing) = async_channel::unbounded();
+        let (incoming, rx) = async_channel::unbounded();
+        let mut connection = PlabbleConnection::new(tx, rx);
+
+        let mut registry = SubProtocolRegistry::new();
+        registry.register(7, |_, data: Vec<u8>| {
+            Ok(CustomBody {
+                protocol: 7,
+                data: [b"pong ".as_slice(), &data].concat(),
+            })
+        });
+        let context = connection.config.data.as_mut().unwrap();
+        context.session_key = Some([0; 64]);
+        context.sub_protocols = Some(Arc::new(registry));
+
+        let mut server = PlabbleConnectionContext::new();
+        server.session_key = Some([0; 64]);
+        let push = |protocol: u16| {
+            let packet: PlabbleResponsePacket = toml::from_str(&format!(
+                r#"
+                version = 1
+                fire_and_forget = true
+
+                [header]
+                packet_type = "Custom"
+                flag3 = true
+
+                [body]
+                protocol = {protocol}
+                data = "cGluZw"
+            "#
+            ))
+            .unwrap();
+
+            packet
+                .to_bytes(Some(&mut SerializerConfig::new(Some(server.clone()))))
+                .unwrap()
+        };
+
+        incoming.try_send(push(7)).unwrap();
+        futures::executor::block_on(connection.recv_response()).unwrap();
+
+        let reply = PlabbleRequestPacket::from_bytes(
+            &outgoing.try_recv().unwrap(),
+            Some(&mut SerializerConfig::new(Some(server.clone()))),
+        )
+        .unwrap();
+        assert!(reply.base.fire_and_forget);
+        assert_eq!(
+            CustomFlags {
+                flag3: true,
+                ..Default::default()
+            }
+            .request_type(),
+            reply.header.packet_type
+        );
+        assert_eq!(
+            PlabbleRequestBody::Custom(CustomBody {
+                protocol: 7,
+                data: b"pong ping".to_vec()
+            }),
+            reply.body
+        );
+
+        // Unknown sub-protocols are not answered
+        incoming.try_send(push(8)).unwrap();
+        futures::executor::block_on(connection.recv_response()).unwrap();
+        assert!(outgoing.is_empty());
+    }
+}
@@ -20,6 +20,9 @@ pub enum PlabbleProtocolError {
     FailedToProcessResponse,
     InputParsingFailed,
     OutputSerializationFailed,
+    IoError(std::io::Error),
+    IntegrityCheckFailed,
+    ReaderRunning,
 }
 
 impl From<SerializationError> for PlabbleProtocolError {
@@ -34,6 +37,12 @@ impl From<DeserializationError> for PlabbleProtocolError {
     }
 }
 
+impl From<std::io::Error> for PlabbleProtocolError {
+    fn from(value: std::io::Error) -> Self {
+        PlabbleProtocolError::IoError(value)
+    }
+}
+
 impl From<PlabbleError> for PlabbleProtocolError {
     fn from(value: PlabbleError) -> Self {
         PlabbleProtocolError::ProtocolError(value)
@@ -54,6 +63,9 @@ impl fmt::Display for PlabbleProtocolError {
             Self::FailedToProcessResponse => write!(f, "Failed to process response"),
             Self::InputParsingFailed => write!(f, "Input parsing failed"),
             Self::OutputSerializationFailed => write!(f, "Output serialization failed"),
+            Self::IoError(e) => write!(f, "IO error: {}", e),
+            Self::IntegrityCheckFailed => write!(f, "Integrity check failed"),
+            Self::ReaderRunning => write!(f, "Reader is running"),
         }
     }
 }
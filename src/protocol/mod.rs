@@ -4,9 +4,13 @@ use async_channel::{Receiver, Sender};
 use binary_codec::SerializerConfig;
 use serde::{Deserialize, Serialize};
 
+pub mod custom;
 pub mod error;
+pub mod proxy;
 
 use crate::packets::{context::PlabbleConnectionContext, response::PlabbleResponsePacket};
+#[cfg(feature = "client")]
+use crate::protocol::client::subscriptions::ClientSubscriptions;
 
 #[cfg(feature = "server")]
 pub mod server;
@@ -17,6 +21,9 @@ pub mod client;
 /// Plabble Connection
 pub struct PlabbleConnection {
     pub hooks: HashMap<u16, Sender<PlabbleResponsePacket>>,
+    /// Subscriptions of the client, receiving the subscription updates pushed by the server
+    #[cfg(feature = "client")]
+    pub subscriptions: ClientSubscriptions,
     pub config: SerializerConfig<PlabbleConnectionContext>,
     pub tx: Sender<Vec<u8>>,
     pub rx: Receiver<Vec<u8>>,
@@ -31,6 +38,8 @@ impl PlabbleConnection {
             tx,
             rx,
             hooks: HashMap::new(),
+            #[cfg(feature = "client")]
+            subscriptions: ClientSubscriptions::default(),
         }
     }
 }
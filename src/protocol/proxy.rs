@@ -0,0 +1,85 @@
+use crate::packets::{
+    base::{PlabblePacketBase, settings::CryptoSettings},
+    context::PlabbleConnectionContext,
+};
+
+/// One layer of the onion encryption of a PROXY tunnel, shared by the client and one hop of the route
+///
+/// The layer key is derived from the shared secrets of the key exchange with the hop, like a session key.
+/// Every tunnel frame (a request and its response) uses new keys thanks to the frame counter, so the client and hop
+/// must call [`TunnelLayer::next_frame`] after every frame to stay in sync. Frames are encrypted with the AEAD ciphers
+/// of the crypto settings of the tunnel, so a hop can not modify them without being noticed.
+#[derive(Clone)]
+pub struct TunnelLayer {
+    context: PlabbleConnectionContext,
+}
+
+impl TunnelLayer {
+    /// Create the layer from the shared secrets of the key exchange with the hop (in the order of the key exchange algorithms)
+    pub fn new(settings: CryptoSettings, shared_secrets: Vec<[u8; 32]>) -> Self {
+        let mut context = PlabbleConnectionContext::new();
+        context.crypto_settings = Some(settings);
+        context.create_session_key(settings.use_blake3, None, None, shared_secrets);
+        Self { context }
+    }
+
+    /// Add the encryption layer to the data of a frame, `is_request` is set for frames towards the target
+    pub fn seal(&self, is_request: bool, data: &[u8]) -> Option<Vec<u8>> {
+        self.context
+            .encrypt(&PlabblePacketBase::default(), is_request, data, &[])
+    }
+
+    /// Remove the encryption layer from the data of a frame, `is_request` is set for frames towards the target.
+    /// Returns None if the data is not valid for this layer.
+    pub fn open(&self, is_request: bool, data: &[u8]) -> Option<Vec<u8>> {
+        self.context
+            .decrypt(&PlabblePacketBase::default(), is_request, data, &[])
+    }
+
+    /// Move on to the keys of the next frame, after the response of the current frame is handled
+    pub fn next_frame(&mut self) {
+        self.context.increment(true);
+        self.context.increment(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{packets::base::settings::CryptoSettings, protocol::proxy::TunnelLayer};
+
+    #[test]
+    fn can_add_and_remove_tunnel_layers() {
+        let settings = CryptoSettings::default();
+        let mut hop1 = TunnelLayer::new(settings, vec![[1; 32]]);
+        let mut hop2 = TunnelLayer::new(settings, vec![[2; 32]]);
+        let (mut client1, mut client2) = (hop1.clone(), hop2.clone());
+
+        for frame in 0..2 {
+            // The client adds the layer of the last hop first
+            let inner = client2.seal(true, b"hello").unwrap();
+            let onion = client1.seal(true, &inner).unwrap();
+            assert_ne!(inner, onion);
+
+            assert!(hop2.open(true, &onion).is_none());
+            let peeled = hop1.open(true, &onion).unwrap();
+            assert_eq!(b"hello".to_vec(), hop2.open(true, &peeled).unwrap());
+
+            // Responses are sealed with other keys than requests
+            let response = hop1
+                .seal(false, &hop2.seal(false, b"world").unwrap())
+                .unwrap();
+            assert!(client1.open(true, &response).is_none());
+            let peeled = client1.open(false, &response).unwrap();
+            assert_eq!(b"world".to_vec(), client2.open(false, &peeled).unwrap());
+
+            for layer in [&mut hop1, &mut hop2, &mut client1, &mut client2] {
+                layer.next_frame();
+            }
+
+            // Frames can not be replayed
+            if frame == 0 {
+                assert!(hop1.open(true, &onion).is_none());
+            }
+        }
+    }
+}
@@ -1,8 +1,33 @@
+use std::{collections::HashMap, sync::Arc};
+
+use binary_codec::{BinaryDeserializer, BinarySerializer, SerializerConfig};
+
 use crate::{
-    crypto::KeyExchange,
+    core::{BucketId, PlabbleDateTime},
+    crypto::{
+        KeyExchange, SignatureAlgorithm, algorithm::CryptoSignature, certificate::Certificate,
+    },
+    errors::SerializationError,
     packets::{
-        body::{request_body::PlabbleRequestBody, response_body::PlabbleResponseBody, session::SessionResponseBody},
+        base::PlabblePacketBase,
+        body::{
+            bucket::{BucketBody, BucketQuery, BucketRange, PutResponseBody},
+            certificate::{CertificateRequestBody, CertificateResponseBody},
+            custom::CustomBody,
+            error::PlabbleError,
+            identify::IdentifyRequestBody,
+            opcode::{OpCodeRequestBody, OpCodeResponseBody},
+            patch::PatchRequestBody,
+            post::PostRequestBody,
+            proxy::{HopInfo, ProxyRequestBody, ProxyResponseBody},
+            register::RegisterRequestBody,
+            request_body::PlabbleRequestBody,
+            response_body::PlabbleResponseBody,
+            session::SessionResponseBody,
+            stream::{StreamRequestBody, StreamResponseBody},
+        },
         header::{
+            request_header::PlabbleRequestHeader,
             response_header::PlabbleResponseHeader,
             type_and_flags::{RequestPacketType, ResponsePacketType},
         },
@@ -10,16 +35,54 @@ use crate::{
         response::PlabbleResponsePacket,
     },
     protocol::{
-        PlabbleConnection, client::options::get_key_exchange_algorithms,
+        PlabbleConnection,
+        client::options::{get_key_exchange_algorithms, get_signature_algorithms},
+        custom::CustomFlags,
         error::PlabbleProtocolError,
+        proxy::TunnelLayer,
+        server::{
+            permissions::{BucketAccess, BucketOperation},
+            proxy::Tunnel,
+            scripting::ServerBucketProvider,
+            subscriptions::{Subscriber, SubscriberAccess},
+        },
     },
+    providers::{BucketStore, CertificateProvider, ProxyLink},
+    scripting::interpreter::ScriptInterpreter,
 };
 
 impl PlabbleConnection {
     /// Handle Plabble request and produce a Plabble response
     ///
-    /// This method assumes that basic checks (Plabble version, supported crypto algorithms, request not malformed) are already performed
-    pub fn handle_request(
+    /// This method assumes that basic checks (Plabble version, supported crypto algorithms, request not malformed) are already performed.
+    /// If handling the request fails with a [`PlabbleError`], an error response is returned instead.
+    pub async fn handle_request(
+        &mut self,
+        req: PlabbleRequestPacket,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let base = req.base.clone();
+        match self.process_request(req).await {
+            Err(PlabbleProtocolError::ProtocolError(error)) => Ok(self.create_response(
+                base,
+                ResponsePacketType::Error,
+                PlabbleResponseBody::Error(error),
+            )),
+            res => res,
+        }
+    }
+
+    /// Counter of the request that is currently handled (the client counter is already incremented when it was received)
+    fn request_counter(&self) -> u16 {
+        self.config
+            .data
+            .as_ref()
+            .unwrap()
+            .client_counter
+            .wrapping_sub(1)
+    }
+
+    /// Dispatch a request to the handler for its packet type
+    async fn process_request(
         &mut self,
         req: PlabbleRequestPacket,
     ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
@@ -27,9 +90,15 @@ impl PlabbleConnection {
             RequestPacketType::Certificate {
                 full_chain,
                 full_certs,
-                challenge,
-                query_mode,
-            } => todo!(),
+                challenge: _,
+                query_mode: _,
+            } => {
+                if let PlabbleRequestBody::Certificate(body) = req.body {
+                    return self.handle_certificate(req.base, full_chain, full_certs, body);
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Session {
                 persist_key,
                 enable_encryption,
@@ -113,60 +182,3662 @@ impl PlabbleConnection {
                 binary_keys,
                 subscribe,
                 range_mode_until,
-                with_limit,
-            } => todo!(),
+                with_limit: _,
+            } => {
+                if let PlabbleRequestBody::Get(query) = req.body {
+                    let id = req.header.id.ok_or(PlabbleError::InvalidRequest)?;
+                    return self.handle_get(
+                        req.base,
+                        id,
+                        binary_keys,
+                        subscribe,
+                        range_mode_until,
+                        query,
+                    );
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Stream {
-                binary_keys,
+                binary_keys: _,
                 subscribe,
                 range_mode_until,
                 write_mode,
-            } => todo!(),
+            } => {
+                if let PlabbleRequestBody::Stream(body) = req.body {
+                    let id = req.header.id.ok_or(PlabbleError::InvalidRequest)?;
+                    return self.handle_stream(
+                        req.base,
+                        id,
+                        subscribe,
+                        range_mode_until,
+                        write_mode,
+                        body,
+                    );
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Post {
-                binary_keys,
+                binary_keys: _,
                 subscribe,
                 range_mode_until,
                 do_not_persist,
-            } => todo!(),
+            } => {
+                if let PlabbleRequestBody::Post(body) = req.body {
+                    return self.handle_post(
+                        req.base,
+                        subscribe,
+                        range_mode_until,
+                        do_not_persist,
+                        body,
+                    );
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Patch {
-                update_permissions,
-                add_to_acl,
-                remove_from_acl,
-            } => todo!(),
+                update_permissions: _,
+                add_to_acl: _,
+                remove_from_acl: _,
+            } => {
+                if let PlabbleRequestBody::Patch(body) = req.body {
+                    let id = req.header.id.ok_or(PlabbleError::InvalidRequest)?;
+                    return self.handle_patch(req.base, id, body);
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Put {
-                binary_keys,
+                binary_keys: _,
                 subscribe,
                 assert_keys,
                 append,
-            } => todo!(),
+            } => {
+                if let PlabbleRequestBody::Put(body) = req.body {
+                    let id = req.header.id.ok_or(PlabbleError::InvalidRequest)?;
+                    return self.handle_put(
+                        req.base,
+                        id,
+                        subscribe,
+                        assert_keys,
+                        append,
+                        body.body,
+                    );
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Delete {
                 binary_keys,
                 range_mode_until,
-                with_limit,
+                with_limit: _,
                 return_deleted,
-            } => todo!(),
+            } => {
+                if let PlabbleRequestBody::Delete(query) = req.body {
+                    let id = req.header.id.ok_or(PlabbleError::InvalidRequest)?;
+                    return self.handle_delete(
+                        req.base,
+                        id,
+                        binary_keys,
+                        range_mode_until,
+                        return_deleted,
+                        query,
+                    );
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Subscribe {
-                binary_keys,
+                binary_keys: _,
                 range_mode_until,
                 unsubscribe,
-            } => todo!(),
+            } => {
+                if let PlabbleRequestBody::Subscribe(query) = req.body {
+                    let id = req.header.id.ok_or(PlabbleError::InvalidRequest)?;
+                    return self.handle_subscribe(
+                        req.base,
+                        id,
+                        range_mode_until,
+                        unsubscribe,
+                        query,
+                    );
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Whisper { whisper_type } => todo!(),
-            RequestPacketType::Register => todo!(),
-            RequestPacketType::Identify => todo!(),
+            RequestPacketType::Register => {
+                if let PlabbleRequestBody::Register(body) = req.body {
+                    return self.handle_register(req.base, body);
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
+            RequestPacketType::Identify => {
+                if let PlabbleRequestBody::Identify(body) = req.body {
+                    return self.handle_identify(req.base, body);
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Proxy {
-                init_session,
+                init_session: _,
                 keep_connection,
                 select_random_hops,
-            } => todo!(),
+            } => match req.body {
+                PlabbleRequestBody::Proxy(ProxyRequestBody::Tunnel { tunnel_id, packet }) => {
+                    self.handle_proxy_tunnel(req.base, keep_connection, tunnel_id, packet)
+                        .await
+                }
+                PlabbleRequestBody::Proxy(body) => {
+                    self.handle_proxy_initialize(req.base, select_random_hops, body)
+                        .await
+                }
+                _ => Err(PlabbleProtocolError::UnexpectedRequest),
+            },
             RequestPacketType::Opcode {
                 allow_bucket_operations,
                 allow_eval,
-            } => todo!(),
+            } => {
+                if let PlabbleRequestBody::Opcode(body) = req.body {
+                    return self.handle_opcode(req.base, allow_bucket_operations, allow_eval, body);
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
             RequestPacketType::Custom {
                 flag1,
                 flag2,
                 flag3,
                 flag4,
-            } => todo!(),
+            } => {
+                if let PlabbleRequestBody::Custom(body) = req.body {
+                    let flags = CustomFlags {
+                        flag1,
+                        flag2,
+                        flag3,
+                        flag4,
+                    };
+                    return self.handle_custom(req.base, flags, body);
+                }
+
+                Err(PlabbleProtocolError::UnexpectedRequest)
+            }
+        }
+    }
+
+    /// Create a response packet to the request that is currently handled
+    fn create_response(
+        &self,
+        base: PlabblePacketBase,
+        packet_type: ResponsePacketType,
+        body: PlabbleResponseBody,
+    ) -> PlabbleResponsePacket {
+        PlabbleResponsePacket {
+            header: PlabbleResponseHeader::new(packet_type, Some(self.request_counter())),
+            base,
+            body,
+        }
+    }
+
+    /// Get the bucket store of the server, or fail with an internal server error if there is none
+    fn bucket_store(&self) -> Result<Arc<dyn BucketStore>, PlabbleProtocolError> {
+        Ok(self
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .ok_or(PlabbleError::InternalServerError)?)
+    }
+
+    /// Check if the requester of the current request is allowed to perform the operation on a bucket,
+    /// based on the bucket permissions and the access tiers of the requester
+    fn authorize(
+        &self,
+        store: &dyn BucketStore,
+        id: &BucketId,
+        operation: BucketOperation,
+    ) -> Result<(), PlabbleProtocolError> {
+        let context = self.config.data.as_ref().unwrap();
+        let settings = store.get_settings(id)?;
+        let access = BucketAccess::new(
+            &settings,
+            context.identity.as_ref(),
+            context.bucket_key_authenticated,
+        );
+
+        Ok(access.authorize(&settings.permissions, operation)?)
+    }
+
+    /// Handle GET request: read the slots within the queried range from the bucket (and optionally subscribe to the range)
+    fn handle_get(
+        &self,
+        base: PlabblePacketBase,
+        id: BucketId,
+        binary_keys: bool,
+        subscribe: bool,
+        range_mode_until: bool,
+        query: BucketQuery,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let store = self.bucket_store()?;
+        self.authorize(store.as_ref(), &id, BucketOperation::Read)?;
+
+        let range = query.range.with_range_mode(range_mode_until);
+        let body = store.read(&id, &range, query.limit)?;
+
+        if subscribe && let Some(subscriber) = self.subscriber() {
+            subscriber.subscribe(&id, range, base.use_encryption, self.subscriber_access());
+        }
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Get { binary_keys },
+            PlabbleResponseBody::Get(body),
+        ))
+    }
+
+    /// Handle PUT request: write the slots to the bucket, or append the values to the next free numeric slots.
+    /// All slots are written at once, or none at all if the request fails.
+    fn handle_put(
+        &self,
+        base: PlabblePacketBase,
+        id: BucketId,
+        subscribe: bool,
+        assert_keys: bool,
+        append: bool,
+        body: BucketBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let store = self.bucket_store()?;
+        let operation = if append {
+            BucketOperation::Append
+        } else {
+            BucketOperation::Write
+        };
+
+        self.authorize(store.as_ref(), &id, operation)?;
+        if subscribe {
+            self.authorize(store.as_ref(), &id, BucketOperation::Read)?;
+        }
+
+        let (written, slots) = if append {
+            // In append mode, the keys only determine the order in which the values are appended
+            let BucketBody::Numeric(values) = body else {
+                return Err(PlabbleError::InvalidRequest.into());
+            };
+
+            let mut values: Vec<_> = values.into_iter().collect();
+            values.sort_by_key(|(slot, _)| *slot);
+            let values: Vec<_> = values.into_iter().map(|(_, value)| value).collect();
+
+            let slots = store.append(&id, values.clone())?;
+            let written = BucketBody::Numeric(slots.iter().copied().zip(values).collect());
+            (written, Some(slots))
+        } else {
+            store.write(&id, body.clone(), !assert_keys)?;
+            (body, None)
+        };
+
+        if let Some(subscriber) = self.subscriber() {
+            if subscribe {
+                for range in slot_ranges(&written) {
+                    subscriber.subscribe(&id, range, base.use_encryption, self.subscriber_access());
+                }
+            }
+
+            subscriber.notify_written(&id, &written);
+        }
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Put { append },
+            PlabbleResponseBody::Put(PutResponseBody { slots }),
+        ))
+    }
+
+    /// Handle STREAM request: read a byte range from a single slot,
+    /// or in write mode write the data into the slot (appending it if the byte range has no start) and return its new size
+    fn handle_stream(
+        &self,
+        base: PlabblePacketBase,
+        id: BucketId,
+        subscribe: bool,
+        range_mode_until: bool,
+        write_mode: bool,
+        body: StreamRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let store = self.bucket_store()?;
+        let operation = if write_mode {
+            BucketOperation::Write
+        } else {
+            BucketOperation::Read
+        };
+
+        self.authorize(store.as_ref(), &id, operation)?;
+        if subscribe && write_mode {
+            self.authorize(store.as_ref(), &id, BucketOperation::Read)?;
+        }
+
+        let range = body.range.with_range_mode(range_mode_until);
+        let slot = range.slot();
+
+        let response = if write_mode {
+            let data = body.data.ok_or(PlabbleError::InvalidRequest)?;
+            let new_size = store.write_bytes(&id, &range, &data)?;
+
+            // Subscribers only receive the written bytes, at the offset they were written at
+            if let Some(subscriber) = self.subscriber() {
+                let offset = range.bounds().0.unwrap_or(new_size - data.len() as u64);
+                subscriber.notify_streamed(&id, &range.body(data), offset);
+            }
+
+            StreamResponseBody {
+                new_size: Some(new_size),
+                data: None,
+            }
+        } else {
+            let value = match store.read(&id, &slot, None)? {
+                BucketBody::Numeric(slots) => slots.into_values().next(),
+                BucketBody::Binary(slots) => slots.into_values().next(),
+            }
+            .ok_or(PlabbleError::SlotNotFound)?;
+
+            StreamResponseBody {
+                new_size: None,
+                data: Some(range.select(&value).to_vec()),
+            }
+        };
+
+        if subscribe && let Some(subscriber) = self.subscriber() {
+            subscriber.subscribe(&id, slot, base.use_encryption, self.subscriber_access());
+        }
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Stream { write_mode },
+            PlabbleResponseBody::Stream(response),
+        ))
+    }
+
+    /// Handle POST request: create a new bucket and store the bucket key that is derived from the current session.
+    /// The client derives the same bucket key, so it is never sent over the wire.
+    fn handle_post(
+        &self,
+        base: PlabblePacketBase,
+        subscribe: bool,
+        range_mode_until: bool,
+        do_not_persist: bool,
+        body: PostRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let store = self.bucket_store()?;
+        let context = self.config.data.as_ref().unwrap();
+        body.settings.validate()?;
+
+        // A bucket can only be created within a session, because the bucket key is derived from the session key
+        let key = context
+            .create_bucket_key(context.use_blake3(), &body.id.data)
+            .ok_or(PlabbleError::InvalidRequest)?;
+
+        let range = match (subscribe, body.range) {
+            (true, Some(range)) => Some(range.with_range_mode(range_mode_until)),
+            (true, None) => return Err(PlabbleError::InvalidRequest.into()),
+            (false, _) => None,
+        };
+
+        store.create_bucket(&body.id, body.settings, key, !do_not_persist)?;
+
+        if let (Some(subscriber), Some(range)) = (self.subscriber(), range) {
+            subscriber.subscribe(
+                &body.id,
+                range,
+                base.use_encryption,
+                self.subscriber_access(),
+            );
+        }
+
+        Ok(self.create_response(base, ResponsePacketType::Post, PlabbleResponseBody::Post))
+    }
+
+    /// Handle PATCH request: update the permissions and/or the ACL of a bucket.
+    /// The locks in the current permissions are checked before anything is changed, so either all changes are applied or none.
+    /// A lock can not be lifted once set, and the patched settings must be valid (like when creating a bucket).
+    /// Subscriptions of subscribers that may no longer read the bucket are removed.
+    fn handle_patch(
+        &self,
+        base: PlabblePacketBase,
+        id: BucketId,
+        body: PatchRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let store = self.bucket_store()?;
+        self.authorize(store.as_ref(), &id, BucketOperation::UpdateSettings)?;
+
+        // The settings are checked and changed atomically, so concurrent changes are not lost
+        let mut patched = None;
+        store.update_settings_with(&id, &mut |settings| {
+            if body.permissions.is_some() && settings.permissions.lock_permissions {
+                return Err(PlabbleError::PermissionsLocked.into());
+            }
+
+            // Once the ACL is locked, it can not be unlocked again
+            let unlocks_acl = body
+                .permissions
+                .as_ref()
+                .is_some_and(|permissions| !permissions.lock_acl);
+            if (body.acl_add.is_some() || body.acl_del.is_some() || unlocks_acl)
+                && settings.permissions.lock_acl
+            {
+                return Err(PlabbleError::AclLocked.into());
+            }
+
+            for user in body.acl_add.iter().flatten() {
+                if !settings.access_control_list.contains(user) {
+                    settings.access_control_list.push(*user);
+                }
+            }
+
+            if let Some(acl_del) = &body.acl_del {
+                settings
+                    .access_control_list
+                    .retain(|user| !acl_del.contains(user));
+            }
+
+            if let Some(permissions) = &body.permissions {
+                settings.permissions = permissions.clone();
+            }
+
+            settings.validate()?;
+            patched = Some(settings.clone());
+            Ok(())
+        })?;
+
+        // Subscribers that may no longer read the bucket stop receiving updates
+        if let (Some(subscriber), Some(settings)) = (self.subscriber(), patched) {
+            subscriber.settings_changed(&id, &settings);
+        }
+
+        Ok(self.create_response(base, ResponsePacketType::Patch, PlabbleResponseBody::Patch))
+    }
+
+    /// Handle DELETE request: delete the slots within the queried range from the bucket (at most `limit` slots).
+    /// An unbounded range without a limit deletes the entire bucket, if the bucket permissions allow that.
+    /// The subscribers are notified of all deleted slots, but only the slots of the range variant can be returned.
+    /// Deleting the entire bucket removes all subscriptions on it.
+    fn handle_delete(
+        &self,
+        base: PlabblePacketBase,
+        id: BucketId,
+        binary_keys: bool,
+        range_mode_until: bool,
+        return_deleted: bool,
+        query: BucketQuery,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let store = self.bucket_store()?;
+        let range = query.range.with_range_mode(range_mode_until);
+
+        let delete_bucket = range.is_unbounded() && query.limit.is_none();
+        let (deleted, other) = if delete_bucket {
+            self.authorize(store.as_ref(), &id, BucketOperation::BucketDelete)?;
+
+            let (numeric, binary) = store.delete_bucket(&id)?;
+            match range {
+                BucketRange::Numeric(..) => (numeric, Some(binary)),
+                BucketRange::Binary(..) => (binary, Some(numeric)),
+            }
+        } else {
+            self.authorize(store.as_ref(), &id, BucketOperation::Delete)?;
+            (store.delete(&id, &range, query.limit)?, None)
+        };
+
+        if let Some(subscriber) = self.subscriber() {
+            for slots in std::iter::once(&deleted).chain(&other) {
+                if !slots.is_empty() {
+                    subscriber.notify_deleted(&id, slots);
+                }
+            }
+
+            // The subscriptions end with the bucket, so they do not carry over to a new bucket with the same ID
+            if delete_bucket {
+                subscriber.bucket_deleted(&id);
+            }
+        }
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Delete {
+                return_deleted,
+                binary_keys,
+            },
+            PlabbleResponseBody::Delete(return_deleted.then_some(deleted)),
+        ))
+    }
+
+    /// Handle SUBSCRIBE request: subscribe to changes of the slots within the range of a bucket,
+    /// or unsubscribe from exactly that range
+    fn handle_subscribe(
+        &self,
+        base: PlabblePacketBase,
+        id: BucketId,
+        range_mode_until: bool,
+        unsubscribe: bool,
+        query: BucketQuery,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let subscriber = self.subscriber().ok_or(PlabbleError::InternalServerError)?;
+        let range = query.range.with_range_mode(range_mode_until);
+
+        if unsubscribe {
+            subscriber.unsubscribe(&id, &range);
+        } else {
+            let store = self.bucket_store()?;
+            self.authorize(store.as_ref(), &id, BucketOperation::Read)?;
+            subscriber.subscribe(&id, range, base.use_encryption, self.subscriber_access());
+        }
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Subscribe {
+                binary_keys: false,
+                update: false,
+                deleted: false,
+                stream: false,
+            },
+            PlabbleResponseBody::Subscribe(None),
+        ))
+    }
+
+    /// Handle REGISTER request: validate the keys and claims and issue a certificate for them, signed by the server certificate.
+    /// Registering is only allowed over an encrypted connection.
+    fn handle_register(
+        &self,
+        base: PlabblePacketBase,
+        body: RegisterRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let context = self.config.data.as_ref().unwrap();
+        if !base.use_encryption && !context.full_encryption {
+            return Err(PlabbleError::InvalidRequest.into());
+        }
+
+        // Exactly one key for each signature algorithm in the crypto settings
+        let settings = base.crypto_settings.or(context.crypto_settings);
+        let algorithms = get_signature_algorithms(&settings.unwrap_or_default());
+        let key_algorithms: Vec<_> = body.keys.iter().map(|k| k.get_algorithm()).collect();
+        if algorithms.is_empty()
+            || key_algorithms.len() != algorithms.len()
+            || algorithms
+                .iter()
+                .any(|alg| !key_algorithms.contains(&Some(*alg)))
+        {
+            return Err(PlabbleError::InvalidRequest.into());
+        }
+
+        body.parse_claims()?;
+
+        let provider = context
+            .certificate_provider
+            .clone()
+            .ok_or(PlabbleError::InternalServerError)?;
+
+        provider.validate_claims(&body.claims)?;
+        let server_cert = provider
+            .get_server_certificate()
+            .ok_or(PlabbleError::InternalServerError)?;
+        let server_valid_until = server_cert
+            .body()
+            .ok_or(PlabbleError::InternalServerError)?
+            .valid_until();
+
+        // Whole seconds (like on the wire), so the stored certificate equals the certificate the client receives
+        let options = &context.server_options;
+        let valid_from = PlabbleDateTime::new(PlabbleDateTime::from_now(0).timestamp());
+        let valid_until = PlabbleDateTime::from_now(options.certificate_lifetime);
+        let certificate = Certificate::issue(
+            &server_cert,
+            &options.certificate_uri,
+            valid_from,
+            PlabbleDateTime::new(valid_until.timestamp().min(server_valid_until.timestamp())),
+            body.claims.canonical(),
+            body.keys,
+        )
+        .ok_or(PlabbleError::InternalServerError)?;
+
+        provider.store_certificate(certificate.clone())?;
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Register,
+            PlabbleResponseBody::Register(certificate),
+        ))
+    }
+
+    /// Handle OPCODE request: run the script with the script settings of the server,
+    /// where bucket operations and eval are only allowed if the request allows them too.
+    /// Fails with `PlabbleError::OpcodeScriptError` if the script fails.
+    fn handle_opcode(
+        &self,
+        base: PlabblePacketBase,
+        allow_bucket_operations: bool,
+        allow_eval: bool,
+        body: OpCodeRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let context = self.config.data.as_ref().unwrap();
+        let mut settings = context.server_options.script_settings.clone();
+        settings.allow_bucket_actions &= allow_bucket_operations;
+        settings.allow_eval &= allow_eval;
+        settings.allow_sandboxed_eval &= allow_eval;
+
+        let mut interpreter = ScriptInterpreter::new(body.script, Some(settings));
+        if allow_bucket_operations && let Some(store) = context.bucket_store.clone() {
+            let provider =
+                ServerBucketProvider::new(store, context.identity, self.subscriber().cloned());
+            interpreter = interpreter.with_bucket_provider(Arc::new(provider));
+        }
+
+        let result = interpreter
+            .exec()
+            .map_err(PlabbleError::OpcodeScriptError)?;
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Opcode,
+            PlabbleResponseBody::Opcode(OpCodeResponseBody { result }),
+        ))
+    }
+
+    /// Handle CUSTOM request: dispatch the body to the handler of its sub-protocol, responding with the same flags.
+    /// Fails with `PlabbleError::UnsupportedSubProtocol` if the server has no handler for the sub-protocol.
+    fn handle_custom(
+        &self,
+        base: PlabblePacketBase,
+        flags: CustomFlags,
+        body: CustomBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let registry = self
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .sub_protocols
+            .clone()
+            .ok_or(PlabbleError::UnsupportedSubProtocol)?;
+
+        let body = registry.dispatch(flags, body)?;
+        Ok(self.create_response(
+            base,
+            flags.response_type(),
+            PlabbleResponseBody::Custom(body),
+        ))
+    }
+
+    /// Handle PROXY request that initializes a tunnel at this hop: perform the key exchange with the client and register the tunnel.
+    ///
+    /// The request contains one key for each key exchange algorithm in the crypto settings, which the hop answers with its own keys,
+    /// signed by its server certificate. If this is the last hop (`hop_count` is 1) the tunnel is connected to the target,
+    /// otherwise the client extends the tunnel to the next hop with a frame (see [`Self::extend_tunnel`]).
+    /// The hop reports itself under the first address in `via`, or under the address in the server options if the hops are selected randomly.
+    async fn handle_proxy_initialize(
+        &self,
+        base: PlabblePacketBase,
+        select_random_hops: bool,
+        body: ProxyRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let ProxyRequestBody::Initialize {
+            target,
+            hop_count,
+            via,
+            keys,
+        } = body
+        else {
+            return Err(PlabbleProtocolError::UnexpectedRequest);
+        };
+
+        let context = self.config.data.as_ref().unwrap();
+        let settings = base
+            .crypto_settings
+            .or(context.crypto_settings)
+            .unwrap_or_default();
+        let algorithms = get_key_exchange_algorithms(&settings);
+        let mut via = via.unwrap_or_default();
+        if hop_count == 0
+            || algorithms.is_empty()
+            || keys.len() != algorithms.len()
+            || (!select_random_hops && via.len() != hop_count as usize)
+        {
+            return Err(PlabbleError::InvalidRequest.into());
+        }
+
+        let tunnels = context
+            .tunnels
+            .clone()
+            .ok_or(PlabbleError::InternalServerError)?;
+        let relay = context
+            .proxy_relay
+            .clone()
+            .ok_or(PlabbleError::HopUnreachable)?;
+        let server_cert = context
+            .certificate_provider
+            .as_ref()
+            .and_then(|provider| provider.get_server_certificate())
+            .ok_or(PlabbleError::InternalServerError)?;
+
+        let (shared_secrets, response_keys): (Vec<_>, Vec<_>) = algorithms
+            .into_iter()
+            .zip(&keys)
+            .map(|(alg, key)| KeyExchange::new(alg).process_request(key))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(PlabbleError::InvalidRequest)?
+            .into_iter()
+            .unzip();
+
+        let data = HopInfo::signature_data(&keys, &response_keys);
+        let hop = HopInfo {
+            signatures: sign_with_certificate(
+                &server_cert,
+                &get_signature_algorithms(&settings),
+                &data,
+            )?,
+            keys: response_keys,
+        };
+
+        let address = if select_random_hops {
+            context.server_options.address.clone()
+        } else {
+            via.remove(0)
+        };
+        let next_hop = via.into_iter().next().filter(|_| hop_count > 1);
+
+        let link = if hop_count == 1 {
+            Some(relay.connect(&target).await?)
+        } else {
+            None
+        };
+
+        let tunnel_id = tunnels.open(Tunnel {
+            layer: TunnelLayer::new(settings, shared_secrets),
+            link,
+            next_tunnel_id: None,
+            target,
+            remaining_hops: hop_count - 1,
+            next_hop,
+        })?;
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Proxy { init_session: true },
+            PlabbleResponseBody::Proxy(ProxyResponseBody::Initialize {
+                tunnel_id,
+                hops: HashMap::from([(address, hop)]),
+            }),
+        ))
+    }
+
+    /// Handle PROXY request that sends a frame through a tunnel: remove the onion layer of this hop, relay the frame
+    /// to the next hop (or target) and add the layer to the response.
+    ///
+    /// Frames that fail authentication are rejected without affecting the tunnel, because anyone who knows the tunnel ID can send them.
+    /// The tunnel is closed if `keep_connection` is not set or if relaying fails, because a failed frame leaves the layers of the route out of sync.
+    async fn handle_proxy_tunnel(
+        &self,
+        base: PlabblePacketBase,
+        keep_connection: bool,
+        tunnel_id: u32,
+        packet: Vec<u8>,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let tunnels = self
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .tunnels
+            .clone()
+            .ok_or(PlabbleError::InternalServerError)?;
+        let mut tunnel = tunnels.take(tunnel_id)?;
+
+        let Some(inner) = tunnel.layer.open(true, &packet) else {
+            tunnels.restore(tunnel_id, tunnel);
+            return Err(PlabbleError::InvalidRequest.into());
+        };
+
+        let packet = self.relay_frame(&mut tunnel, keep_connection, inner).await;
+        match packet {
+            Ok(_) if keep_connection => {
+                tunnel.layer.next_frame();
+                tunnels.restore(tunnel_id, tunnel);
+            }
+            _ => {
+                tunnels.close(tunnel_id);
+            }
+        }
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Proxy {
+                init_session: false,
+            },
+            PlabbleResponseBody::Proxy(ProxyResponseBody::Tunnel {
+                tunnel_id,
+                packet: packet?,
+            }),
+        ))
+    }
+
+    /// Relay a frame (without the onion layer of this hop) through a tunnel to the next hop or target
+    /// and add the onion layer to the response.
+    ///
+    /// The target does not reply to fire-and-forget packets, so the response to those frames is empty.
+    async fn relay_frame(
+        &self,
+        tunnel: &mut Tunnel,
+        keep_connection: bool,
+        frame: Vec<u8>,
+    ) -> Result<Vec<u8>, PlabbleProtocolError> {
+        let response = match (tunnel.link.as_mut(), tunnel.next_tunnel_id) {
+            (None, _) => self.extend_tunnel(tunnel, frame).await?,
+            (Some(link), None) => {
+                let fire_and_forget =
+                    PlabblePacketBase::from_bytes(&frame, None::<&mut SerializerConfig>)
+                        .is_ok_and(|base| base.fire_and_forget);
+
+                if fire_and_forget {
+                    link.send(frame).await?;
+                    Vec::new()
+                } else {
+                    link.send_and_recv(frame).await?
+                }
+            }
+            (Some(link), Some(tunnel_id)) => {
+                let req = PlabbleRequestPacket {
+                    base: PlabblePacketBase::default(),
+                    header: PlabbleRequestHeader::new(
+                        RequestPacketType::Proxy {
+                            init_session: false,
+                            keep_connection,
+                            select_random_hops: false,
+                        },
+                        None,
+                    ),
+                    body: PlabbleRequestBody::Proxy(ProxyRequestBody::Tunnel {
+                        tunnel_id,
+                        packet: frame,
+                    }),
+                };
+
+                match relay_request(link.as_mut(), req.to_bytes(None)?).await? {
+                    (ProxyResponseBody::Tunnel { packet, .. }, _) => packet,
+                    _ => return Err(PlabbleError::HopUnreachable.into()),
+                }
+            }
+        };
+
+        Ok(tunnel
+            .layer
+            .seal(false, &response)
+            .ok_or(PlabbleError::InternalServerError)?)
+    }
+
+    /// Extend a tunnel to the next hop. The frame must contain the (session-less) PROXY request that initializes the tunnel at the next hop,
+    /// with the keys of the client for that hop. The request is sent to the next hop as it is and its raw response is the response to the frame.
+    ///
+    /// Every hop gets its own keys this way, sealed by the onion layers of the hops before it.
+    /// With hops given by the client, the request must be for the next hop this hop was given (the first address in `via`).
+    /// With random hops, it must continue the route (same target and one hop less).
+    async fn extend_tunnel(
+        &self,
+        tunnel: &mut Tunnel,
+        frame: Vec<u8>,
+    ) -> Result<Vec<u8>, PlabbleProtocolError> {
+        let req = PlabbleRequestPacket::from_bytes(&frame, None)
+            .map_err(|_| PlabbleError::InvalidRequest)?;
+        let (
+            RequestPacketType::Proxy {
+                init_session: true,
+                select_random_hops,
+                ..
+            },
+            PlabbleRequestBody::Proxy(ProxyRequestBody::Initialize {
+                target,
+                hop_count,
+                via,
+                ..
+            }),
+        ) = (req.header.packet_type, req.body)
+        else {
+            return Err(PlabbleError::InvalidRequest.into());
+        };
+
+        let context = self.config.data.as_ref().unwrap();
+        let relay = context
+            .proxy_relay
+            .clone()
+            .ok_or(PlabbleError::HopUnreachable)?;
+        let next = if select_random_hops {
+            if tunnel.next_hop.is_some()
+                || target != tunnel.target
+                || hop_count != tunnel.remaining_hops
+            {
+                return Err(PlabbleError::InvalidRequest.into());
+            }
+
+            relay
+                .select_hop(&[&context.server_options.address, &target])
+                .ok_or(PlabbleError::HopUnreachable)?
+        } else {
+            match via.and_then(|via| via.into_iter().next()) {
+                Some(next) if tunnel.next_hop.as_ref() == Some(&next) => next,
+                _ => return Err(PlabbleError::InvalidRequest.into()),
+            }
+        };
+
+        let mut link = relay.connect(&next).await?;
+        let (body, response) = relay_request(link.as_mut(), frame).await?;
+        let ProxyResponseBody::Initialize { tunnel_id, .. } = body else {
+            return Err(PlabbleError::HopUnreachable.into());
+        };
+
+        tunnel.link = Some(link);
+        tunnel.next_tunnel_id = Some(tunnel_id);
+        Ok(response)
+    }
+
+    /// Handle IDENTIFY request: verify the certificate chain and the signatures of the client
+    /// and authenticate the connection as the identity (certificate ID), which gives protected access to buckets with the identity on their ACL.
+    fn handle_identify(
+        &mut self,
+        base: PlabblePacketBase,
+        body: IdentifyRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let context = self.config.data.as_ref().unwrap();
+        let session_key = context.session_key.ok_or(PlabbleError::InvalidRequest)?;
+        let provider = context
+            .certificate_provider
+            .clone()
+            .ok_or(PlabbleError::InternalServerError)?;
+        let server_cert = provider
+            .get_server_certificate()
+            .ok_or(PlabbleError::InternalServerError)?;
+
+        // Timestamps outside the window are rejected to prevent replay attacks
+        let now = PlabbleDateTime::from_now(0);
+        if now.timestamp().abs_diff(body.timestamp.timestamp())
+            > context.server_options.identify_window
+        {
+            return Err(PlabbleError::IdentificationFailed.into());
+        }
+
+        let identity = verify_chain(provider.as_ref(), &body.certificates, &now)?;
+        let keys = identity.body().map(|b| b.keys()).unwrap_or_default();
+        let data =
+            IdentifyRequestBody::signature_data(&body.timestamp, server_cert.id(), &session_key);
+
+        // Exactly one signature for each signature algorithm in the crypto settings (in the same order),
+        // verified with the key of the identity for that algorithm
+        let settings = base.crypto_settings.or(context.crypto_settings);
+        let algorithms = get_signature_algorithms(&settings.unwrap_or_default());
+        let valid = !algorithms.is_empty()
+            && body.signatures.len() == algorithms.len()
+            && algorithms
+                .iter()
+                .zip(&body.signatures)
+                .all(|(alg, signature)| {
+                    keys.iter()
+                        .find(|key| key.get_algorithm() == Some(*alg))
+                        .is_some_and(|key| key.verify(&data, signature) == Some(true))
+                });
+        if !valid {
+            return Err(PlabbleError::IdentificationFailed.into());
         }
+
+        self.config.data.as_mut().unwrap().identity = Some(*identity.id());
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Identify,
+            PlabbleResponseBody::Identity,
+        ))
+    }
+
+    /// Get the subscriber handle of this connection, if the server supports subscriptions
+    fn subscriber(&self) -> Option<&Subscriber> {
+        self.config.data.as_ref().unwrap().subscriber.as_ref()
+    }
+
+    /// Access of the requester of the current request, with which its subscriptions are re-authorized when the bucket settings change
+    fn subscriber_access(&self) -> SubscriberAccess {
+        let context = self.config.data.as_ref().unwrap();
+        SubscriberAccess {
+            identity: context.identity,
+            bucket_key_authenticated: context.bucket_key_authenticated,
+        }
+    }
+
+    /// Handle CERTIFICATE request: look up the requested certificate (or the server certificate), build the chain
+    /// and sign the challenge and certificates with every signature algorithm in the crypto settings
+    fn handle_certificate(
+        &self,
+        base: PlabblePacketBase,
+        full_chain: bool,
+        full_certs: bool,
+        body: CertificateRequestBody,
+    ) -> Result<PlabbleResponsePacket, PlabbleProtocolError> {
+        let provider = self
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .certificate_provider
+            .clone()
+            .ok_or(PlabbleError::InternalServerError)?;
+
+        let server_cert = provider
+            .get_server_certificate()
+            .ok_or(PlabbleError::InternalServerError)?;
+
+        let requested = match &body.id {
+            Some(id) => provider
+                .get_certificate(id)
+                .ok_or(PlabbleError::CertificateNotFound)?,
+            None => server_cert.clone(),
+        };
+
+        if !requested.is_valid_at(&PlabbleDateTime::from_now(0)) {
+            return Err(PlabbleError::CertificateInvalid.into());
+        }
+
+        let mut certificates = vec![requested.without_secret_keys()];
+
+        // Walk up the chain using the issuer URIs, until the root certificate is reached or the issuer is unknown
+        if full_chain {
+            let mut current = requested;
+            while !current.is_root()
+                && let Some(issuer) = current
+                    .body()
+                    .and_then(|b| b.issuer_uri())
+                    .and_then(|uri| provider.get_certificate_by_uri(uri))
+            {
+                // Prevent endless loops on malformed (circular) chains
+                if certificates.iter().any(|c| c.id() == issuer.id()) {
+                    break;
+                }
+
+                certificates.push(if full_certs {
+                    issuer.without_secret_keys()
+                } else {
+                    issuer.to_compact()
+                });
+                current = issuer;
+            }
+        }
+
+        let settings = base.crypto_settings.unwrap_or_default();
+        let data = CertificateResponseBody::signature_data(
+            body.challenge.as_ref(),
+            &certificates,
+            &settings,
+        )
+        .map_err(SerializationError::from)?;
+
+        let signatures =
+            sign_with_certificate(&server_cert, &get_signature_algorithms(&settings), &data)?;
+
+        Ok(self.create_response(
+            base,
+            ResponsePacketType::Certificate,
+            PlabbleResponseBody::Certificate(CertificateResponseBody {
+                signatures,
+                certificates,
+            }),
+        ))
+    }
+}
+
+/// Create a range for every single slot in the body
+fn slot_ranges(body: &BucketBody) -> Vec<BucketRange> {
+    match body {
+        BucketBody::Numeric(slots) => slots
+            .keys()
+            .map(|slot| BucketRange::Numeric(Some(*slot), Some(*slot)))
+            .collect(),
+        BucketBody::Binary(slots) => slots
+            .keys()
+            .map(|key| BucketRange::Binary(Some(key.clone()), Some(key.clone())))
+            .collect(),
+    }
+}
+
+/// Resolve and verify the certificate chain of an identity, returning the (full) certificate of the identity.
+///
+/// Compact certificates in the chain are looked up with the certificate provider, full certificates must have an ID that matches their content.
+/// Only the server certificate and root certificates known by the provider are trust anchors: the identity must be one of them,
+/// or be valid and issued by one (the next certificate in the chain, or its issuer that is known by the provider).
+/// Certificates registered by users are never accepted as issuers, so they can not be used to mint new identities.
+fn verify_chain(
+    provider: &dyn CertificateProvider,
+    chain: &[Certificate],
+    now: &PlabbleDateTime,
+) -> Result<Certificate, PlabbleProtocolError> {
+    // A full certificate with an ID that does not match its content could impersonate a known certificate
+    let resolve = |certificate: &Certificate, not_found: PlabbleError| match certificate.body() {
+        Some(_) if certificate.has_valid_id() => Ok(certificate.clone()),
+        Some(_) => Err(PlabbleError::IdentificationFailed),
+        None => provider.get_certificate(certificate.id()).ok_or(not_found),
+    };
+
+    let identity = resolve(
+        chain.first().ok_or(PlabbleError::InvalidRequest)?,
+        PlabbleError::CertificateNotFound,
+    )?;
+
+    if !identity.is_valid_at(now) {
+        return Err(PlabbleError::CertificateInvalid.into());
+    }
+
+    if is_trust_anchor(provider, &identity) {
+        return Ok(identity);
+    }
+
+    let issuer = match chain.get(1) {
+        Some(issuer) => resolve(issuer, PlabbleError::IdentificationFailed)?,
+        None => {
+            let uri = identity
+                .body()
+                .and_then(|b| b.issuer_uri())
+                .ok_or(PlabbleError::IdentificationFailed)?;
+            provider
+                .get_server_certificate()
+                .filter(|server| server.uri() == uri)
+                .or_else(|| provider.get_certificate_by_uri(uri))
+                .ok_or(PlabbleError::IdentificationFailed)?
+        }
+    };
+
+    if !is_trust_anchor(provider, &issuer)
+        || !issuer.is_valid_at(now)
+        || identity.is_root()
+        || !identity.is_issued_by(&issuer)
+    {
+        return Err(PlabbleError::IdentificationFailed.into());
+    }
+
+    Ok(identity)
+}
+
+/// Check if a certificate is a trust anchor: the server certificate, or a root certificate that is known by the provider (with the same keys)
+fn is_trust_anchor(provider: &dyn CertificateProvider, certificate: &Certificate) -> bool {
+    let same_keys = |known: Certificate| {
+        known.id() == certificate.id()
+            && known.body().map(|b| b.keys()) == certificate.body().map(|b| b.keys())
+    };
+
+    provider.get_server_certificate().is_some_and(same_keys)
+        || (certificate.is_root()
+            && provider
+                .get_certificate(certificate.id())
+                .is_some_and(|known| known.is_root() && same_keys(known)))
+}
+
+/// Send a raw (session-less) PROXY request over the link of a tunnel and get the PROXY response body, along with the raw response.
+/// An error response of the next hop is passed on, any other response means the hop is not usable.
+async fn relay_request(
+    link: &mut dyn ProxyLink,
+    packet: Vec<u8>,
+) -> Result<(ProxyResponseBody, Vec<u8>), PlabbleProtocolError> {
+    let bytes = link.send_and_recv(packet).await?;
+    let res = PlabbleResponsePacket::from_bytes(&bytes, None)
+        .map_err(|_| PlabbleError::HopUnreachable)?;
+
+    match res.body {
+        PlabbleResponseBody::Proxy(body) => Ok((body, bytes)),
+        PlabbleResponseBody::Error(error) => Err(error.into()),
+        _ => Err(PlabbleError::HopUnreachable.into()),
+    }
+}
+
+/// Sign data with the secret keys in a certificate, for each of the given algorithms (in order)
+///
+/// Fails with an internal server error if the certificate does not contain a key for one of the algorithms.
+fn sign_with_certificate(
+    certificate: &Certificate,
+    algorithms: &[SignatureAlgorithm],
+    data: &[u8],
+) -> Result<Vec<CryptoSignature>, PlabbleProtocolError> {
+    algorithms
+        .iter()
+        .map(|alg| {
+            certificate
+                .get_signing_key(*alg)
+                .and_then(|key| key.sign(data))
+                .ok_or(PlabbleError::InternalServerError.into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+    use binary_codec::{BinaryDeserializer, BinarySerializer, SerializerConfig};
+    use futures::executor::block_on;
+
+    use crate::{
+        core::{BucketId, Claims, PlabbleDateTime},
+        crypto::{
+            KeyExchange, KeyExchangeAlgorithm, SignatureAlgorithm,
+            algorithm::{KeyExhangeRequest, SigningKey, VerificationKey},
+            certificate::Certificate,
+        },
+        packets::{
+            base::{PlabblePacketBase, settings::CryptoSettings},
+            body::{
+                bucket::{BucketBody, BucketRange, PutResponseBody, SubscriptionUpdate},
+                certificate::CertificateResponseBody,
+                custom::CustomBody,
+                error::PlabbleError,
+                identify::IdentifyRequestBody,
+                opcode::{OpCodeRequestBody, OpCodeResponseBody},
+                post::BucketSettings,
+                proxy::{HopInfo, ProxyRequestBody, ProxyResponseBody},
+                register::RegisterRequestBody,
+                request_body::PlabbleRequestBody,
+                response_body::PlabbleResponseBody,
+                stream::StreamResponseBody,
+            },
+            header::{
+                request_header::PlabbleRequestHeader,
+                type_and_flags::{RequestPacketType, ResponsePacketType},
+            },
+            request::PlabbleRequestPacket,
+            response::PlabbleResponsePacket,
+        },
+        protocol::{
+            PlabbleConnection,
+            custom::{CustomFlags, SubProtocolRegistry},
+            error::PlabbleProtocolError,
+            proxy::TunnelLayer,
+            server::{
+                proxy::TunnelRegistry,
+                subscriptions::{SubscriberAccess, SubscriptionRegistry},
+            },
+        },
+        providers::{
+            BucketStore, CertificateProvider, ProviderFuture, ProxyLink, ProxyRelay,
+            memory::MemoryBucketStore,
+        },
+        scripting::opcode_script::{Opcode, OpcodeScript, ScriptError},
+    };
+
+    struct ExampleCertificateProvider {
+        server: Certificate,
+        root: Certificate,
+        registered: Mutex<Vec<Certificate>>,
+    }
+
+    impl ExampleCertificateProvider {
+        fn all(&self) -> Vec<Certificate> {
+            let mut certificates = vec![self.server.clone(), self.root.clone()];
+            certificates.extend(self.registered.lock().unwrap().iter().cloned());
+            certificates
+        }
+    }
+
+    impl CertificateProvider for ExampleCertificateProvider {
+        fn get_server_certificate(&self) -> Option<Certificate> {
+            Some(self.server.clone())
+        }
+
+        fn get_certificate(&self, id: &[u8; 16]) -> Option<Certificate> {
+            self.all().into_iter().find(|c| c.id() == id)
+        }
+
+        fn get_certificate_by_uri(&self, uri: &str) -> Option<Certificate> {
+            self.all().into_iter().find(|c| c.uri() == uri)
+        }
+
+        fn store_certificate(&self, certificate: Certificate) -> Result<(), PlabbleProtocolError> {
+            let mut registered = self.registered.lock().unwrap();
+            if registered
+                .iter()
+                .any(|c| c.body().map(|b| b.data()) == certificate.body().map(|b| b.data()))
+            {
+                return Err(PlabbleError::ClaimsAlreadyRegistered.into());
+            }
+
+            registered.push(certificate);
+            Ok(())
+        }
+
+        fn validate_claims(&self, claims: &Claims) -> Result<(), PlabbleError> {
+            claims.get("USERNAME").ok_or(PlabbleError::InvalidClaims)?;
+            claims.validate(|key, value| key != "AGE" || value.parse::<u8>().is_ok())
+        }
+    }
+
+    fn create_connection() -> PlabbleConnection {
+        let (tx, _) = async_channel::unbounded();
+        let (_, rx) = async_channel::unbounded();
+        let mut connection = PlabbleConnection::new(tx, rx);
+
+        // This is NOT a valid certificate chain - the signatures do not match the content - just for testing
+        let server: Certificate = toml::from_str(
+            r#"
+            id = "AQEBAQEBAQEBAQEBAQEBAQ"
+            uri = "plabble:localhost/server.crt"
+            valid_from = "2025-05-15T12:30:00+00:00"
+            valid_until = "2161-02-07T06:28:15+00:00"
+            issuer_uri = "plabble:localhost/root.crt"
+            data = "CN=localhost"
+            with_secret_keys = true
+
+            [[keys]]
+            Ed25519 = "PXCMlNZIKU-TI8BWCB8QsNSJp0bLdB1jDzhlSiLXxas"
+
+            [[signatures]]
+            Ed25519 = "GLr1ep-8O70YihvouWdnsFBAPP6poaAVC3TFyz1Lu60MjK_n5D29lYDWmUWit4JaSiN8SpNSpBNmdlFMu8gODQ"
+
+            [[secret_keys]]
+            Ed25519 = "r91Qx-o5PetYDFuO1T6NPW2Q4w1yL13fKmIj2vRQU9g"
+        "#,
+        )
+        .unwrap();
+
+        let root: Certificate = toml::from_str(
+            r#"
+            root_cert = true
+            id = "AgICAgICAgICAgICAgICAg"
+            uri = "plabble:localhost/root.crt"
+            valid_from = "2025-05-15T12:30:00+00:00"
+            valid_until = "2161-02-07T06:28:15+00:00"
+            data = "CA=localhost"
+
+            [[keys]]
+            Ed25519 = "AwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwM"
+
+            [[signatures]]
+            Ed25519 = "BAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBA"
+        "#,
+        )
+        .unwrap();
+
+        let context = connection.config.data.as_mut().unwrap();
+        context.certificate_provider = Some(Arc::new(ExampleCertificateProvider {
+            server,
+            root,
+            registered: Mutex::default(),
+        }));
+        context.bucket_store = Some(Arc::new(create_store()));
+        context.client_counter = 1;
+
+        // Requests are authenticated with the bucket key (private access), unless a test says otherwise
+        context.bucket_key_authenticated = true;
+        connection
+    }
+
+    /// Bucket store with bucket `@test`, containing numeric slots 1, 5, 7, 9 and binary slots `alias`, `name`
+    fn create_store() -> MemoryBucketStore {
+        let store = MemoryBucketStore::new();
+        let id = BucketId::parse("@test").unwrap();
+        store
+            .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+            .unwrap();
+        store
+            .write(
+                &id,
+                BucketBody::Numeric(
+                    [1, 5, 7, 9]
+                        .into_iter()
+                        .map(|slot| (slot, vec![slot as u8]))
+                        .collect(),
+                ),
+                true,
+            )
+            .unwrap();
+        store
+            .write(
+                &id,
+                BucketBody::Binary(
+                    [("alias", b"henk"), ("name", b"Henk")]
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_vec()))
+                        .collect(),
+                ),
+                true,
+            )
+            .unwrap();
+        store
+    }
+
+    fn numeric(slots: &[u32]) -> BucketBody {
+        BucketBody::Numeric(slots.iter().map(|s| (*s, vec![*s as u8])).collect())
+    }
+
+    #[test]
+    fn can_handle_certificate_request_with_challenge_and_compact_chain() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Certificate"
+            full_chain = true
+            challenge = true
+
+            [body]
+            challenge = "BQUFBQUFBQUFBQUFBQUFBQ"
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(res.header.request_counter, Some(0));
+
+        let PlabbleResponseBody::Certificate(body) = res.body else {
+            panic!("Expected certificate response");
+        };
+
+        assert_eq!(2, body.certificates.len());
+        assert_eq!(&[1u8; 16], body.certificates[0].id());
+        assert!(body.certificates[0].body().is_some());
+        assert_eq!(&[2u8; 16], body.certificates[1].id());
+        assert!(body.certificates[1].body().is_none());
+
+        // Secret keys are never sent to the client
+        assert!(
+            body.certificates[0]
+                .get_signing_key(SignatureAlgorithm::Ed25519)
+                .is_none()
+        );
+
+        let data = CertificateResponseBody::signature_data(
+            Some(&[5u8; 16]),
+            &body.certificates,
+            &CryptoSettings::default(),
+        )
+        .unwrap();
+
+        let key = body.certificates[0].body().unwrap().keys()[0].clone();
+        assert!(matches!(key, VerificationKey::Ed25519(_)));
+        assert_eq!(1, body.signatures.len());
+        assert_eq!(Some(true), key.verify(&data, &body.signatures[0]));
+    }
+
+    #[test]
+    fn can_handle_certificate_request_by_id_without_chain() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Certificate"
+            query_mode = true
+
+            [body]
+            id = "AgICAgICAgICAgICAgICAg"
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        let PlabbleResponseBody::Certificate(body) = res.body else {
+            panic!("Expected certificate response");
+        };
+
+        assert_eq!(1, body.certificates.len());
+        assert!(body.certificates[0].is_root());
+    }
+
+    #[test]
+    fn returns_error_if_certificate_not_found() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Certificate"
+            query_mode = true
+
+            [body]
+            id = "AAAAAAAAAAAAAAAAAAAAAA"
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::CertificateNotFound),
+            res.body
+        );
+    }
+
+    #[test]
+    fn can_handle_get_request_with_range_mode_until_and_limit() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Get"
+            id = "@test"
+            range_mode_until = true
+            with_limit = true
+
+            [body]
+            limit = 2
+            range.Numeric = [7]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            res.header.packet_type,
+            ResponsePacketType::Get { binary_keys: false }
+        );
+        assert_eq!(PlabbleResponseBody::Get(numeric(&[1, 5])), res.body);
+    }
+
+    #[test]
+    fn can_handle_get_request_with_binary_keys() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Get"
+            id = "@test"
+            binary_keys = true
+
+            [body]
+            range.Binary = ["b"]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            res.header.packet_type,
+            ResponsePacketType::Get { binary_keys: true }
+        );
+        assert_eq!(
+            PlabbleResponseBody::Get(BucketBody::Binary(
+                [("name".to_string(), b"Henk".to_vec())].into()
+            )),
+            res.body
+        );
+    }
+
+    #[test]
+    fn returns_error_if_bucket_not_found() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Get"
+            id = "@missing"
+
+            [body]
+            range.Numeric = []
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::BucketNotFound),
+            res.body
+        );
+    }
+
+    #[test]
+    fn can_handle_put_request_in_append_mode() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            id = "@test"
+            append = true
+
+            [body]
+            body.Numeric = { 2 = "Cw", 1 = "Cg" }
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            res.header.packet_type,
+            ResponsePacketType::Put { append: true }
+        );
+        assert_eq!(
+            PlabbleResponseBody::Put(PutResponseBody {
+                slots: Some(vec![10, 11])
+            }),
+            res.body
+        );
+
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone();
+        let body = store
+            .unwrap()
+            .read(
+                &BucketId::parse("@test").unwrap(),
+                &BucketRange::Numeric(Some(10), None),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            BucketBody::Numeric([(10, vec![10]), (11, vec![11])].into()),
+            body
+        );
+    }
+
+    #[test]
+    fn put_request_with_assert_keys_fails_atomically() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            id = "@test"
+            assert_keys = true
+
+            [body]
+            body.Numeric = { 2 = "Ag", 5 = "Ag" }
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::SlotAlreadyExists),
+            res.body
+        );
+
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone();
+        let body = store
+            .unwrap()
+            .read(
+                &BucketId::parse("@test").unwrap(),
+                &BucketRange::Numeric(None, None),
+                None,
+            )
+            .unwrap();
+        assert_eq!(numeric(&[1, 5, 7, 9]), body);
+    }
+
+    #[test]
+    fn put_request_subscribes_and_notifies_other_subscribers() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, _rx) = registry.create_subscriber();
+        connection.config.data.as_mut().unwrap().subscriber = Some(subscriber);
+
+        let (other, other_rx) = registry.create_subscriber();
+        let id = BucketId::parse("@test").unwrap();
+        other.subscribe(
+            &id,
+            BucketRange::Binary(None, None),
+            false,
+            SubscriberAccess::default(),
+        );
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            id = "@test"
+            binary_keys = true
+            subscribe = true
+
+            [body]
+            body.Binary = { name = "SGVuaw" }
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Put(PutResponseBody { slots: None }),
+            res.body
+        );
+        assert_eq!(2, registry.count(&id));
+
+        let update = other_rx.try_recv().unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                id,
+                offset: None,
+                slots: BucketBody::Binary([("name".to_string(), b"Henk".to_vec())].into())
+            })),
+            update.body
+        );
+    }
+
+    #[test]
+    fn subscription_updates_are_encrypted_if_subscribed_over_encryption() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, rx) = registry.create_subscriber();
+        let context = connection.config.data.as_mut().unwrap();
+        context.subscriber = Some(subscriber);
+        context.session_key = Some([1; 64]);
+
+        let mut writer = create_connection();
+        let (writer_subscriber, _writer_rx) = registry.create_subscriber();
+        let context = writer.config.data.as_mut().unwrap();
+        context.subscriber = Some(writer_subscriber);
+        context.bucket_store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone();
+
+        let subscribe: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+            use_encryption = true
+
+            [header]
+            packet_type = "Subscribe"
+            id = "@test"
+
+            [body]
+            range.Binary = []
+        "#,
+        )
+        .unwrap();
+        block_on(connection.handle_request(subscribe)).unwrap();
+
+        let put: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            id = "@test"
+            binary_keys = true
+
+            [body]
+            body.Binary = { name = "SGVuaw" }
+        "#,
+        )
+        .unwrap();
+        block_on(writer.handle_request(put)).unwrap();
+
+        let update = rx.try_recv().unwrap();
+        assert!(update.base.use_encryption);
+
+        // The slot data is not readable on the wire, but the client of the session can decrypt it
+        let bytes = update.to_bytes(Some(&mut connection.config)).unwrap();
+        assert!(!bytes.windows(4).any(|w| w == b"Henk"));
+        assert_eq!(
+            update,
+            PlabbleResponsePacket::from_bytes(&bytes, Some(&mut connection.config)).unwrap()
+        );
+    }
+
+    #[test]
+    fn can_handle_post_request_and_store_bucket_key() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, _rx) = registry.create_subscriber();
+        let context = connection.config.data.as_mut().unwrap();
+        context.subscriber = Some(subscriber);
+        context.session_key = Some([1; 64]);
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Post"
+            subscribe = true
+            do_not_persist = true
+
+            [body]
+            id = "@new"
+            range.Numeric = [1, 10]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(ResponsePacketType::Post, res.header.packet_type);
+        assert_eq!(PlabbleResponseBody::Post, res.body);
+
+        let id = BucketId::parse("@new").unwrap();
+        let context = connection.config.data.as_ref().unwrap();
+        let store = context.bucket_store.clone().unwrap();
+        assert_eq!(
+            context.create_bucket_key(false, &id.data).unwrap(),
+            store.get_bucket_key(&id).unwrap()
+        );
+        assert_eq!(BucketSettings::default(), store.get_settings(&id).unwrap());
+        assert_eq!(1, registry.count(&id));
+    }
+
+    #[test]
+    fn post_request_fails_if_bucket_exists_or_without_session() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Post"
+
+            [body]
+            id = "@test"
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req.clone())).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::InvalidRequest),
+            res.body
+        );
+
+        connection.config.data.as_mut().unwrap().session_key = Some([1; 64]);
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::BucketAlreadyExists),
+            res.body
+        );
+    }
+
+    #[test]
+    fn post_request_fails_with_invalid_settings() {
+        let mut connection = create_connection();
+        connection.config.data.as_mut().unwrap().session_key = Some([1; 64]);
+
+        for settings in [
+            "permissions = { deny_existence = true }",
+            r#"permissions = {}
+            access_control_list = ["AQEBAQEBAQEBAQEBAQEBAQ", "AQEBAQEBAQEBAQEBAQEBAQ"]"#,
+        ] {
+            let req: PlabbleRequestPacket = toml::from_str(&format!(
+                r#"
+                version = 1
+
+                [header]
+                packet_type = "Post"
+
+                [body]
+                id = "@invalid"
+
+                [body.settings]
+                {settings}
+            "#
+            ))
+            .unwrap();
+
+            let res = block_on(connection.handle_request(req)).unwrap();
+            assert_eq!(
+                PlabbleResponseBody::Error(PlabbleError::InvalidRequest),
+                res.body
+            );
+        }
+
+        // Existence can be denied if the bucket is not publicly readable
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Post"
+
+            [body]
+            id = "@invalid"
+            settings.permissions = { public_read = false, deny_existence = true }
+        "#,
+        )
+        .unwrap();
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(PlabbleResponseBody::Post, res.body);
+    }
+
+    #[test]
+    fn can_handle_delete_request_with_limit_and_return_deleted() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, _rx) = registry.create_subscriber();
+        connection.config.data.as_mut().unwrap().subscriber = Some(subscriber);
+
+        let (other, other_rx) = registry.create_subscriber();
+        let id = BucketId::parse("@test").unwrap();
+        other.subscribe(
+            &id,
+            BucketRange::Numeric(Some(5), None),
+            false,
+            SubscriberAccess::default(),
+        );
+
+        // Pop the first two slots, like a queue
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Delete"
+            id = "@test"
+            with_limit = true
+            return_deleted = true
+
+            [body]
+            limit = 2
+            range.Numeric = []
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            ResponsePacketType::Delete {
+                return_deleted: true,
+                binary_keys: false
+            },
+            res.header.packet_type
+        );
+        assert_eq!(
+            PlabbleResponseBody::Delete(Some(numeric(&[1, 5]))),
+            res.body
+        );
+
+        let update = other_rx.try_recv().unwrap();
+        assert_eq!(
+            ResponsePacketType::Subscribe {
+                binary_keys: false,
+                update: true,
+                deleted: true,
+                stream: false
+            },
+            update.header.packet_type
+        );
+        assert_eq!(
+            PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                id: id.clone(),
+                offset: None,
+                slots: numeric(&[5])
+            })),
+            update.body
+        );
+
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+        let body = store
+            .read(&id, &BucketRange::Numeric(None, None), None)
+            .unwrap();
+        assert_eq!(numeric(&[7, 9]), body);
+    }
+
+    #[test]
+    fn can_delete_entire_bucket_only_if_permissions_allow_it() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, _rx) = registry.create_subscriber();
+        connection.config.data.as_mut().unwrap().subscriber = Some(subscriber);
+
+        let (other, other_rx) = registry.create_subscriber();
+        let id = BucketId::parse("@test").unwrap();
+        other.subscribe(
+            &id,
+            BucketRange::Numeric(None, None),
+            false,
+            SubscriberAccess::default(),
+        );
+        other.subscribe(
+            &id,
+            BucketRange::Binary(None, None),
+            false,
+            SubscriberAccess::default(),
+        );
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Delete"
+            id = "@test"
+            return_deleted = true
+
+            [body]
+            range.Numeric = []
+        "#,
+        )
+        .unwrap();
+
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+
+        let mut settings = store.get_settings(&id).unwrap();
+        settings.permissions.private_bucket_delete = false;
+        store.update_settings(&id, settings.clone()).unwrap();
+
+        let res = block_on(connection.handle_request(req.clone())).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::PermissionDenied),
+            res.body
+        );
+
+        settings.permissions.private_bucket_delete = true;
+        store.update_settings(&id, settings).unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Delete(Some(numeric(&[1, 5, 7, 9]))),
+            res.body
+        );
+        assert!(matches!(
+            store.get_settings(&id),
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::BucketNotFound
+            ))
+        ));
+
+        // The subscribers are notified of the deleted binary slots as well
+        let updates: Vec<_> = std::iter::from_fn(|| other_rx.try_recv().ok())
+            .map(|update| update.body)
+            .collect();
+        assert_eq!(2, updates.len());
+        assert!(
+            updates.contains(&PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                id: id.clone(),
+                offset: None,
+                slots: numeric(&[1, 5, 7, 9])
+            })))
+        );
+        assert!(updates.iter().any(|body| matches!(
+            body,
+            PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                slots: BucketBody::Binary(slots),
+                ..
+            })) if slots.len() == 2
+        )));
+
+        // The subscriptions end with the bucket
+        assert_eq!(0, registry.count(&id));
+    }
+
+    #[test]
+    fn patch_request_removes_subscriptions_that_lost_read_access() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, _rx) = registry.create_subscriber();
+        connection.config.data.as_mut().unwrap().subscriber = Some(subscriber.clone());
+
+        let id = BucketId::parse("@test").unwrap();
+        let (anonymous, anonymous_rx) = registry.create_subscriber();
+        let (member, member_rx) = registry.create_subscriber();
+        anonymous.subscribe(
+            &id,
+            BucketRange::Numeric(None, None),
+            false,
+            SubscriberAccess::default(),
+        );
+        member.subscribe(
+            &id,
+            BucketRange::Numeric(None, None),
+            false,
+            SubscriberAccess {
+                identity: Some([2; 16]),
+                bucket_key_authenticated: false,
+            },
+        );
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Patch"
+            id = "@test"
+            update_permissions = true
+            add_to_acl = true
+
+            [body]
+            acl_add = ["AgICAgICAgICAgICAgICAg"]
+
+            [body.permissions]
+            public_read = false
+            protected_read = true
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(PlabbleResponseBody::Patch, res.body);
+        assert_eq!(1, registry.count(&id));
+
+        // Only the subscriber on the ACL still receives updates
+        subscriber.notify_written(&id, &numeric(&[1]));
+        assert!(anonymous_rx.try_recv().is_err());
+        assert!(member_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn can_handle_patch_request_for_acl_and_permissions() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Patch"
+            id = "@test"
+            add_to_acl = true
+
+            [body]
+            acl_add = ["AQEBAQEBAQEBAQEBAQEBAQ", "AgICAgICAgICAgICAgICAg", "AQEBAQEBAQEBAQEBAQEBAQ"]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(ResponsePacketType::Patch, res.header.packet_type);
+        assert_eq!(PlabbleResponseBody::Patch, res.body);
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Patch"
+            id = "@test"
+            update_permissions = true
+            remove_from_acl = true
+
+            [body]
+            acl_del = ["AQEBAQEBAQEBAQEBAQEBAQ", "AwMDAwMDAwMDAwMDAwMDAw"]
+
+            [body.permissions]
+            public_write = true
+            lock_permissions = true
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(PlabbleResponseBody::Patch, res.body);
+
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+        let settings = store
+            .get_settings(&BucketId::parse("@test").unwrap())
+            .unwrap();
+        assert_eq!(vec![[2u8; 16]], settings.access_control_list);
+        assert!(settings.permissions.public_write);
+        assert!(settings.permissions.lock_permissions);
+    }
+
+    #[test]
+    fn patch_request_fails_if_permissions_or_acl_are_locked() {
+        let mut connection = create_connection();
+        let id = BucketId::parse("@test").unwrap();
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+
+        let mut settings = store.get_settings(&id).unwrap();
+        settings.permissions.lock_acl = true;
+        store.update_settings(&id, settings.clone()).unwrap();
+
+        // Nothing is changed if one of the changes is not allowed
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Patch"
+            id = "@test"
+            update_permissions = true
+            add_to_acl = true
+
+            [body]
+            acl_add = ["AQEBAQEBAQEBAQEBAQEBAQ"]
+
+            [body.permissions]
+            public_write = true
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::AclLocked),
+            res.body
+        );
+        assert_eq!(settings, store.get_settings(&id).unwrap());
+
+        // The ACL lock can not be lifted by patching only the permissions
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Patch"
+            id = "@test"
+            update_permissions = true
+
+            [body.permissions]
+            lock_acl = false
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::AclLocked),
+            res.body
+        );
+        assert_eq!(settings, store.get_settings(&id).unwrap());
+
+        // Patched settings are validated like the settings of a new bucket
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Patch"
+            id = "@test"
+            update_permissions = true
+
+            [body.permissions]
+            lock_acl = true
+            public_read = true
+            deny_existence = true
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::InvalidRequest),
+            res.body
+        );
+        assert_eq!(settings, store.get_settings(&id).unwrap());
+
+        settings.permissions.lock_permissions = true;
+        store.update_settings(&id, settings.clone()).unwrap();
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Patch"
+            id = "@test"
+            update_permissions = true
+
+            [body.permissions]
+            lock_permissions = false
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::PermissionsLocked),
+            res.body
+        );
+        assert_eq!(settings, store.get_settings(&id).unwrap());
+    }
+
+    #[test]
+    fn enforces_bucket_permissions_per_access_tier() {
+        let mut connection = create_connection();
+        let id = BucketId::parse("@test").unwrap();
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+
+        let mut settings = store.get_settings(&id).unwrap();
+        settings.access_control_list.push([1; 16]);
+        settings.permissions.protected_write = true;
+        store.update_settings(&id, settings).unwrap();
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            id = "@test"
+
+            [body]
+            body.Numeric = { 2 = "Ag" }
+        "#,
+        )
+        .unwrap();
+
+        // Anonymous users are not allowed to write
+        let context = connection.config.data.as_mut().unwrap();
+        context.bucket_key_authenticated = false;
+        let res = block_on(connection.handle_request(req.clone())).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::PermissionDenied),
+            res.body
+        );
+
+        // Identified users on the ACL are
+        connection.config.data.as_mut().unwrap().identity = Some([1; 16]);
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Put(PutResponseBody { slots: None }),
+            res.body
+        );
+    }
+
+    #[test]
+    fn denies_existence_of_bucket_if_requester_cannot_read() {
+        let mut connection = create_connection();
+        let id = BucketId::parse("@test").unwrap();
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+
+        let mut settings = store.get_settings(&id).unwrap();
+        settings.permissions.public_read = false;
+        settings.permissions.deny_existence = true;
+        store.update_settings(&id, settings).unwrap();
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Get"
+            id = "@test"
+
+            [body]
+            range.Numeric = []
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req.clone())).unwrap();
+        assert_eq!(PlabbleResponseBody::Get(numeric(&[1, 5, 7, 9])), res.body);
+
+        let context = connection.config.data.as_mut().unwrap();
+        context.bucket_key_authenticated = false;
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::BucketNotFound),
+            res.body
+        );
+    }
+
+    #[test]
+    fn can_handle_subscribe_and_unsubscribe_requests() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, rx) = registry.create_subscriber();
+        connection.config.data.as_mut().unwrap().subscriber = Some(subscriber);
+
+        // Another connection on the same server writes to the bucket
+        let mut writer = create_connection();
+        let (writer_subscriber, _writer_rx) = registry.create_subscriber();
+        let context = writer.config.data.as_mut().unwrap();
+        context.subscriber = Some(writer_subscriber);
+        context.bucket_store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone();
+
+        let subscribe = |unsubscribe: bool| -> PlabbleRequestPacket {
+            toml::from_str(&format!(
+                r#"
+                version = 1
+
+                [header]
+                packet_type = "Subscribe"
+                id = "@test"
+                range_mode_until = true
+                unsubscribe = {unsubscribe}
+
+                [body]
+                range.Numeric = [5]
+            "#
+            ))
+            .unwrap()
+        };
+
+        let res = block_on(connection.handle_request(subscribe(false))).unwrap();
+        assert_eq!(PlabbleResponseBody::Subscribe(None), res.body);
+        assert_eq!(1, registry.count(&BucketId::parse("@test").unwrap()));
+
+        let put: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            id = "@test"
+
+            [body]
+            body.Numeric = { 2 = "Ag", 6 = "Bg" }
+        "#,
+        )
+        .unwrap();
+
+        block_on(writer.handle_request(put.clone())).unwrap();
+        let update = rx.try_recv().unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                id: BucketId::parse("@test").unwrap(),
+                offset: None,
+                slots: numeric(&[2])
+            })),
+            update.body
+        );
+
+        let res = block_on(connection.handle_request(subscribe(true))).unwrap();
+        assert_eq!(PlabbleResponseBody::Subscribe(None), res.body);
+        assert_eq!(0, registry.count(&BucketId::parse("@test").unwrap()));
+
+        block_on(writer.handle_request(put)).unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn can_handle_stream_request_for_byte_range() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Stream"
+            id = "@test"
+            binary_keys = true
+            range_mode_until = true
+
+            [body]
+            range.Binary = ["name", 1]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            ResponsePacketType::Stream { write_mode: false },
+            res.header.packet_type
+        );
+        assert_eq!(
+            PlabbleResponseBody::Stream(StreamResponseBody {
+                new_size: None,
+                data: Some(b"He".to_vec())
+            }),
+            res.body
+        );
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Stream"
+            id = "@test"
+
+            [body]
+            range.Numeric = [2]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::SlotNotFound),
+            res.body
+        );
+    }
+
+    #[test]
+    fn can_handle_stream_request_in_write_mode() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let mut connection = create_connection();
+        let (subscriber, _rx) = registry.create_subscriber();
+        connection.config.data.as_mut().unwrap().subscriber = Some(subscriber);
+
+        let (other, other_rx) = registry.create_subscriber();
+        let id = BucketId::parse("@test").unwrap();
+        other.subscribe(
+            &id,
+            BucketRange::Binary(None, None),
+            false,
+            SubscriberAccess::default(),
+        );
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Stream"
+            id = "@test"
+            binary_keys = true
+            write_mode = true
+
+            [body]
+            data = "IGRlIFZyaWVz"
+            range.Binary = ["name"]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            ResponsePacketType::Stream { write_mode: true },
+            res.header.packet_type
+        );
+        assert_eq!(
+            PlabbleResponseBody::Stream(StreamResponseBody {
+                new_size: Some(13),
+                data: None
+            }),
+            res.body
+        );
+
+        // The subscriber only receives the appended bytes
+        let update = other_rx.try_recv().unwrap();
+        assert_eq!(
+            ResponsePacketType::Subscribe {
+                binary_keys: true,
+                update: true,
+                deleted: false,
+                stream: true
+            },
+            update.header.packet_type
+        );
+        assert_eq!(
+            PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                id,
+                offset: Some(4),
+                slots: BucketBody::Binary([("name".to_string(), b" de Vries".to_vec())].into())
+            })),
+            update.body
+        );
+
+        // Writing beyond the end of the slot would leave a gap
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Stream"
+            id = "@test"
+            binary_keys = true
+            write_mode = true
+
+            [body]
+            data = "IGRlIFZyaWVz"
+            range.Binary = ["name", 14]
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::InvalidRequest),
+            res.body
+        );
+    }
+
+    /// Create an OPCODE request for the script
+    fn opcode_request(
+        instructions: Vec<Opcode>,
+        allow_bucket_operations: bool,
+        allow_eval: bool,
+    ) -> PlabbleRequestPacket {
+        PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Opcode {
+                    allow_bucket_operations,
+                    allow_eval,
+                },
+                None,
+            ),
+            body: PlabbleRequestBody::Opcode(OpCodeRequestBody {
+                script: OpcodeScript::new(instructions),
+            }),
+        }
+    }
+
+    #[test]
+    fn can_handle_opcode_request() {
+        let mut connection = create_connection();
+        let req = opcode_request(
+            vec![
+                Opcode::PUSHINT(2),
+                Opcode::PUSHINT(3),
+                Opcode::ADD,
+                Opcode::RETURN,
+            ],
+            false,
+            false,
+        );
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(ResponsePacketType::Opcode, res.header.packet_type);
+        assert_eq!(
+            PlabbleResponseBody::Opcode(OpCodeResponseBody {
+                result: Some(vec![5])
+            }),
+            res.body
+        );
+
+        // Eval is only allowed if the server allows it too
+        let req = opcode_request(vec![Opcode::PUSH1(0), Opcode::EVAL], false, true);
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::OpcodeScriptError(
+                ScriptError::EvalNotAllowed
+            )),
+            res.body
+        );
+
+        // Opcodes that are not implemented are rejected instead of executed
+        let req = opcode_request(vec![Opcode::CHECKLOCK], false, false);
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::OpcodeScriptError(
+                ScriptError::OpcodeNotSupported
+            )),
+            res.body
+        );
+    }
+
+    #[test]
+    fn opcode_request_can_use_buckets_with_script_execution_permission() {
+        let mut connection = create_connection();
+        let id = BucketId::parse("@test").unwrap();
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+
+        // Read slot 1 and copy it into slot 3
+        let script = vec![
+            Opcode::PUSHL1 {
+                len: 16,
+                data: id.data.to_vec(),
+            },
+            Opcode::SELECT,
+            Opcode::PUSHINT(1),
+            Opcode::READ,
+            Opcode::DUP,
+            Opcode::PUSHINT(3),
+            Opcode::WRITE,
+            Opcode::RETURN,
+        ];
+
+        // Bucket operations are not allowed by the server by default
+        let res = block_on(connection.handle_request(opcode_request(script.clone(), true, false)))
+            .unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::OpcodeScriptError(
+                ScriptError::BucketActionsNotAllowed
+            )),
+            res.body
+        );
+
+        let context = connection.config.data.as_mut().unwrap();
+        context.server_options.script_settings.allow_bucket_actions = true;
+
+        // Bucket operations must be allowed by the request
+        let res = block_on(connection.handle_request(opcode_request(script.clone(), false, false)))
+            .unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::OpcodeScriptError(
+                ScriptError::BucketActionsNotAllowed
+            )),
+            res.body
+        );
+
+        // Only private script execution is allowed by default, which OPCODE requests never have
+        let res = block_on(connection.handle_request(opcode_request(script.clone(), true, false)))
+            .unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::OpcodeScriptError(
+                ScriptError::BucketConnectionFailed
+            )),
+            res.body
+        );
+
+        // Script execution alone does not allow writing
+        let mut settings = store.get_settings(&id).unwrap();
+        settings.permissions.public_script_execution = true;
+        store.update_settings(&id, settings.clone()).unwrap();
+        let res = block_on(connection.handle_request(opcode_request(script.clone(), true, false)))
+            .unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::OpcodeScriptError(
+                ScriptError::BucketWriteFailed
+            )),
+            res.body
+        );
+
+        settings.permissions.public_write = true;
+        store.update_settings(&id, settings).unwrap();
+        let res = block_on(connection.handle_request(opcode_request(script, true, false))).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Opcode(OpCodeResponseBody {
+                result: Some(vec![1])
+            }),
+            res.body
+        );
+        assert_eq!(
+            BucketBody::Numeric([(3, vec![1])].into()),
+            store
+                .read(&id, &BucketRange::Numeric(Some(3), Some(3)), None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn can_handle_custom_request_with_registered_sub_protocol() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Custom"
+            flag2 = true
+
+            [body]
+            protocol = 42
+            data = "AQID"
+        "#,
+        )
+        .unwrap();
+
+        // Without sub-protocols, every protocol is unsupported
+        let res = block_on(connection.handle_request(req.clone())).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::UnsupportedSubProtocol),
+            res.body
+        );
+
+        let mut registry = SubProtocolRegistry::new();
+        registry.register(42, |flags: CustomFlags, data: Vec<u8>| {
+            Ok(CustomBody {
+                protocol: 42,
+                data: data
+                    .into_iter()
+                    .map(|b| b * 2)
+                    .chain([flags.flag2 as u8])
+                    .collect(),
+            })
+        });
+        connection.config.data.as_mut().unwrap().sub_protocols = Some(Arc::new(registry));
+
+        let res = block_on(connection.handle_request(req.clone())).unwrap();
+        assert_eq!(
+            ResponsePacketType::Custom {
+                flag1: false,
+                flag2: true,
+                flag3: false,
+                flag4: false
+            },
+            res.header.packet_type
+        );
+        assert_eq!(
+            PlabbleResponseBody::Custom(CustomBody {
+                protocol: 42,
+                data: vec![2, 4, 6, 1]
+            }),
+            res.body
+        );
+
+        let mut req = req;
+        if let PlabbleRequestBody::Custom(body) = &mut req.body {
+            body.protocol = 43;
+        }
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::UnsupportedSubProtocol),
+            res.body
+        );
+    }
+
+    #[test]
+    fn can_handle_register_request_and_issue_certificate() {
+        let mut connection = create_connection();
+        let register = r#"
+            version = 1
+            use_encryption = true
+
+            [header]
+            packet_type = "Register"
+
+            [body]
+            claims = "USERNAME=henk;AGE=24"
+
+            [[body.keys]]
+            Ed25519 = "8KIgA6PQbtFvWSCgPBKXx0LCgb2kiV6nyspoLCdr8Jg"
+        "#;
+
+        let req: PlabbleRequestPacket = toml::from_str(register).unwrap();
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(ResponsePacketType::Register, res.header.packet_type);
+
+        let PlabbleResponseBody::Register(certificate) = res.body else {
+            panic!("Expected register response");
+        };
+
+        let body = certificate.body().unwrap();
+        assert_eq!(body.get_id(), *certificate.id());
+        // The claims are ordered canonically
+        assert_eq!("AGE=24;USERNAME=henk", body.data().to_string());
+        assert_eq!(Some("plabble:localhost/server.crt"), body.issuer_uri());
+        assert!(certificate.is_valid_at(&PlabbleDateTime::from_now(0)));
+
+        // Signed by the server certificate
+        let provider = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .certificate_provider
+            .clone()
+            .unwrap();
+        let server = provider.get_server_certificate().unwrap();
+        let server_key = &server.body().unwrap().keys()[0];
+        let key = &body.keys()[0];
+        assert_eq!(
+            Some(true),
+            server_key.verify(&body.signature_data(key), &body.signatures()[0])
+        );
+        assert_eq!(
+            Some(certificate.clone()),
+            provider.get_certificate(certificate.id())
+        );
+
+        // The claims must be unique
+        let req: PlabbleRequestPacket = toml::from_str(register).unwrap();
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::ClaimsAlreadyRegistered),
+            res.body
+        );
+    }
+
+    #[test]
+    fn register_request_fails_without_encryption_or_with_invalid_claims() {
+        let mut connection = create_connection();
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Register"
+
+            [body]
+            claims = "USERNAME=henk"
+
+            [[body.keys]]
+            Ed25519 = "8KIgA6PQbtFvWSCgPBKXx0LCgb2kiV6nyspoLCdr8Jg"
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::InvalidRequest),
+            res.body
+        );
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+            use_encryption = true
+
+            [header]
+            packet_type = "Register"
+
+            [body]
+            claims = "USERNAME=henk;USERNAME=piet"
+
+            [[body.keys]]
+            Ed25519 = "8KIgA6PQbtFvWSCgPBKXx0LCgb2kiV6nyspoLCdr8Jg"
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req.clone())).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::InvalidClaims),
+            res.body
+        );
+
+        // No claims, or claims that are rejected by the certificate provider
+        for claims in ["", "AGE=24", "USERNAME=henk;AGE=old"] {
+            let mut req = req.clone();
+            if let PlabbleRequestBody::Register(body) = &mut req.body {
+                body.claims = claims.parse().unwrap();
+            }
+
+            let res = block_on(connection.handle_request(req)).unwrap();
+            assert_eq!(
+                PlabbleResponseBody::Error(PlabbleError::InvalidClaims),
+                res.body
+            );
+        }
+    }
+
+    /// Register a new identity with the claims and a generated Ed25519 key, returning the issued certificate and its secret key
+    fn register_identity(
+        connection: &mut PlabbleConnection,
+        claims: &str,
+    ) -> (Certificate, SigningKey) {
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519);
+        let req = PlabbleRequestPacket {
+            base: PlabblePacketBase {
+                use_encryption: true,
+                ..Default::default()
+            },
+            header: PlabbleRequestHeader::new(RequestPacketType::Register, None),
+            body: PlabbleRequestBody::Register(RegisterRequestBody {
+                keys: vec![key.verification_key().unwrap()],
+                claims: claims.parse().unwrap(),
+            }),
+        };
+
+        let PlabbleResponseBody::Register(certificate) =
+            block_on(connection.handle_request(req)).unwrap().body
+        else {
+            panic!("Expected register response");
+        };
+
+        (certificate, key)
+    }
+
+    /// Create an IDENTIFY request for the certificate, signed with the key
+    fn identify_request(
+        certificate: Certificate,
+        key: &SigningKey,
+        timestamp: PlabbleDateTime,
+    ) -> PlabbleRequestPacket {
+        let data = IdentifyRequestBody::signature_data(
+            &timestamp, &[1; 16], // ID of the server certificate
+            &[0; 64],
+        );
+
+        PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(RequestPacketType::Identify, None),
+            body: PlabbleRequestBody::Identify(IdentifyRequestBody {
+                timestamp,
+                signatures: vec![key.sign(&data).unwrap()],
+                certificates: vec![certificate],
+            }),
+        }
+    }
+
+    #[test]
+    fn can_identify_with_registered_certificate_for_protected_access() {
+        let mut connection = create_connection();
+        connection.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+        let (certificate, key) = register_identity(&mut connection, "USERNAME=henk");
+
+        let id = BucketId::parse("@test").unwrap();
+        let store = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .bucket_store
+            .clone()
+            .unwrap();
+        let mut settings = store.get_settings(&id).unwrap();
+        settings.access_control_list.push(*certificate.id());
+        settings.permissions.protected_write = true;
+        store.update_settings(&id, settings).unwrap();
+
+        // A compact certificate is enough, because the server knows the registered certificate
+        let req = identify_request(certificate.to_compact(), &key, PlabbleDateTime::from_now(0));
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(ResponsePacketType::Identify, res.header.packet_type);
+        assert_eq!(PlabbleResponseBody::Identity, res.body);
+
+        let context = connection.config.data.as_mut().unwrap();
+        assert_eq!(Some(*certificate.id()), context.identity);
+        context.bucket_key_authenticated = false;
+
+        let req: PlabbleRequestPacket = toml::from_str(
+            r#"
+            version = 1
+
+            [header]
+            packet_type = "Put"
+            id = "@test"
+
+            [body]
+            body.Numeric = { 2 = "Ag" }
+        "#,
+        )
+        .unwrap();
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Put(PutResponseBody { slots: None }),
+            res.body
+        );
+    }
+
+    #[test]
+    fn identify_fails_outside_timestamp_window_or_with_invalid_signature() {
+        let mut connection = create_connection();
+        connection.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+        let (certificate, key) = register_identity(&mut connection, "USERNAME=henk");
+
+        let expired = PlabbleDateTime::new(PlabbleDateTime::from_now(0).timestamp() - 10 * 60);
+        let req = identify_request(certificate.clone(), &key, expired);
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::IdentificationFailed),
+            res.body
+        );
+
+        // Only one signature is allowed for every signature algorithm in the crypto settings
+        let mut req = identify_request(certificate.clone(), &key, PlabbleDateTime::from_now(0));
+        if let PlabbleRequestBody::Identify(body) = &mut req.body {
+            body.signatures.push(body.signatures[0].clone());
+        }
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::IdentificationFailed),
+            res.body
+        );
+
+        // Signed for another session
+        connection.config.data.as_mut().unwrap().session_key = Some([1; 64]);
+        let req = identify_request(certificate, &key, PlabbleDateTime::from_now(0));
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::IdentificationFailed),
+            res.body
+        );
+        assert_eq!(None, connection.config.data.as_ref().unwrap().identity);
+    }
+
+    #[test]
+    fn identify_fails_with_certificate_that_has_a_forged_id() {
+        let mut connection = create_connection();
+        connection.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+        let (victim, _) = register_identity(&mut connection, "USERNAME=henk");
+        let (certificate, key) = register_identity(&mut connection, "USERNAME=piet");
+
+        // A certificate that is validly issued to piet, but claims to have the ID of henk
+        let mut forged = toml::Value::try_from(&certificate).unwrap();
+        forged["id"] = BASE64_URL_SAFE_NO_PAD.encode(victim.id()).into();
+        let forged: Certificate = forged.try_into().unwrap();
+        assert!(!forged.has_valid_id());
+
+        let req = identify_request(forged, &key, PlabbleDateTime::from_now(0));
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Error(PlabbleError::IdentificationFailed),
+            res.body
+        );
+        assert_eq!(None, connection.config.data.as_ref().unwrap().identity);
+
+        // The genuine certificate of piet is accepted
+        let req = identify_request(certificate.clone(), &key, PlabbleDateTime::from_now(0));
+        let res = block_on(connection.handle_request(req)).unwrap();
+        assert_eq!(PlabbleResponseBody::Identity, res.body);
+        assert_eq!(
+            Some(*certificate.id()),
+            connection.config.data.as_ref().unwrap().identity
+        );
+    }
+
+    #[test]
+    fn identify_fails_with_certificate_issued_by_registered_certificate() {
+        let mut connection = create_connection();
+        connection.config.data.as_mut().unwrap().session_key = Some([0; 64]);
+        let (user, user_key) = register_identity(&mut connection, "USERNAME=henk");
+
+        // A registered user mints a new identity with its own certificate
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519);
+        let minted = Certificate::issue(
+            &user.clone().with_secret_keys(vec![user_key]),
+            "plabble://example.com/certs/{id}",
+            PlabbleDateTime::from_now(0),
+            PlabbleDateTime::from_now(60 * 60),
+            "USERNAME=piet".parse().unwrap(),
+            vec![key.verification_key().unwrap()],
+        )
+        .unwrap();
+        assert!(minted.is_issued_by(&user));
+
+        for chain in [
+            vec![minted.clone(), user.clone()],
+            vec![minted.clone(), user.to_compact()],
+            vec![minted],
+        ] {
+            let mut req = identify_request(chain[0].clone(), &key, PlabbleDateTime::from_now(0));
+            if let PlabbleRequestBody::Identify(body) = &mut req.body {
+                body.certificates = chain;
+            }
+
+            let res = block_on(connection.handle_request(req)).unwrap();
+            assert_eq!(
+                PlabbleResponseBody::Error(PlabbleError::IdentificationFailed),
+                res.body
+            );
+            assert_eq!(None, connection.config.data.as_ref().unwrap().identity);
+        }
+    }
+
+    /// Proxy relay that connects to in-memory hops (with the server certificate of [`create_connection`])
+    /// and to the target `target`, which echoes every packet with an `echo:` prefix
+    #[derive(Clone)]
+    struct ExampleProxyRelay {
+        hops: HashMap<String, Arc<TunnelRegistry>>,
+
+        /// Fire-and-forget packets received by the target
+        received: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl ExampleProxyRelay {
+        fn new(hops: &[&str]) -> Self {
+            Self {
+                hops: hops
+                    .iter()
+                    .map(|hop| (hop.to_string(), Arc::new(TunnelRegistry::new())))
+                    .collect(),
+                received: Arc::default(),
+            }
+        }
+
+        /// Create the connection of a client (or previous hop) to a hop
+        fn hop(&self, address: &str) -> PlabbleConnection {
+            let mut connection = create_connection();
+            let context = connection.config.data.as_mut().unwrap();
+            context.tunnels = Some(self.hops[address].clone());
+            context.proxy_relay = Some(Arc::new(self.clone()));
+            context.server_options.address = address.to_string();
+            connection
+        }
+    }
+
+    impl ProxyRelay for ExampleProxyRelay {
+        fn select_hop(&self, exclude: &[&str]) -> Option<String> {
+            self.hops
+                .keys()
+                .find(|hop| !exclude.contains(&hop.as_str()))
+                .cloned()
+        }
+
+        fn connect<'a>(&'a self, address: &'a str) -> ProviderFuture<'a, Box<dyn ProxyLink>> {
+            Box::pin(async move {
+                if address == "target" {
+                    return Ok(Box::new(EchoLink(self.received.clone())) as Box<dyn ProxyLink>);
+                }
+
+                if !self.hops.contains_key(address) {
+                    return Err(PlabbleError::HopUnreachable.into());
+                }
+
+                Ok(Box::new(HopLink(self.hop(address))) as Box<dyn ProxyLink>)
+            })
+        }
+    }
+
+    struct HopLink(PlabbleConnection);
+
+    impl ProxyLink for HopLink {
+        fn send_and_recv(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, Vec<u8>> {
+            Box::pin(async move {
+                // Hops are not in a session with each other, so the packets are (de)serialized without the connection context
+                let req = PlabbleRequestPacket::from_bytes(&packet, None)?;
+                let context = self.0.config.data.as_mut().unwrap();
+                context.increment(true);
+
+                let res = self.0.handle_request(req).await?;
+                self.0.config.data.as_mut().unwrap().increment(false);
+                Ok(res.to_bytes(None)?)
+            })
+        }
+
+        fn send(&mut self, _packet: Vec<u8>) -> ProviderFuture<'_, ()> {
+            Box::pin(async { Err(PlabbleProtocolError::UnexpectedRequest) })
+        }
+    }
+
+    struct EchoLink(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl ProxyLink for EchoLink {
+        fn send_and_recv(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, Vec<u8>> {
+            Box::pin(async move { Ok([b"echo:".as_slice(), &packet].concat()) })
+        }
+
+        fn send(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, ()> {
+            self.0.lock().unwrap().push(packet);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn proxy_request(keep_connection: bool, body: ProxyRequestBody) -> PlabbleRequestPacket {
+        let init_session = matches!(body, ProxyRequestBody::Initialize { .. });
+        PlabbleRequestPacket {
+            base: PlabblePacketBase::default(),
+            header: PlabbleRequestHeader::new(
+                RequestPacketType::Proxy {
+                    init_session,
+                    keep_connection,
+                    select_random_hops: init_session
+                        && matches!(body, ProxyRequestBody::Initialize { via: None, .. }),
+                },
+                None,
+            ),
+            body: PlabbleRequestBody::Proxy(body),
+        }
+    }
+
+    /// Initialize request for the hop at the index of the route, with new keys for that hop
+    fn initialize_request(
+        route: &[&str],
+        index: usize,
+        random_hops: bool,
+    ) -> (KeyExchange, Vec<KeyExhangeRequest>, PlabbleRequestPacket) {
+        let mut kx = KeyExchange::new(KeyExchangeAlgorithm::X25519);
+        let keys = vec![kx.make_request().unwrap()];
+
+        // With hops given by the client, a hop only learns the next hop and only the last hop learns the target
+        let last = index == route.len() - 1;
+        let body = if random_hops {
+            ProxyRequestBody::Initialize {
+                target: "target".to_string(),
+                hop_count: (route.len() - index) as u8,
+                via: None,
+                keys: keys.clone(),
+            }
+        } else {
+            let via: Vec<_> = route[index..]
+                .iter()
+                .take(2)
+                .map(|hop| hop.to_string())
+                .collect();
+            ProxyRequestBody::Initialize {
+                target: if last {
+                    "target".to_string()
+                } else {
+                    String::new()
+                },
+                hop_count: via.len() as u8,
+                via: Some(via),
+                keys: keys.clone(),
+            }
+        };
+        let req = proxy_request(true, body);
+
+        (kx, keys, req)
+    }
+
+    /// Initialize a tunnel and extend it hop by hop, returning the tunnel ID and the onion layers (in route order)
+    /// after verifying the signatures of the hops
+    fn initialize_tunnel(
+        connection: &mut PlabbleConnection,
+        random_hops: bool,
+        route: &[&str],
+    ) -> (u32, Vec<TunnelLayer>) {
+        let server = connection
+            .config
+            .data
+            .as_ref()
+            .unwrap()
+            .certificate_provider
+            .as_ref()
+            .unwrap()
+            .get_server_certificate()
+            .unwrap();
+        let verification_key = &server.body().unwrap().keys()[0];
+
+        let mut tunnel_id = 0;
+        let mut layers = Vec::new();
+        for (index, hop) in route.iter().enumerate() {
+            let (kx, keys, req) = initialize_request(route, index, random_hops);
+            let body = if index == 0 {
+                let res = block_on(connection.handle_request(req)).unwrap();
+                assert_eq!(
+                    ResponsePacketType::Proxy { init_session: true },
+                    res.header.packet_type,
+                    "{:?}",
+                    res.body
+                );
+                res.body
+            } else {
+                // The tunnel is extended with a frame that contains the request for the next hop
+                let data = send_frame(
+                    connection,
+                    tunnel_id,
+                    &mut layers,
+                    true,
+                    &req.to_bytes(None).unwrap(),
+                )
+                .unwrap();
+                PlabbleResponsePacket::from_bytes(&data, None).unwrap().body
+            };
+
+            let PlabbleResponseBody::Proxy(ProxyResponseBody::Initialize {
+                tunnel_id: id,
+                hops,
+            }) = body
+            else {
+                panic!("Expected proxy initialize response, got {:?}", body);
+            };
+            if index == 0 {
+                tunnel_id = id;
+            }
+            assert_eq!(1, hops.len());
+
+            let info = &hops[*hop];
+            let data = HopInfo::signature_data(&keys, &info.keys);
+            assert_eq!(
+                Some(true),
+                verification_key.verify(&data, &info.signatures[0])
+            );
+
+            let secret = kx.process_response(&info.keys[0]).unwrap();
+            layers.push(TunnelLayer::new(CryptoSettings::default(), vec![secret]));
+        }
+
+        (tunnel_id, layers)
+    }
+
+    /// Send a frame through the tunnel, adding and removing the onion layers
+    fn send_frame(
+        connection: &mut PlabbleConnection,
+        tunnel_id: u32,
+        layers: &mut [TunnelLayer],
+        keep_connection: bool,
+        data: &[u8],
+    ) -> Result<Vec<u8>, PlabbleError> {
+        let packet = layers.iter().rev().fold(data.to_vec(), |data, layer| {
+            layer.seal(true, &data).unwrap()
+        });
+
+        let res = block_on(connection.handle_request(proxy_request(
+            keep_connection,
+            ProxyRequestBody::Tunnel { tunnel_id, packet },
+        )))
+        .unwrap();
+
+        let response = match res.body {
+            PlabbleResponseBody::Proxy(ProxyResponseBody::Tunnel {
+                tunnel_id: id,
+                packet,
+            }) => {
+                assert_eq!(tunnel_id, id);
+                layers
+                    .iter()
+                    .fold(packet, |data, layer| layer.open(false, &data).unwrap())
+            }
+            PlabbleResponseBody::Error(error) => return Err(error),
+            body => panic!("Unexpected response {:?}", body),
+        };
+
+        for layer in layers.iter_mut() {
+            layer.next_frame();
+        }
+        Ok(response)
+    }
+
+    #[test]
+    fn can_relay_frames_through_a_proxy_tunnel() {
+        let relay = ExampleProxyRelay::new(&["hop1", "hop2", "hop3"]);
+        let mut connection = relay.hop("hop1");
+
+        let route = ["hop1", "hop2", "hop3"];
+        let (tunnel_id, mut layers) = initialize_tunnel(&mut connection, false, &route);
+        assert!(route.iter().all(|hop| relay.hops[*hop].count() == 1));
+
+        for data in [b"ping".as_slice(), b"pong"] {
+            assert_eq!(
+                Ok([b"echo:".as_slice(), data].concat()),
+                send_frame(&mut connection, tunnel_id, &mut layers, true, data)
+            );
+        }
+
+        // Frames can only be sent with the onion layers of the tunnel, but frames that fail authentication do not close it
+        let mut wrong = vec![TunnelLayer::new(CryptoSettings::default(), vec![[0; 32]])];
+        assert_eq!(
+            Err(PlabbleError::InvalidRequest),
+            send_frame(&mut connection, tunnel_id, &mut wrong, true, b"ping")
+        );
+        assert!(route.iter().all(|hop| relay.hops[*hop].count() == 1));
+        assert_eq!(
+            Ok(b"echo:ping".to_vec()),
+            send_frame(&mut connection, tunnel_id, &mut layers, true, b"ping")
+        );
+
+        // A tunnel that is complete can not be extended anymore, the frame goes to the target
+        let (_, _, req) = initialize_request(&route, 2, false);
+        let data = req.to_bytes(None).unwrap();
+        assert_eq!(
+            Ok([b"echo:".as_slice(), &data].concat()),
+            send_frame(&mut connection, tunnel_id, &mut layers, true, &data)
+        );
+    }
+
+    #[test]
+    fn proxy_tunnel_is_closed_without_keep_connection() {
+        let relay = ExampleProxyRelay::new(&["hop1", "hop2"]);
+        let mut connection = relay.hop("hop1");
+
+        // Random hops are selected by the servers, which report themselves under their own address
+        let (tunnel_id, mut layers) = initialize_tunnel(&mut connection, true, &["hop1", "hop2"]);
+        assert_eq!(
+            Ok(b"echo:bye".to_vec()),
+            send_frame(&mut connection, tunnel_id, &mut layers, false, b"bye")
+        );
+        assert_eq!(0, relay.hops["hop1"].count());
+        assert_eq!(0, relay.hops["hop2"].count());
+    }
+
+    #[test]
+    fn proxy_tunnel_does_not_wait_for_reply_to_fire_and_forget_packets() {
+        let relay = ExampleProxyRelay::new(&["hop1", "hop2"]);
+        let mut connection = relay.hop("hop1");
+        let (tunnel_id, mut layers) = initialize_tunnel(&mut connection, false, &["hop1", "hop2"]);
+
+        let packet = PlabblePacketBase {
+            fire_and_forget: true,
+            ..Default::default()
+        }
+        .to_bytes(None::<&mut SerializerConfig>)
+        .unwrap();
+        assert_eq!(
+            Ok(vec![]),
+            send_frame(&mut connection, tunnel_id, &mut layers, true, &packet)
+        );
+        assert_eq!(vec![packet], *relay.received.lock().unwrap());
+
+        assert_eq!(
+            Ok(b"echo:ping".to_vec()),
+            send_frame(&mut connection, tunnel_id, &mut layers, true, b"ping")
+        );
+    }
+
+    #[test]
+    fn proxy_hops_only_learn_next_hop_and_last_hop_learns_target() {
+        let relay = ExampleProxyRelay::new(&["hop1", "hop2", "hop3"]);
+        let mut connection = relay.hop("hop1");
+
+        let route = ["hop1", "hop2", "hop3"];
+        let (kx, _, req) = initialize_request(&route, 0, false);
+        let PlabbleRequestBody::Proxy(ProxyRequestBody::Initialize { target, via, .. }) = &req.body
+        else {
+            unreachable!();
+        };
+        assert_eq!("", target);
+        assert_eq!(Some(vec!["hop1".to_string(), "hop2".to_string()]), *via);
+
+        let res = block_on(connection.handle_request(req)).unwrap();
+        let PlabbleResponseBody::Proxy(ProxyResponseBody::Initialize { tunnel_id, hops }) =
+            res.body
+        else {
+            panic!("Expected proxy initialize response, got {:?}", res.body);
+        };
+        let tunnel = relay.hops["hop1"].take(tunnel_id).unwrap();
+        assert_eq!("", tunnel.target);
+        assert_eq!(Some("hop2".to_string()), tunnel.next_hop);
+        relay.hops["hop1"].restore(tunnel_id, tunnel);
+
+        // The tunnel can only be extended to the next hop the first hop was given
+        let secret = kx.process_response(&hops["hop1"].keys[0]).unwrap();
+        let mut layers = vec![TunnelLayer::new(CryptoSettings::default(), vec![secret])];
+        let (_, _, req) = initialize_request(&["hop1", "hop3"], 1, false);
+        assert_eq!(
+            Err(PlabbleError::InvalidRequest),
+            send_frame(
+                &mut connection,
+                tunnel_id,
+                &mut layers,
+                true,
+                &req.to_bytes(None).unwrap()
+            )
+        );
+        assert_eq!(0, relay.hops["hop1"].count());
+        assert_eq!(0, relay.hops["hop3"].count());
+    }
+
+    #[test]
+    fn proxy_initialize_fails_on_invalid_or_unreachable_route() {
+        let relay = ExampleProxyRelay::new(&["hop1"]);
+        let mut connection = relay.hop("hop1");
+
+        let key = KeyExchange::new(KeyExchangeAlgorithm::X25519)
+            .make_request()
+            .unwrap();
+        let initialize = |target: &str, via: Vec<&str>, keys: usize| {
+            proxy_request(
+                true,
+                ProxyRequestBody::Initialize {
+                    target: target.to_string(),
+                    hop_count: via.len() as u8,
+                    via: Some(via.into_iter().map(String::from).collect()),
+                    keys: vec![key.clone(); keys],
+                },
+            )
+        };
+
+        for (req, error) in [
+            (
+                initialize("target", vec!["hop1", "hop2"], 2),
+                PlabbleError::InvalidRequest,
+            ),
+            (
+                initialize("target", vec![], 1),
+                PlabbleError::InvalidRequest,
+            ),
+            (
+                initialize("missing", vec!["hop1"], 1),
+                PlabbleError::HopUnreachable,
+            ),
+        ] {
+            let res = block_on(connection.handle_request(req)).unwrap();
+            assert_eq!(PlabbleResponseBody::Error(error), res.body);
+        }
+        assert_eq!(0, relay.hops["hop1"].count());
+
+        // The next hop is only reached when the tunnel is extended, which closes the tunnel if it fails
+        let route = ["hop1", "hop2"];
+        let (kx, _, req) = initialize_request(&route, 0, false);
+        let res = block_on(connection.handle_request(req)).unwrap();
+        let PlabbleResponseBody::Proxy(ProxyResponseBody::Initialize { tunnel_id, hops }) =
+            res.body
+        else {
+            panic!("Expected proxy initialize response, got {:?}", res.body);
+        };
+        let secret = kx.process_response(&hops["hop1"].keys[0]).unwrap();
+        let mut layers = vec![TunnelLayer::new(CryptoSettings::default(), vec![secret])];
+        assert_eq!(1, relay.hops["hop1"].count());
+
+        let (_, _, req) = initialize_request(&route, 1, false);
+        assert_eq!(
+            Err(PlabbleError::HopUnreachable),
+            send_frame(
+                &mut connection,
+                tunnel_id,
+                &mut layers,
+                true,
+                &req.to_bytes(None).unwrap()
+            )
+        );
+        assert_eq!(0, relay.hops["hop1"].count());
     }
 }
@@ -9,6 +9,14 @@ use crate::{
 pub mod implementation;
 #[cfg(feature = "implementation")]
 pub mod options;
+#[cfg(feature = "implementation")]
+pub mod permissions;
+#[cfg(feature = "implementation")]
+pub mod proxy;
+#[cfg(feature = "implementation")]
+pub mod scripting;
+#[cfg(feature = "implementation")]
+pub mod subscriptions;
 
 pub mod node;
 
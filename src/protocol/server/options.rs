@@ -0,0 +1,35 @@
+use crate::scripting::opcode_script::ScriptSettings;
+
+/// Options of a Plabble server, shared by all of its connections
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// URI of certificates issued with REGISTER, `{id}` is replaced by the (base64url) certificate ID
+    pub certificate_uri: String,
+
+    /// Lifetime in seconds of certificates issued with REGISTER.
+    /// Issued certificates never outlive the server certificate that signs them.
+    pub certificate_lifetime: u32,
+
+    /// Maximum difference in seconds between the timestamp of an IDENTIFY request and the clock of the server
+    pub identify_window: u32,
+
+    /// Address of this server, under which it reports itself as hop of PROXY tunnels with randomly selected hops
+    pub address: String,
+
+    /// Settings of OPCODE scripts executed by the server.
+    /// Bucket operations and eval are only allowed if both these settings and the flags of the request allow them.
+    /// Both are disabled by default.
+    pub script_settings: ScriptSettings,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            certificate_uri: "plabble:localhost/certificates/{id}.crt".to_string(),
+            certificate_lifetime: 365 * 24 * 60 * 60,
+            identify_window: 5 * 60,
+            address: "localhost".to_string(),
+            script_settings: ScriptSettings::default(),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::packets::body::{
+    error::PlabbleError,
+    post::{BucketPermissions, BucketSettings},
+};
+
+/// Operation on a bucket that is guarded by the bucket permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketOperation {
+    /// Read slots (GET, STREAM, SUBSCRIBE)
+    Read,
+    /// Append slots to the next free numeric slots (PUT with append)
+    Append,
+    /// Write or update slots (PUT)
+    Write,
+    /// Delete slots (DELETE)
+    Delete,
+    /// Execute OPCODE scripts interacting with the bucket
+    ScriptExecution,
+    /// Delete the entire bucket (DELETE)
+    BucketDelete,
+    /// Update the permissions or ACL of the bucket (PATCH), only allowed for owners of the bucket key
+    UpdateSettings,
+}
+
+/// Access tiers of a requester to a bucket. Everyone has public access, the other tiers are optional.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BucketAccess {
+    /// The requester proved its identity with IDENTIFY and is on the ACL of the bucket
+    pub protected: bool,
+
+    /// The requester authenticated the request with the bucket key
+    pub private: bool,
+}
+
+impl BucketAccess {
+    /// Determine the access tiers of a requester to a bucket
+    ///
+    /// # Parameters
+    /// - `settings`: The settings of the bucket, containing the ACL
+    /// - `identity`: The ID of the certificate the requester identified with, if any
+    /// - `bucket_key_authenticated`: If the request was authenticated with the bucket key
+    pub fn new(
+        settings: &BucketSettings,
+        identity: Option<&[u8; 16]>,
+        bucket_key_authenticated: bool,
+    ) -> Self {
+        Self {
+            protected: identity.is_some_and(|id| settings.access_control_list.contains(id)),
+            private: bucket_key_authenticated,
+        }
+    }
+
+    /// Check if any of the access tiers of the requester allows the operation
+    pub fn allows(&self, permissions: &BucketPermissions, operation: BucketOperation) -> bool {
+        let p = permissions;
+        let (public, protected, private) = match operation {
+            BucketOperation::Read => (p.public_read, p.protected_read, p.private_read),
+            BucketOperation::Append => (p.public_append, p.protected_append, p.private_append),
+            BucketOperation::Write => (p.public_write, p.protected_write, p.private_write),
+            BucketOperation::Delete => (p.public_delete, p.protected_delete, p.private_delete),
+            BucketOperation::ScriptExecution => (
+                p.public_script_execution,
+                p.protected_script_execution,
+                p.private_script_execution,
+            ),
+            BucketOperation::BucketDelete => {
+                (false, p.protected_bucket_delete, p.private_bucket_delete)
+            }
+            BucketOperation::UpdateSettings => (false, false, true),
+        };
+
+        public || (self.protected && protected) || (self.private && private)
+    }
+
+    /// Authorize the operation, failing with `PlabbleError::PermissionDenied` if it is not allowed.
+    /// If the requester is not allowed to read the bucket and `deny_existence` is set, fail with `PlabbleError::BucketNotFound` instead.
+    pub fn authorize(
+        &self,
+        permissions: &BucketPermissions,
+        operation: BucketOperation,
+    ) -> Result<(), PlabbleError> {
+        if self.allows(permissions, operation) {
+            return Ok(());
+        }
+
+        if permissions.deny_existence && !self.allows(permissions, BucketOperation::Read) {
+            return Err(PlabbleError::BucketNotFound);
+        }
+
+        Err(PlabbleError::PermissionDenied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        packets::body::{error::PlabbleError, post::BucketSettings},
+        protocol::server::permissions::{BucketAccess, BucketOperation},
+    };
+
+    #[test]
+    fn evaluates_public_protected_and_private_tiers() {
+        let mut settings = BucketSettings::default();
+        settings.access_control_list.push([1; 16]);
+        settings.permissions.protected_write = true;
+
+        let public = BucketAccess::new(&settings, Some(&[2; 16]), false);
+        let protected = BucketAccess::new(&settings, Some(&[1; 16]), false);
+        let private = BucketAccess::new(&settings, None, true);
+        assert_eq!(BucketAccess::default(), public);
+
+        let permissions = &settings.permissions;
+        assert!(public.allows(permissions, BucketOperation::Read));
+        assert!(!public.allows(permissions, BucketOperation::Write));
+        assert!(protected.allows(permissions, BucketOperation::Write));
+        assert!(!protected.allows(permissions, BucketOperation::Append));
+        assert!(!protected.allows(permissions, BucketOperation::UpdateSettings));
+        assert!(private.allows(permissions, BucketOperation::Append));
+        assert!(private.allows(permissions, BucketOperation::BucketDelete));
+        assert!(private.allows(permissions, BucketOperation::UpdateSettings));
+    }
+
+    #[test]
+    fn denies_existence_only_if_requester_cannot_read() {
+        let mut settings = BucketSettings::default();
+        settings.permissions.public_read = false;
+
+        let public = BucketAccess::default();
+        assert_eq!(
+            Err(PlabbleError::PermissionDenied),
+            public.authorize(&settings.permissions, BucketOperation::Read)
+        );
+
+        settings.permissions.deny_existence = true;
+        assert_eq!(
+            Err(PlabbleError::BucketNotFound),
+            public.authorize(&settings.permissions, BucketOperation::Write)
+        );
+
+        settings.permissions.public_read = true;
+        assert_eq!(
+            Err(PlabbleError::PermissionDenied),
+            public.authorize(&settings.permissions, BucketOperation::Write)
+        );
+    }
+}
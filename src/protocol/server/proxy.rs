@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    packets::body::error::PlabbleError, protocol::proxy::TunnelLayer, providers::ProxyLink,
+};
+
+/// A tunnel through this server, as one hop of its route
+pub struct Tunnel {
+    /// Onion layer shared with the client of the tunnel
+    pub layer: TunnelLayer,
+
+    /// Connection to the next hop, or to the target if this server is the last hop.
+    /// None until the client extends the tunnel to the next hop.
+    pub link: Option<Box<dyn ProxyLink>>,
+
+    /// Tunnel ID the next hop gave to the tunnel, None if the link goes to the target
+    pub next_tunnel_id: Option<u32>,
+
+    /// Target of the tunnel. With hops given by the client only the last hop knows it, it is empty for the other hops.
+    pub target: String,
+
+    /// Amount of hops in the route after this hop, as far as this hop knows
+    pub remaining_hops: u8,
+
+    /// Address of the next hop, if it is given by the client (None for the last hop or if the hops are selected randomly)
+    pub next_hop: Option<String>,
+}
+
+/// Registry of PROXY tunnels, shared between all connections of a server
+///
+/// Tunnels are looked up by the tunnel ID this server gave them, which is only known by the previous hop (or client).
+/// A tunnel is taken out of the registry while a frame is relayed, so the frames of a tunnel are handled in order
+/// without holding a lock while waiting for the next hop.
+///
+/// Tunnels are not bound to a connection, so the amount of open tunnels is limited and tunnels that are idle
+/// for longer than the idle timeout are closed (when the registry is used).
+pub struct TunnelRegistry {
+    tunnels: Mutex<HashMap<u32, TunnelEntry>>,
+    max_tunnels: usize,
+    idle_timeout: Duration,
+}
+
+/// A tunnel in the registry (None while a frame is relayed) and the moment it was last used
+struct TunnelEntry {
+    tunnel: Option<Tunnel>,
+    last_used: Instant,
+}
+
+impl Default for TunnelRegistry {
+    fn default() -> Self {
+        Self::with_limits(1024, Duration::from_secs(10 * 60))
+    }
+}
+
+impl TunnelRegistry {
+    /// Create new, empty tunnel registry with at most 1024 tunnels that are closed after 10 minutes of inactivity
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create new, empty tunnel registry with at most `max_tunnels` tunnels that are closed after being idle for `idle_timeout`
+    pub fn with_limits(max_tunnels: usize, idle_timeout: Duration) -> Self {
+        Self {
+            tunnels: Mutex::default(),
+            max_tunnels,
+            idle_timeout,
+        }
+    }
+
+    /// Lock the tunnels, closing the tunnels that are idle for longer than the idle timeout.
+    /// Tunnels that are relaying a frame are not idle.
+    fn lock(&self) -> MutexGuard<'_, HashMap<u32, TunnelEntry>> {
+        let mut tunnels = self.tunnels.lock().unwrap_or_else(PoisonError::into_inner);
+        tunnels.retain(|_, entry| {
+            entry.tunnel.is_none() || entry.last_used.elapsed() <= self.idle_timeout
+        });
+        tunnels
+    }
+
+    /// Register a tunnel under a new random tunnel ID, which is returned.
+    /// Fails with `TooManyTunnels` if the maximum amount of tunnels is open.
+    pub fn open(&self, tunnel: Tunnel) -> Result<u32, PlabbleError> {
+        let mut tunnels = self.lock();
+        if tunnels.len() >= self.max_tunnels {
+            return Err(PlabbleError::TooManyTunnels);
+        }
+
+        let mut id = rand::random();
+        while tunnels.contains_key(&id) {
+            id = rand::random();
+        }
+
+        tunnels.insert(
+            id,
+            TunnelEntry {
+                tunnel: Some(tunnel),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Take a tunnel out of the registry to relay a frame, it must be [restored](Self::restore) or [closed](Self::close) afterwards.
+    /// Fails with `TunnelNotFound` if the tunnel does not exist (or was idle for too long) or `TunnelBusy` if another frame is being relayed.
+    pub fn take(&self, id: u32) -> Result<Tunnel, PlabbleError> {
+        let mut tunnels = self.lock();
+        tunnels
+            .get_mut(&id)
+            .ok_or(PlabbleError::TunnelNotFound)?
+            .tunnel
+            .take()
+            .ok_or(PlabbleError::TunnelBusy)
+    }
+
+    /// Put a tunnel that was taken back into the registry, unless it was closed in the meantime
+    pub fn restore(&self, id: u32, tunnel: Tunnel) {
+        let mut tunnels = self.lock();
+        if let Some(entry) = tunnels.get_mut(&id) {
+            entry.tunnel = Some(tunnel);
+            entry.last_used = Instant::now();
+        }
+    }
+
+    /// Remove a tunnel (closing its link once it is no longer used), returns true if it existed
+    pub fn close(&self, id: u32) -> bool {
+        self.lock().remove(&id).is_some()
+    }
+
+    /// Amount of open tunnels
+    pub fn count(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        packets::{base::settings::CryptoSettings, body::error::PlabbleError},
+        protocol::{
+            proxy::TunnelLayer,
+            server::proxy::{Tunnel, TunnelRegistry},
+        },
+    };
+
+    fn tunnel() -> Tunnel {
+        Tunnel {
+            layer: TunnelLayer::new(CryptoSettings::default(), vec![[0; 32]]),
+            link: None,
+            next_tunnel_id: None,
+            target: "target".to_string(),
+            remaining_hops: 1,
+            next_hop: None,
+        }
+    }
+
+    #[test]
+    fn tunnel_is_unavailable_while_relaying_a_frame() {
+        let registry = TunnelRegistry::new();
+        let id = registry.open(tunnel()).unwrap();
+
+        let tunnel = registry.take(id).unwrap();
+        assert!(matches!(registry.take(id), Err(PlabbleError::TunnelBusy)));
+        assert_eq!(1, registry.count());
+
+        registry.restore(id, tunnel);
+        let tunnel = registry.take(id).unwrap();
+        assert!(registry.close(id));
+
+        // A tunnel that was closed while relaying is not restored
+        registry.restore(id, tunnel);
+        assert!(matches!(
+            registry.take(id),
+            Err(PlabbleError::TunnelNotFound)
+        ));
+        assert_eq!(0, registry.count());
+    }
+
+    #[test]
+    fn amount_of_tunnels_is_limited() {
+        let registry = TunnelRegistry::with_limits(2, Duration::from_secs(60));
+        let id = registry.open(tunnel()).unwrap();
+        registry.open(tunnel()).unwrap();
+        assert!(matches!(
+            registry.open(tunnel()),
+            Err(PlabbleError::TooManyTunnels)
+        ));
+
+        assert!(registry.close(id));
+        registry.open(tunnel()).unwrap();
+        assert_eq!(2, registry.count());
+    }
+
+    #[test]
+    fn idle_tunnels_are_closed() {
+        let registry = TunnelRegistry::with_limits(1, Duration::from_millis(50));
+        let id = registry.open(tunnel()).unwrap();
+
+        // A tunnel that is relaying a frame is not idle
+        let taken = registry.take(id).unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(1, registry.count());
+        registry.restore(id, taken);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(
+            registry.take(id),
+            Err(PlabbleError::TunnelNotFound)
+        ));
+        assert_eq!(0, registry.count());
+        registry.open(tunnel()).unwrap();
+    }
+}
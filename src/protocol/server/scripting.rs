@@ -0,0 +1,132 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    core::BucketId,
+    packets::body::{
+        bucket::{BucketBody, BucketRange},
+        error::PlabbleError,
+    },
+    protocol::{
+        error::PlabbleProtocolError,
+        server::{
+            permissions::{BucketAccess, BucketOperation},
+            subscriptions::Subscriber,
+        },
+    },
+    providers::{BucketStore, PlabbleBucketProvider},
+};
+
+/// Bucket provider for OPCODE scripts that are executed by the server, backed by the bucket store of the server.
+///
+/// Scripts can only select buckets of this server on which the requester is allowed to execute scripts,
+/// every operation on the selected bucket also needs the permission for the operation itself.
+/// OPCODE requests are not bound to a bucket (key), so the requester only has public or protected access.
+pub struct ServerBucketProvider {
+    store: Arc<dyn BucketStore>,
+    identity: Option<[u8; 16]>,
+    subscriber: Option<Subscriber>,
+    selected: Mutex<Option<BucketId>>,
+}
+
+impl ServerBucketProvider {
+    /// Create a bucket provider for the requester that identified with the identity (if any).
+    /// Changes made by the script are reported to the subscriber, if given.
+    pub fn new(
+        store: Arc<dyn BucketStore>,
+        identity: Option<[u8; 16]>,
+        subscriber: Option<Subscriber>,
+    ) -> Self {
+        Self {
+            store,
+            identity,
+            subscriber,
+            selected: Mutex::new(None),
+        }
+    }
+
+    /// Check if the requester is allowed to perform the operation on a bucket
+    fn authorize(
+        &self,
+        id: &BucketId,
+        operation: BucketOperation,
+    ) -> Result<(), PlabbleProtocolError> {
+        let settings = self.store.get_settings(id)?;
+        let access = BucketAccess::new(&settings, self.identity.as_ref(), false);
+
+        Ok(access.authorize(&settings.permissions, operation)?)
+    }
+
+    /// Get the selected bucket if the requester is allowed to perform the operation on it.
+    /// Fails with `PlabbleError::InvalidRequest` if no bucket is selected.
+    fn selected(&self, operation: BucketOperation) -> Result<BucketId, PlabbleProtocolError> {
+        let id = self
+            .selected
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(PlabbleError::InvalidRequest)?;
+
+        self.authorize(&id, operation)?;
+        Ok(id)
+    }
+}
+
+impl PlabbleBucketProvider for ServerBucketProvider {
+    fn connect(&self, _address: &str) -> Result<(), PlabbleProtocolError> {
+        // Scripts executed by the server can only use the buckets of this server
+        Err(PlabbleError::InvalidRequest.into())
+    }
+
+    fn select_bucket(&self, bucket_id: &[u8; 16]) -> Result<(), PlabbleProtocolError> {
+        let id = BucketId { data: *bucket_id };
+        self.authorize(&id, BucketOperation::ScriptExecution)?;
+        *self.selected.lock().unwrap() = Some(id);
+        Ok(())
+    }
+
+    fn read(&self, slot: u32) -> Result<Vec<u8>, PlabbleProtocolError> {
+        let id = self.selected(BucketOperation::Read)?;
+        let range = BucketRange::Numeric(Some(slot), Some(slot));
+        match self.store.read(&id, &range, None)? {
+            BucketBody::Numeric(mut slots) => {
+                Ok(slots.remove(&slot).ok_or(PlabbleError::SlotNotFound)?)
+            }
+            _ => Err(PlabbleError::InternalServerError.into()),
+        }
+    }
+
+    fn write(&self, slot: u32, data: Vec<u8>) -> Result<(), PlabbleProtocolError> {
+        let id = self.selected(BucketOperation::Write)?;
+        let body = BucketBody::Numeric([(slot, data)].into());
+        self.store.write(&id, body.clone(), true)?;
+
+        if let Some(subscriber) = &self.subscriber {
+            subscriber.notify_written(&id, &body);
+        }
+        Ok(())
+    }
+
+    fn append(&self, data: Vec<u8>) -> Result<(), PlabbleProtocolError> {
+        let id = self.selected(BucketOperation::Append)?;
+        let slots = self.store.append(&id, vec![data.clone()])?;
+
+        if let Some(subscriber) = &self.subscriber {
+            let body = BucketBody::Numeric(slots.into_iter().zip([data]).collect());
+            subscriber.notify_written(&id, &body);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, slot: u32) -> Result<(), PlabbleProtocolError> {
+        let id = self.selected(BucketOperation::Delete)?;
+        let range = BucketRange::Numeric(Some(slot), Some(slot));
+        let deleted = self.store.delete(&id, &range, None)?;
+
+        if let Some(subscriber) = &self.subscriber
+            && !deleted.is_empty()
+        {
+            subscriber.notify_deleted(&id, &deleted);
+        }
+        Ok(())
+    }
+}
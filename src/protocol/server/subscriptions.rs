@@ -0,0 +1,446 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, PoisonError, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use async_channel::{Receiver, Sender};
+
+use crate::{
+    core::BucketId,
+    packets::{
+        base::PlabblePacketBase,
+        body::{
+            bucket::{BucketBody, BucketRange, SubscriptionUpdate},
+            post::BucketSettings,
+            response_body::PlabbleResponseBody,
+        },
+        header::{response_header::PlabbleResponseHeader, type_and_flags::ResponsePacketType},
+        response::PlabbleResponsePacket,
+    },
+    protocol::server::permissions::{BucketAccess, BucketOperation},
+};
+
+/// The subscription of a subscriber on one or more ranges of a bucket
+struct Subscription {
+    subscriber: u64,
+    ranges: Vec<BucketRange>,
+    use_encryption: bool,
+    access: SubscriberAccess,
+    sender: Sender<PlabbleResponsePacket>,
+}
+
+/// Access of a subscriber to a bucket when it (last) subscribed, to re-authorize its subscription when the bucket settings change
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubscriberAccess {
+    /// The ID of the certificate the subscriber identified with, if any
+    pub identity: Option<[u8; 16]>,
+
+    /// If the subscription was authenticated with the bucket key
+    pub bucket_key_authenticated: bool,
+}
+
+/// Registry of bucket subscriptions, shared between all connections of a server
+///
+/// Every connection gets its own [`Subscriber`] handle (stored in the connection context) and a receiver for the subscription updates.
+/// The updates are fire-and-forget SUBSCRIBE response packets. The server loop of a connection should wait for both
+/// incoming requests and this receiver, and send every update to the client with `send_response` as soon as it is received.
+/// When the connection is closed the receiver is dropped, after which its subscriptions are removed on the next change.
+/// Subscriptions are removed when their bucket is deleted, or when the subscriber may no longer read the bucket after its settings changed.
+#[derive(Default)]
+pub struct SubscriptionRegistry {
+    next_id: AtomicU64,
+    subscriptions: RwLock<HashMap<[u8; 16], Vec<Subscription>>>,
+}
+
+impl SubscriptionRegistry {
+    /// Create new, empty subscription registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new subscriber (for a connection) and the receiver for its subscription updates
+    pub fn create_subscriber(self: &Arc<Self>) -> (Subscriber, Receiver<PlabbleResponsePacket>) {
+        let (sender, receiver) = async_channel::unbounded();
+        let subscriber = Subscriber {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            registry: self.clone(),
+            sender,
+        };
+
+        (subscriber, receiver)
+    }
+
+    /// Amount of subscribed ranges on a bucket (of all subscribers)
+    pub fn count(&self, bucket: &BucketId) -> usize {
+        let subscriptions = self
+            .subscriptions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        subscriptions
+            .get(&bucket.data)
+            .map_or(0, |s| s.iter().map(|s| s.ranges.len()).sum())
+    }
+
+    /// Push an update for the changed slots to every subscriber (except `origin`) with a subscription on an overlapping range.
+    /// Every subscriber receives at most one update, containing the changed slots within any of its ranges.
+    /// If `offset` is set, the slots only contain the bytes that were streamed into them at that offset.
+    /// Subscriptions of subscribers that are gone (receiver dropped) are removed.
+    fn notify(
+        &self,
+        origin: u64,
+        bucket: &BucketId,
+        slots: &BucketBody,
+        deleted: bool,
+        offset: Option<u64>,
+    ) {
+        let mut subscriptions = self
+            .subscriptions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let Some(bucket_subscriptions) = subscriptions.get_mut(&bucket.data) else {
+            return;
+        };
+
+        bucket_subscriptions.retain(|subscription| {
+            if subscription.sender.is_closed() {
+                return false;
+            }
+
+            if subscription.subscriber == origin {
+                return true;
+            }
+
+            if let Some(slots) = slots.filter(&subscription.ranges) {
+                let packet =
+                    create_update(bucket, slots, deleted, offset, subscription.use_encryption);
+                return subscription.sender.try_send(packet).is_ok();
+            }
+
+            true
+        });
+
+        if bucket_subscriptions.is_empty() {
+            subscriptions.remove(&bucket.data);
+        }
+    }
+
+    /// Remove the subscriptions on a bucket of the subscribers that may no longer read it with the new settings
+    fn reauthorize(&self, bucket: &BucketId, settings: &BucketSettings) {
+        let mut subscriptions = self
+            .subscriptions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let Some(bucket_subscriptions) = subscriptions.get_mut(&bucket.data) else {
+            return;
+        };
+
+        bucket_subscriptions.retain(|subscription| {
+            let access = subscription.access;
+            BucketAccess::new(
+                settings,
+                access.identity.as_ref(),
+                access.bucket_key_authenticated,
+            )
+            .allows(&settings.permissions, BucketOperation::Read)
+        });
+
+        if bucket_subscriptions.is_empty() {
+            subscriptions.remove(&bucket.data);
+        }
+    }
+
+    /// Remove all subscriptions on a bucket
+    fn remove_bucket(&self, bucket: &BucketId) {
+        self.subscriptions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&bucket.data);
+    }
+}
+
+/// Subscriber handle of a single connection in the [`SubscriptionRegistry`]
+#[derive(Clone)]
+pub struct Subscriber {
+    id: u64,
+    registry: Arc<SubscriptionRegistry>,
+    sender: Sender<PlabbleResponsePacket>,
+}
+
+impl Subscriber {
+    /// Subscribe to changes of the slots within the range of a bucket, with the access the subscriber has to the bucket.
+    /// The updates are encrypted if `use_encryption` is set for this or any earlier subscription on the bucket.
+    pub fn subscribe(
+        &self,
+        bucket: &BucketId,
+        range: BucketRange,
+        use_encryption: bool,
+        access: SubscriberAccess,
+    ) {
+        let mut subscriptions = self
+            .registry
+            .subscriptions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let bucket_subscriptions = subscriptions.entry(bucket.data).or_default();
+        match bucket_subscriptions
+            .iter_mut()
+            .find(|s| s.subscriber == self.id)
+        {
+            Some(subscription) => {
+                subscription.use_encryption |= use_encryption;
+                subscription.access = access;
+                if !subscription.ranges.contains(&range) {
+                    subscription.ranges.push(range)
+                }
+            }
+            None => bucket_subscriptions.push(Subscription {
+                subscriber: self.id,
+                ranges: vec![range],
+                use_encryption,
+                access,
+                sender: self.sender.clone(),
+            }),
+        }
+    }
+
+    /// Unsubscribe from exactly the given range of a bucket (other ranges, even overlapping ones, are kept).
+    /// Returns false if there was no subscription on this range.
+    pub fn unsubscribe(&self, bucket: &BucketId, range: &BucketRange) -> bool {
+        let mut subscriptions = self
+            .registry
+            .subscriptions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let Some(bucket_subscriptions) = subscriptions.get_mut(&bucket.data) else {
+            return false;
+        };
+
+        let Some(subscription) = bucket_subscriptions
+            .iter_mut()
+            .find(|s| s.subscriber == self.id)
+        else {
+            return false;
+        };
+
+        let count = subscription.ranges.len();
+        subscription.ranges.retain(|r| r != range);
+        let removed = subscription.ranges.len() < count;
+
+        bucket_subscriptions.retain(|s| !s.ranges.is_empty());
+        if bucket_subscriptions.is_empty() {
+            subscriptions.remove(&bucket.data);
+        }
+
+        removed
+    }
+
+    /// Notify all other subscribers that slots in a bucket were written (with their new values)
+    pub fn notify_written(&self, bucket: &BucketId, slots: &BucketBody) {
+        self.registry.notify(self.id, bucket, slots, false, None);
+    }
+
+    /// Notify all other subscribers that bytes were streamed into a slot at the byte offset (with only the written bytes)
+    pub fn notify_streamed(&self, bucket: &BucketId, slot: &BucketBody, offset: u64) {
+        self.registry
+            .notify(self.id, bucket, slot, false, Some(offset));
+    }
+
+    /// Notify all other subscribers that slots in a bucket were deleted (with their old values)
+    pub fn notify_deleted(&self, bucket: &BucketId, slots: &BucketBody) {
+        self.registry.notify(self.id, bucket, slots, true, None);
+    }
+
+    /// Report that the settings of a bucket changed, removing the subscriptions (of all subscribers)
+    /// of which the subscriber may no longer read the bucket
+    pub fn settings_changed(&self, bucket: &BucketId, settings: &BucketSettings) {
+        self.registry.reauthorize(bucket, settings);
+    }
+
+    /// Report that a bucket was deleted, removing all subscriptions on it (of all subscribers)
+    pub fn bucket_deleted(&self, bucket: &BucketId) {
+        self.registry.remove_bucket(bucket);
+    }
+}
+
+/// Create a fire-and-forget subscription update packet, encrypted if the subscription was made over encryption
+fn create_update(
+    bucket: &BucketId,
+    slots: BucketBody,
+    deleted: bool,
+    offset: Option<u64>,
+    use_encryption: bool,
+) -> PlabbleResponsePacket {
+    PlabbleResponsePacket {
+        base: PlabblePacketBase {
+            fire_and_forget: true,
+            use_encryption,
+            ..Default::default()
+        },
+        header: PlabbleResponseHeader::new(
+            ResponsePacketType::Subscribe {
+                binary_keys: matches!(slots, BucketBody::Binary(_)),
+                update: true,
+                deleted,
+                stream: offset.is_some(),
+            },
+            None,
+        ),
+        body: PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+            id: bucket.clone(),
+            offset,
+            slots,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        core::BucketId,
+        packets::{
+            body::{
+                bucket::{BucketBody, BucketRange, SubscriptionUpdate},
+                response_body::PlabbleResponseBody,
+            },
+            header::type_and_flags::ResponsePacketType,
+        },
+        protocol::server::subscriptions::{SubscriberAccess, SubscriptionRegistry},
+    };
+
+    #[test]
+    fn pushes_updates_within_range_to_other_subscribers() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let (writer, writer_rx) = registry.create_subscriber();
+        let (reader, reader_rx) = registry.create_subscriber();
+        let id = BucketId::parse("#test").unwrap();
+
+        writer.subscribe(
+            &id,
+            BucketRange::Numeric(None, None),
+            false,
+            SubscriberAccess::default(),
+        );
+        reader.subscribe(
+            &id,
+            BucketRange::Numeric(Some(5), Some(10)),
+            false,
+            SubscriberAccess::default(),
+        );
+        reader.subscribe(
+            &id,
+            BucketRange::Numeric(Some(5), Some(10)),
+            false,
+            SubscriberAccess::default(),
+        );
+        assert_eq!(2, registry.count(&id));
+
+        writer.notify_written(
+            &id,
+            &BucketBody::Numeric([(1, vec![1]), (7, vec![7])].into()),
+        );
+        writer.notify_written(&id, &BucketBody::Numeric([(20, vec![20])].into()));
+
+        // The writer does not receive its own updates
+        assert!(writer_rx.try_recv().is_err());
+
+        let update = reader_rx.try_recv().unwrap();
+        assert!(update.base.fire_and_forget);
+        assert_eq!(
+            ResponsePacketType::Subscribe {
+                binary_keys: false,
+                update: true,
+                deleted: false,
+                stream: false
+            },
+            update.header.packet_type
+        );
+        assert_eq!(
+            PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                id: id.clone(),
+                offset: None,
+                slots: BucketBody::Numeric([(7, vec![7])].into())
+            })),
+            update.body
+        );
+        assert!(reader_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn removes_subscriptions_of_dropped_subscribers() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let (writer, _writer_rx) = registry.create_subscriber();
+        let (reader, reader_rx) = registry.create_subscriber();
+        let id = BucketId::parse("#test").unwrap();
+
+        reader.subscribe(
+            &id,
+            BucketRange::Binary(None, None),
+            false,
+            SubscriberAccess::default(),
+        );
+        drop(reader_rx);
+
+        writer.notify_deleted(
+            &id,
+            &BucketBody::Binary([("key".to_string(), vec![])].into()),
+        );
+        assert_eq!(0, registry.count(&id));
+    }
+
+    #[test]
+    fn unsubscribes_exactly_the_given_range() {
+        let registry = Arc::new(SubscriptionRegistry::new());
+        let (writer, _writer_rx) = registry.create_subscriber();
+        let (reader, reader_rx) = registry.create_subscriber();
+        let id = BucketId::parse("#test").unwrap();
+
+        reader.subscribe(
+            &id,
+            BucketRange::Numeric(Some(1), Some(10)),
+            false,
+            SubscriberAccess::default(),
+        );
+        reader.subscribe(
+            &id,
+            BucketRange::Numeric(Some(5), None),
+            false,
+            SubscriberAccess::default(),
+        );
+        assert!(!reader.unsubscribe(&id, &BucketRange::Numeric(Some(1), None)));
+        assert_eq!(2, registry.count(&id));
+
+        // Overlapping ranges result in a single update
+        writer.notify_written(&id, &BucketBody::Numeric([(7, vec![7])].into()));
+        assert!(reader_rx.try_recv().is_ok());
+        assert!(reader_rx.try_recv().is_err());
+
+        assert!(reader.unsubscribe(&id, &BucketRange::Numeric(Some(5), None)));
+        assert_eq!(1, registry.count(&id));
+
+        writer.notify_written(
+            &id,
+            &BucketBody::Numeric([(7, vec![7]), (20, vec![20])].into()),
+        );
+        let update = reader_rx.try_recv().unwrap();
+        assert_eq!(
+            PlabbleResponseBody::Subscribe(Some(SubscriptionUpdate {
+                id: id.clone(),
+                offset: None,
+                slots: BucketBody::Numeric([(7, vec![7])].into())
+            })),
+            update.body
+        );
+
+        assert!(reader.unsubscribe(&id, &BucketRange::Numeric(Some(1), Some(10))));
+        assert_eq!(0, registry.count(&id));
+    }
+}
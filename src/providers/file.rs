@@ -0,0 +1,882 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use binary_codec::{BinaryDeserializer, BinarySerializer, FromBytes, SerializerConfig, ToBytes};
+use log::{debug, warn};
+
+use crate::{
+    core::BucketId,
+    crypto::hash_128,
+    packets::body::{
+        bucket::{BucketBody, BucketRange},
+        error::PlabbleError,
+        post::BucketSettings,
+        stream::SlotRange,
+    },
+    protocol::error::PlabbleProtocolError,
+    providers::{BucketStore, memory::MemoryBucket},
+};
+
+/// Default amount of log records after which the log is compacted
+const DEFAULT_COMPACTION_THRESHOLD: usize = 1024;
+
+/// Size of the record header: 4-byte length + 16-byte checksum
+const RECORD_HEADER_SIZE: usize = 4 + 16;
+
+/// A single mutation in the bucket log
+#[derive(FromBytes, ToBytes, Debug, PartialEq, Clone)]
+#[repr(u8)]
+enum LogEntry {
+    /// A bucket was created
+    CreateBucket {
+        id: BucketId,
+        settings: BucketSettings,
+        key: [u8; 64],
+    } = 0,
+
+    /// A bucket was deleted
+    DeleteBucket { id: BucketId } = 1,
+
+    /// The settings of a bucket were replaced
+    UpdateSettings {
+        id: BucketId,
+        settings: BucketSettings,
+    } = 2,
+
+    /// Slots were written (or appended) to a bucket
+    Write {
+        id: BucketId,
+        #[toggles("binary_keys")]
+        binary_keys: bool,
+        #[variant_by = "binary_keys"]
+        body: BucketBody,
+    } = 3,
+
+    /// Slots within a range were deleted from a bucket
+    Delete {
+        id: BucketId,
+        #[toggles("binary_keys")]
+        binary_keys: bool,
+        #[toggles("limit")]
+        with_limit: bool,
+        #[dyn_int]
+        #[toggled_by = "limit"]
+        limit: Option<u32>,
+        #[variant_by = "binary_keys"]
+        range: BucketRange,
+    } = 4,
+
+    /// Bytes were written into a slot at the start of the byte range (which is always set)
+    WriteBytes {
+        id: BucketId,
+        #[toggles("binary_keys")]
+        binary_keys: bool,
+        #[dyn_length]
+        data: Vec<u8>,
+        // The byte range is optional, so the slot must come last
+        #[variant_by = "binary_keys"]
+        slot: SlotRange,
+    } = 5,
+}
+
+/// Mutable state of the file bucket store
+struct FileBucketStoreState {
+    /// All buckets (persistent and RAM buckets)
+    buckets: HashMap<[u8; 16], MemoryBucket>,
+
+    /// IDs of the buckets that are not persisted (RAM buckets)
+    volatile: HashSet<[u8; 16]>,
+
+    /// Log file, opened in append mode
+    log: File,
+
+    /// Length of the log up to the last fully written record
+    len: u64,
+
+    /// Amount of records in the log
+    records: usize,
+
+    /// Amount of records in the log right after the last compaction
+    compacted_records: usize,
+}
+
+/// Persistent bucket store, writing every mutation to an append-only log file on disk.
+///
+/// The log is replayed when the store is opened. Records are checksummed, so a torn or corrupted
+/// record at the end of the log (e.g. after a crash) is detected and discarded during recovery.
+/// Every mutation is a single record, so a mutation is either fully applied or not at all.
+///
+/// When the log grows beyond the compaction threshold (and is at least twice as large as after
+/// the previous compaction) it is rewritten to contain only the current state of the buckets.
+///
+/// Buckets created without `persist` are kept in memory only and are lost on restart.
+pub struct FileBucketStore {
+    path: PathBuf,
+    compaction_threshold: usize,
+    state: Mutex<FileBucketStoreState>,
+}
+
+impl FileBucketStore {
+    /// Open (or create) the bucket store log at the given path and recover its state
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut buckets = HashMap::new();
+        let mut records = 0;
+
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut offset = 0;
+        while offset < data.len() {
+            match read_record(&data[offset..]) {
+                Some((entry, size)) => {
+                    apply(&mut buckets, entry);
+                    offset += size;
+                    records += 1;
+                }
+                None => {
+                    warn!(
+                        "Bucket log {:?} contains a corrupted record at offset {}, discarding the rest of the log",
+                        path, offset
+                    );
+                    break;
+                }
+            }
+        }
+
+        let log = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        // Cut off the corrupted tail, so new records are appended after the last valid one
+        if offset < data.len() {
+            log.set_len(offset as u64)?;
+            log.sync_all()?;
+        }
+
+        debug!(
+            "Recovered {} buckets from {} records in bucket log {:?}",
+            buckets.len(),
+            records,
+            path
+        );
+
+        Ok(Self {
+            path,
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            state: Mutex::new(FileBucketStoreState {
+                buckets,
+                volatile: HashSet::new(),
+                log,
+                len: offset as u64,
+                records,
+                compacted_records: 0,
+            }),
+        })
+    }
+
+    /// Set the amount of log records after which the log is compacted
+    pub fn with_compaction_threshold(mut self, threshold: usize) -> Self {
+        self.compaction_threshold = threshold;
+        self
+    }
+
+    /// Rewrite the log so it only contains the current state of the persistent buckets
+    pub fn compact(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        self.compact_state(&mut state)
+    }
+
+    /// Write a new log with the current state to a temporary file and replace the log with it
+    fn compact_state(&self, state: &mut FileBucketStoreState) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut data = Vec::new();
+        let mut records = 0;
+        for (id, bucket) in state.buckets.iter() {
+            if state.volatile.contains(id) {
+                continue;
+            }
+
+            let id = BucketId { data: *id };
+            let mut entries = vec![LogEntry::CreateBucket {
+                id: id.clone(),
+                settings: bucket.settings.clone(),
+                key: bucket.key,
+            }];
+
+            if !bucket.numeric.is_empty() {
+                entries.push(LogEntry::Write {
+                    id: id.clone(),
+                    binary_keys: false,
+                    body: BucketBody::Numeric(bucket.numeric.clone().into_iter().collect()),
+                });
+            }
+
+            if !bucket.binary.is_empty() {
+                entries.push(LogEntry::Write {
+                    id: id.clone(),
+                    binary_keys: true,
+                    body: BucketBody::Binary(bucket.binary.clone().into_iter().collect()),
+                });
+            }
+
+            for entry in entries {
+                data.extend(encode_record(&entry)?);
+                records += 1;
+            }
+        }
+
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&data)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        state.log = OpenOptions::new().append(true).open(&self.path)?;
+        state.len = data.len() as u64;
+        state.records = records;
+        state.compacted_records = records;
+
+        debug!(
+            "Compacted bucket log {:?} to {} records",
+            self.path, records
+        );
+        Ok(())
+    }
+
+    /// Truncate the log to the given length
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        file.set_len(len)?;
+        file.sync_all()
+    }
+
+    /// Append a record to the log (if the bucket is persistent) and compact the log if needed
+    fn persist(
+        &self,
+        state: &mut FileBucketStoreState,
+        id: &BucketId,
+        entry: &LogEntry,
+    ) -> Result<(), PlabbleProtocolError> {
+        if state.volatile.contains(&id.data) {
+            return Ok(());
+        }
+
+        let record = encode_record(entry).map_err(internal_error)?;
+        if let Err(e) = state
+            .log
+            .write_all(&record)
+            .and_then(|_| state.log.sync_data())
+        {
+            // Cut off the part of the record that was written, otherwise recovery would stop at it
+            // and discard every record that is written after it
+            if let Err(e) = self.truncate(state.len) {
+                warn!("Failed to truncate bucket log {:?}: {}", self.path, e);
+            }
+            return Err(internal_error(e));
+        }
+
+        state.len += record.len() as u64;
+        state.records += 1;
+
+        if state.records >= self.compaction_threshold
+            && state.records >= state.compacted_records * 2
+        {
+            // The record is already safely stored, so a failed compaction only means the log stays larger
+            if let Err(e) = self.compact_state(state) {
+                warn!("Failed to compact bucket log {:?}: {}", self.path, e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BucketStore for FileBucketStore {
+    fn create_bucket(
+        &self,
+        id: &BucketId,
+        settings: BucketSettings,
+        key: [u8; 64],
+        persist: bool,
+    ) -> Result<(), PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.buckets.contains_key(&id.data) {
+            return Err(PlabbleError::BucketAlreadyExists.into());
+        }
+
+        if !persist {
+            state.volatile.insert(id.data);
+        }
+
+        let entry = LogEntry::CreateBucket {
+            id: id.clone(),
+            settings,
+            key,
+        };
+        if let Err(e) = self.persist(&mut state, id, &entry) {
+            state.volatile.remove(&id.data);
+            return Err(e);
+        }
+
+        apply(&mut state.buckets, entry);
+        Ok(())
+    }
+
+    fn delete_bucket(
+        &self,
+        id: &BucketId,
+    ) -> Result<(BucketBody, BucketBody), PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.buckets.contains_key(&id.data) {
+            return Err(PlabbleError::BucketNotFound.into());
+        }
+
+        let entry = LogEntry::DeleteBucket { id: id.clone() };
+        self.persist(&mut state, id, &entry)?;
+        state.volatile.remove(&id.data);
+        let bucket = state
+            .buckets
+            .remove(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+        Ok(bucket.into_slots())
+    }
+
+    fn get_bucket_key(&self, id: &BucketId) -> Result<[u8; 64], PlabbleProtocolError> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = state
+            .buckets
+            .get(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+        Ok(bucket.key)
+    }
+
+    fn get_settings(&self, id: &BucketId) -> Result<BucketSettings, PlabbleProtocolError> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = state
+            .buckets
+            .get(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+        Ok(bucket.settings.clone())
+    }
+
+    fn update_settings(
+        &self,
+        id: &BucketId,
+        settings: BucketSettings,
+    ) -> Result<(), PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.buckets.contains_key(&id.data) {
+            return Err(PlabbleError::BucketNotFound.into());
+        }
+
+        let entry = LogEntry::UpdateSettings {
+            id: id.clone(),
+            settings,
+        };
+        self.persist(&mut state, id, &entry)?;
+        apply(&mut state.buckets, entry);
+        Ok(())
+    }
+
+    fn update_settings_with(
+        &self,
+        id: &BucketId,
+        update: &mut dyn FnMut(&mut BucketSettings) -> Result<(), PlabbleProtocolError>,
+    ) -> Result<(), PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut settings = state
+            .buckets
+            .get(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?
+            .settings
+            .clone();
+        update(&mut settings)?;
+
+        let entry = LogEntry::UpdateSettings {
+            id: id.clone(),
+            settings,
+        };
+        self.persist(&mut state, id, &entry)?;
+        apply(&mut state.buckets, entry);
+        Ok(())
+    }
+
+    fn read(
+        &self,
+        id: &BucketId,
+        range: &BucketRange,
+        limit: Option<u32>,
+    ) -> Result<BucketBody, PlabbleProtocolError> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = state
+            .buckets
+            .get(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+        Ok(bucket.read(range, limit))
+    }
+
+    fn write(
+        &self,
+        id: &BucketId,
+        body: BucketBody,
+        overwrite: bool,
+    ) -> Result<(), PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = state
+            .buckets
+            .get(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+
+        let exists = match &body {
+            BucketBody::Numeric(slots) => slots.keys().any(|k| bucket.numeric.contains_key(k)),
+            BucketBody::Binary(slots) => slots.keys().any(|k| bucket.binary.contains_key(k)),
+        };
+        if !overwrite && exists {
+            return Err(PlabbleError::SlotAlreadyExists.into());
+        }
+
+        let entry = LogEntry::Write {
+            id: id.clone(),
+            binary_keys: matches!(body, BucketBody::Binary(_)),
+            body,
+        };
+        self.persist(&mut state, id, &entry)?;
+        apply(&mut state.buckets, entry);
+        Ok(())
+    }
+
+    fn append(
+        &self,
+        id: &BucketId,
+        values: Vec<Vec<u8>>,
+    ) -> Result<Vec<u32>, PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = state
+            .buckets
+            .get(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+
+        // Appends are logged as plain writes to the allocated slots, so replaying them is deterministic
+        let slots = bucket.next_slots(values.len())?;
+        let entry = LogEntry::Write {
+            id: id.clone(),
+            binary_keys: false,
+            body: BucketBody::Numeric(slots.iter().copied().zip(values).collect()),
+        };
+        self.persist(&mut state, id, &entry)?;
+        apply(&mut state.buckets, entry);
+        Ok(slots)
+    }
+
+    fn write_bytes(
+        &self,
+        id: &BucketId,
+        slot: &SlotRange,
+        data: &[u8],
+    ) -> Result<u64, PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = state
+            .buckets
+            .get(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+
+        // Only the written bytes are logged, at the resolved offset so replaying an append is deterministic
+        let offset = bucket.write_offset(slot)?;
+        let slot = match slot {
+            SlotRange::Numeric(slot, ..) => SlotRange::Numeric(*slot, Some(offset), None),
+            SlotRange::Binary(key, ..) => SlotRange::Binary(key.clone(), Some(offset), None),
+        };
+
+        let entry = LogEntry::WriteBytes {
+            id: id.clone(),
+            binary_keys: matches!(slot, SlotRange::Binary(..)),
+            data: data.to_vec(),
+            slot: slot.clone(),
+        };
+        self.persist(&mut state, id, &entry)?;
+
+        let bucket = state
+            .buckets
+            .get_mut(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+        Ok(bucket.write_bytes(&slot, data)?)
+    }
+
+    fn delete(
+        &self,
+        id: &BucketId,
+        range: &BucketRange,
+        limit: Option<u32>,
+    ) -> Result<BucketBody, PlabbleProtocolError> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.buckets.contains_key(&id.data) {
+            return Err(PlabbleError::BucketNotFound.into());
+        }
+
+        let entry = LogEntry::Delete {
+            id: id.clone(),
+            binary_keys: matches!(range, BucketRange::Binary(..)),
+            with_limit: limit.is_some(),
+            limit,
+            range: range.clone(),
+        };
+        self.persist(&mut state, id, &entry)?;
+
+        let bucket = state
+            .buckets
+            .get_mut(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+        Ok(bucket.delete(range, limit))
+    }
+}
+
+/// Apply a log entry to the buckets
+fn apply(buckets: &mut HashMap<[u8; 16], MemoryBucket>, entry: LogEntry) {
+    match entry {
+        LogEntry::CreateBucket { id, settings, key } => {
+            buckets.insert(id.data, MemoryBucket::new(settings, key));
+        }
+        LogEntry::DeleteBucket { id } => {
+            buckets.remove(&id.data);
+        }
+        LogEntry::UpdateSettings { id, settings } => {
+            if let Some(bucket) = buckets.get_mut(&id.data) {
+                bucket.settings = settings;
+            }
+        }
+        LogEntry::Write { id, body, .. } => {
+            if let Some(bucket) = buckets.get_mut(&id.data) {
+                // Existing keys are checked before logging, so the log always overwrites
+                let _ = bucket.write(body, true);
+            }
+        }
+        LogEntry::Delete {
+            id, limit, range, ..
+        } => {
+            if let Some(bucket) = buckets.get_mut(&id.data) {
+                bucket.delete(&range, limit);
+            }
+        }
+        LogEntry::WriteBytes { id, slot, data, .. } => {
+            if let Some(bucket) = buckets.get_mut(&id.data) {
+                let _ = bucket.write_bytes(&slot, &data);
+            }
+        }
+    }
+}
+
+/// Encode a log entry as record: 4-byte length, 16-byte checksum and the serialized entry
+fn encode_record(entry: &LogEntry) -> io::Result<Vec<u8>> {
+    let bytes = entry
+        .to_bytes(None::<&mut SerializerConfig>)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + bytes.len());
+    record.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    record.extend_from_slice(&hash_128(false, vec![&bytes]));
+    record.extend_from_slice(&bytes);
+    Ok(record)
+}
+
+/// Read a record from the start of the data, returning the log entry and the size of the record.
+/// Returns None if the record is incomplete, its checksum does not match or it could not be decoded.
+fn read_record(data: &[u8]) -> Option<(LogEntry, usize)> {
+    let header = data.get(..RECORD_HEADER_SIZE)?;
+    let len = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+    let bytes = data.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE.checked_add(len)?)?;
+
+    if hash_128(false, vec![bytes])[..] != header[4..] {
+        return None;
+    }
+
+    let entry = LogEntry::from_bytes(bytes, None::<&mut SerializerConfig>).ok()?;
+    Some((entry, RECORD_HEADER_SIZE + len))
+}
+
+/// Map an I/O error to an internal server error
+fn internal_error(e: io::Error) -> PlabbleProtocolError {
+    warn!("Bucket log I/O error: {}", e);
+    PlabbleError::InternalServerError.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, OpenOptions},
+        io::Write,
+        path::PathBuf,
+    };
+
+    use crate::{
+        core::BucketId,
+        packets::body::{
+            bucket::{BucketBody, BucketRange},
+            error::PlabbleError,
+            post::BucketSettings,
+            stream::SlotRange,
+        },
+        protocol::error::PlabbleProtocolError,
+        providers::{BucketStore, file::FileBucketStore},
+    };
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "plabble-bucket-log-{}-{}.log",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn numeric(slots: &[(u32, &[u8])]) -> BucketBody {
+        BucketBody::Numeric(slots.iter().map(|(k, v)| (*k, v.to_vec())).collect())
+    }
+
+    #[test]
+    fn buckets_survive_reopening_the_store() {
+        let path = log_path("reopen");
+        let id = BucketId::parse("#persistent").unwrap();
+        let ram_id = BucketId::parse("#ram").unwrap();
+
+        {
+            let store = FileBucketStore::open(&path).unwrap();
+            store
+                .create_bucket(&id, BucketSettings::default(), [7; 64], true)
+                .unwrap();
+            store
+                .create_bucket(&ram_id, BucketSettings::default(), [0; 64], false)
+                .unwrap();
+            store
+                .append(&id, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+                .unwrap();
+            store.append(&ram_id, vec![b"x".to_vec()]).unwrap();
+            store
+                .delete(&id, &BucketRange::Numeric(None, None), Some(1))
+                .unwrap();
+            store
+                .write(
+                    &id,
+                    BucketBody::Binary([("name".to_string(), b"henk".to_vec())].into()),
+                    true,
+                )
+                .unwrap();
+        }
+
+        let store = FileBucketStore::open(&path).unwrap();
+        assert_eq!([7; 64], store.get_bucket_key(&id).unwrap());
+        assert_eq!(
+            store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap(),
+            numeric(&[(1, b"b"), (2, b"c")])
+        );
+        assert_eq!(
+            store
+                .read(&id, &BucketRange::Binary(None, None), None)
+                .unwrap(),
+            BucketBody::Binary([("name".to_string(), b"henk".to_vec())].into())
+        );
+        assert!(matches!(
+            store.get_settings(&ram_id),
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::BucketNotFound
+            ))
+        ));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn settings_updates_are_persisted() {
+        let path = log_path("settings");
+        let id = BucketId::parse("#settings").unwrap();
+
+        {
+            let store = FileBucketStore::open(&path).unwrap();
+            store
+                .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+                .unwrap();
+            store
+                .update_settings_with(&id, &mut |settings| {
+                    settings.access_control_list.push([1; 16]);
+                    Ok(())
+                })
+                .unwrap();
+
+            // A failed update is not logged
+            let result = store.update_settings_with(&id, &mut |settings| {
+                settings.access_control_list.clear();
+                Err(PlabbleError::InvalidRequest.into())
+            });
+            assert!(result.is_err());
+        }
+
+        let store = FileBucketStore::open(&path).unwrap();
+        assert_eq!(
+            vec![[1; 16]],
+            store.get_settings(&id).unwrap().access_control_list
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn streamed_writes_only_log_the_written_bytes() {
+        let path = log_path("stream");
+        let id = BucketId::parse("#stream").unwrap();
+
+        {
+            let store = FileBucketStore::open(&path).unwrap();
+            store
+                .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+                .unwrap();
+            store
+                .write_bytes(&id, &SlotRange::Numeric(1, None, None), &[1; 1000])
+                .unwrap();
+
+            let len = fs::metadata(&path).unwrap().len();
+            for _ in 0..10 {
+                store
+                    .write_bytes(&id, &SlotRange::Numeric(1, None, None), b"ab")
+                    .unwrap();
+            }
+            store
+                .write_bytes(&id, &SlotRange::Numeric(1, Some(0), None), b"xyz")
+                .unwrap();
+
+            // Every record only contains the chunk, not the entire slot
+            assert!(fs::metadata(&path).unwrap().len() - len < 1000);
+        }
+
+        let store = FileBucketStore::open(&path).unwrap();
+        let mut expected = vec![1; 1000];
+        expected[..3].copy_from_slice(b"xyz");
+        expected.extend(b"ab".repeat(10));
+        assert_eq!(
+            store
+                .read(&id, &BucketRange::Numeric(Some(1), Some(1)), None)
+                .unwrap(),
+            BucketBody::Numeric([(1, expected)].into())
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn discards_torn_record_on_recovery() {
+        let path = log_path("torn");
+        let id = BucketId::parse("#torn").unwrap();
+
+        {
+            let store = FileBucketStore::open(&path).unwrap();
+            store
+                .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+                .unwrap();
+            store.write(&id, numeric(&[(1, b"a")]), true).unwrap();
+        }
+
+        // Simulate a crash halfway writing a record
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 50, 1, 2, 3]).unwrap();
+        drop(file);
+
+        let store = FileBucketStore::open(&path).unwrap();
+        store.write(&id, numeric(&[(2, b"b")]), true).unwrap();
+        drop(store);
+
+        let store = FileBucketStore::open(&path).unwrap();
+        assert_eq!(
+            store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap(),
+            numeric(&[(1, b"a"), (2, b"b")])
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn failed_write_does_not_leave_torn_record() {
+        let path = log_path("failed-write");
+        let id = BucketId::parse("#failed").unwrap();
+
+        {
+            let store = FileBucketStore::open(&path).unwrap();
+            store
+                .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+                .unwrap();
+            store.write(&id, numeric(&[(1, b"a")]), true).unwrap();
+
+            // Simulate a write that fails halfway: part of the record ends up in the log, the rest can not be written
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(&[0, 0, 0, 50, 1, 2, 3]).unwrap();
+            let mut state = store.state.lock().unwrap();
+            state.log = OpenOptions::new().read(true).open(&path).unwrap();
+            drop(state);
+
+            assert!(store.write(&id, numeric(&[(2, b"b")]), true).is_err());
+
+            // Writing works again once the log is writable, and is not lost behind the torn record
+            store.state.lock().unwrap().log = file;
+            store.write(&id, numeric(&[(3, b"c")]), true).unwrap();
+        }
+
+        let store = FileBucketStore::open(&path).unwrap();
+        assert_eq!(
+            store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap(),
+            numeric(&[(1, b"a"), (3, b"c")])
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn compacts_log_and_keeps_state() {
+        let path = log_path("compact");
+        let id = BucketId::parse("#compact").unwrap();
+        let deleted_id = BucketId::parse("#deleted").unwrap();
+
+        {
+            let store = FileBucketStore::open(&path)
+                .unwrap()
+                .with_compaction_threshold(10);
+            store
+                .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+                .unwrap();
+            store
+                .create_bucket(&deleted_id, BucketSettings::default(), [0; 64], true)
+                .unwrap();
+            store.delete_bucket(&deleted_id).unwrap();
+            for i in 0..20u8 {
+                store.write(&id, numeric(&[(1, &[i])]), true).unwrap();
+            }
+        }
+
+        // Compacted every 10 records, so far less than the 23 records (of ~40 bytes) written in total
+        let size = fs::metadata(&path).unwrap().len();
+        assert!(size < 400, "log was not compacted, size: {}", size);
+
+        let store = FileBucketStore::open(&path).unwrap();
+        assert_eq!(
+            store
+                .read(&id, &BucketRange::Numeric(None, None), None)
+                .unwrap(),
+            numeric(&[(1, &[19])])
+        );
+        assert!(store.get_settings(&deleted_id).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
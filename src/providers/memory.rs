@@ -0,0 +1,582 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{PoisonError, RwLock},
+};
+
+use crate::{
+    core::BucketId,
+    packets::body::{
+        bucket::{BucketBody, BucketRange},
+        error::PlabbleError,
+        post::BucketSettings,
+        stream::SlotRange,
+    },
+    protocol::error::PlabbleProtocolError,
+    providers::BucketStore,
+};
+
+/// A single bucket with its settings and slots, kept in memory
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MemoryBucket {
+    /// Bucket settings (permissions and ACL)
+    pub settings: BucketSettings,
+
+    /// Bucket key, derived from the session key of the creator
+    pub key: [u8; 64],
+
+    /// Numeric slots, ordered by slot number
+    pub numeric: BTreeMap<u32, Vec<u8>>,
+
+    /// Binary slots, ordered by key
+    pub binary: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemoryBucket {
+    /// Create new empty bucket with the given settings and bucket key
+    pub fn new(settings: BucketSettings, key: [u8; 64]) -> Self {
+        Self {
+            settings,
+            key,
+            numeric: BTreeMap::new(),
+            binary: BTreeMap::new(),
+        }
+    }
+
+    /// Take all numeric and binary slots out of the bucket
+    pub fn into_slots(self) -> (BucketBody, BucketBody) {
+        (
+            BucketBody::Numeric(self.numeric.into_iter().collect()),
+            BucketBody::Binary(self.binary.into_iter().collect()),
+        )
+    }
+
+    /// Read the slots within the range, returning at most `limit` slots
+    pub fn read(&self, range: &BucketRange, limit: Option<u32>) -> BucketBody {
+        let limit = limit.map(|l| l as usize).unwrap_or(usize::MAX);
+        match range {
+            BucketRange::Numeric(from, to) => BucketBody::Numeric(
+                numeric_slots(&self.numeric, from, to)
+                    .take(limit)
+                    .map(|(k, v)| (*k, v.clone()))
+                    .collect(),
+            ),
+            BucketRange::Binary(from, to) => BucketBody::Binary(
+                binary_slots(&self.binary, from, to)
+                    .take(limit)
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Write all slots in the body. If `overwrite` is not set, nothing is written if any of the slots already exists.
+    pub fn write(&mut self, body: BucketBody, overwrite: bool) -> Result<(), PlabbleError> {
+        match body {
+            BucketBody::Numeric(slots) => {
+                if !overwrite && slots.keys().any(|k| self.numeric.contains_key(k)) {
+                    return Err(PlabbleError::SlotAlreadyExists);
+                }
+                self.numeric.extend(slots);
+            }
+            BucketBody::Binary(slots) => {
+                if !overwrite && slots.keys().any(|k| self.binary.contains_key(k)) {
+                    return Err(PlabbleError::SlotAlreadyExists);
+                }
+                self.binary.extend(slots);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the slots the values would be appended to (after the highest numeric slot in use)
+    pub fn next_slots(&self, count: usize) -> Result<Vec<u32>, PlabbleError> {
+        let start = match self.numeric.last_key_value() {
+            Some((last, _)) => last.checked_add(1).ok_or(PlabbleError::BucketFull)?,
+            None => 0,
+        };
+
+        if count > 0 && start.checked_add((count - 1) as u32).is_none() {
+            return Err(PlabbleError::BucketFull);
+        }
+
+        Ok((0..count as u32).map(|i| start + i).collect())
+    }
+
+    /// Append the values to the next free numeric slots, returning the slots they were stored in
+    pub fn append(&mut self, values: Vec<Vec<u8>>) -> Result<Vec<u32>, PlabbleError> {
+        let slots = self.next_slots(values.len())?;
+        self.numeric.extend(slots.iter().copied().zip(values));
+        Ok(slots)
+    }
+
+    /// Get the byte offset the data is written at in the slot: the start of the byte range, or the end of the slot if it has no start.
+    /// A slot that does not exist yet is treated as an empty slot.
+    /// Fails with `PlabbleError::InvalidRequest` if the start lies beyond the end of the slot.
+    pub fn write_offset(&self, slot: &SlotRange) -> Result<u64, PlabbleError> {
+        let len = match slot {
+            SlotRange::Numeric(slot, ..) => self.numeric.get(slot),
+            SlotRange::Binary(key, ..) => self.binary.get(key),
+        }
+        .map_or(0, |value| value.len() as u64);
+
+        let offset = slot.bounds().0.unwrap_or(len);
+        if offset > len {
+            return Err(PlabbleError::InvalidRequest);
+        }
+
+        Ok(offset)
+    }
+
+    /// Write the data into the slot at the start of the byte range (or append it if it has no start), in place.
+    /// Returns the new size of the slot in bytes.
+    pub fn write_bytes(&mut self, slot: &SlotRange, data: &[u8]) -> Result<u64, PlabbleError> {
+        let offset = self.write_offset(slot)? as usize;
+        let value = match slot {
+            SlotRange::Numeric(slot, ..) => self.numeric.entry(*slot).or_default(),
+            SlotRange::Binary(key, ..) => self.binary.entry(key.clone()).or_default(),
+        };
+
+        let overlap = data.len().min(value.len() - offset);
+        value[offset..offset + overlap].copy_from_slice(&data[..overlap]);
+        value.extend_from_slice(&data[overlap..]);
+        Ok(value.len() as u64)
+    }
+
+    /// Delete the slots within the range, deleting at most `limit` slots. Returns the deleted slots.
+    pub fn delete(&mut self, range: &BucketRange, limit: Option<u32>) -> BucketBody {
+        let deleted = self.read(range, limit);
+        match &deleted {
+            BucketBody::Numeric(slots) => slots.keys().for_each(|k| {
+                self.numeric.remove(k);
+            }),
+            BucketBody::Binary(slots) => slots.keys().for_each(|k| {
+                self.binary.remove(k);
+            }),
+        }
+
+        deleted
+    }
+}
+
+/// Iterate over the numeric slots within the (inclusive) range
+fn numeric_slots<'a>(
+    slots: &'a BTreeMap<u32, Vec<u8>>,
+    from: &Option<u32>,
+    to: &Option<u32>,
+) -> Box<dyn Iterator<Item = (&'a u32, &'a Vec<u8>)> + 'a> {
+    if matches!((from, to), (Some(from), Some(to)) if from > to) {
+        return Box::new(std::iter::empty());
+    }
+
+    let start = from.map(Bound::Included).unwrap_or(Bound::Unbounded);
+    let end = to.map(Bound::Included).unwrap_or(Bound::Unbounded);
+    Box::new(slots.range((start, end)))
+}
+
+/// Iterate over the binary slots within the (inclusive) range
+fn binary_slots<'a>(
+    slots: &'a BTreeMap<String, Vec<u8>>,
+    from: &'a Option<String>,
+    to: &'a Option<String>,
+) -> Box<dyn Iterator<Item = (&'a String, &'a Vec<u8>)> + 'a> {
+    if matches!((from, to), (Some(from), Some(to)) if from > to) {
+        return Box::new(std::iter::empty());
+    }
+
+    let start = from
+        .as_ref()
+        .map(Bound::Included)
+        .unwrap_or(Bound::Unbounded);
+    let end = to.as_ref().map(Bound::Included).unwrap_or(Bound::Unbounded);
+    Box::new(slots.range::<String, _>((start, end)))
+}
+
+/// In-memory bucket store, keeping all buckets in RAM.
+///
+/// Useful for tests, WASM builds and servers that do not need persistent storage.
+/// Because nothing is persisted, buckets created with and without `persist` are handled the same way.
+#[derive(Debug, Default)]
+pub struct MemoryBucketStore {
+    buckets: RwLock<HashMap<[u8; 16], MemoryBucket>>,
+}
+
+impl MemoryBucketStore {
+    /// Create new, empty in-memory bucket store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run a function on a bucket (read-only), or fail if the bucket does not exist
+    fn with_bucket<T>(
+        &self,
+        id: &BucketId,
+        f: impl FnOnce(&MemoryBucket) -> T,
+    ) -> Result<T, PlabbleProtocolError> {
+        let buckets = self.buckets.read().unwrap_or_else(PoisonError::into_inner);
+        let bucket = buckets.get(&id.data).ok_or(PlabbleError::BucketNotFound)?;
+        Ok(f(bucket))
+    }
+
+    /// Run a function on a bucket (mutable), or fail if the bucket does not exist
+    fn with_bucket_mut<T>(
+        &self,
+        id: &BucketId,
+        f: impl FnOnce(&mut MemoryBucket) -> T,
+    ) -> Result<T, PlabbleProtocolError> {
+        let mut buckets = self.buckets.write().unwrap_or_else(PoisonError::into_inner);
+        let bucket = buckets
+            .get_mut(&id.data)
+            .ok_or(PlabbleError::BucketNotFound)?;
+        Ok(f(bucket))
+    }
+}
+
+impl BucketStore for MemoryBucketStore {
+    fn create_bucket(
+        &self,
+        id: &BucketId,
+        settings: BucketSettings,
+        key: [u8; 64],
+        _persist: bool,
+    ) -> Result<(), PlabbleProtocolError> {
+        let mut buckets = self.buckets.write().unwrap_or_else(PoisonError::into_inner);
+        if buckets.contains_key(&id.data) {
+            return Err(PlabbleError::BucketAlreadyExists.into());
+        }
+
+        buckets.insert(id.data, MemoryBucket::new(settings, key));
+        Ok(())
+    }
+
+    fn delete_bucket(
+        &self,
+        id: &BucketId,
+    ) -> Result<(BucketBody, BucketBody), PlabbleProtocolError> {
+        let mut buckets = self.buckets.write().unwrap_or_else(PoisonError::into_inner);
+        buckets
+            .remove(&id.data)
+            .map(MemoryBucket::into_slots)
+            .ok_or(PlabbleError::BucketNotFound.into())
+    }
+
+    fn get_bucket_key(&self, id: &BucketId) -> Result<[u8; 64], PlabbleProtocolError> {
+        self.with_bucket(id, |bucket| bucket.key)
+    }
+
+    fn get_settings(&self, id: &BucketId) -> Result<BucketSettings, PlabbleProtocolError> {
+        self.with_bucket(id, |bucket| bucket.settings.clone())
+    }
+
+    fn update_settings(
+        &self,
+        id: &BucketId,
+        settings: BucketSettings,
+    ) -> Result<(), PlabbleProtocolError> {
+        self.with_bucket_mut(id, |bucket| bucket.settings = settings)
+    }
+
+    fn update_settings_with(
+        &self,
+        id: &BucketId,
+        update: &mut dyn FnMut(&mut BucketSettings) -> Result<(), PlabbleProtocolError>,
+    ) -> Result<(), PlabbleProtocolError> {
+        self.with_bucket_mut(id, |bucket| {
+            let mut settings = bucket.settings.clone();
+            update(&mut settings)?;
+            bucket.settings = settings;
+            Ok(())
+        })?
+    }
+
+    fn read(
+        &self,
+        id: &BucketId,
+        range: &BucketRange,
+        limit: Option<u32>,
+    ) -> Result<BucketBody, PlabbleProtocolError> {
+        self.with_bucket(id, |bucket| bucket.read(range, limit))
+    }
+
+    fn write(
+        &self,
+        id: &BucketId,
+        body: BucketBody,
+        overwrite: bool,
+    ) -> Result<(), PlabbleProtocolError> {
+        Ok(self.with_bucket_mut(id, |bucket| bucket.write(body, overwrite))??)
+    }
+
+    fn append(
+        &self,
+        id: &BucketId,
+        values: Vec<Vec<u8>>,
+    ) -> Result<Vec<u32>, PlabbleProtocolError> {
+        Ok(self.with_bucket_mut(id, |bucket| bucket.append(values))??)
+    }
+
+    fn write_bytes(
+        &self,
+        id: &BucketId,
+        slot: &SlotRange,
+        data: &[u8],
+    ) -> Result<u64, PlabbleProtocolError> {
+        Ok(self.with_bucket_mut(id, |bucket| bucket.write_bytes(slot, data))??)
+    }
+
+    fn delete(
+        &self,
+        id: &BucketId,
+        range: &BucketRange,
+        limit: Option<u32>,
+    ) -> Result<BucketBody, PlabbleProtocolError> {
+        self.with_bucket_mut(id, |bucket| bucket.delete(range, limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        core::BucketId,
+        packets::body::{
+            bucket::{BucketBody, BucketRange},
+            error::PlabbleError,
+            post::BucketSettings,
+            stream::SlotRange,
+        },
+        protocol::error::PlabbleProtocolError,
+        providers::{BucketStore, memory::MemoryBucketStore},
+    };
+
+    fn create_store() -> (MemoryBucketStore, BucketId) {
+        let store = MemoryBucketStore::new();
+        let id = BucketId::parse("#test").unwrap();
+        store
+            .create_bucket(&id, BucketSettings::default(), [0; 64], true)
+            .unwrap();
+        (store, id)
+    }
+
+    fn numeric(slots: &[(u32, &[u8])]) -> BucketBody {
+        BucketBody::Numeric(slots.iter().map(|(k, v)| (*k, v.to_vec())).collect())
+    }
+
+    #[test]
+    fn cannot_create_bucket_twice() {
+        let (store, id) = create_store();
+        let result = store.create_bucket(&id, BucketSettings::default(), [0; 64], false);
+        assert!(matches!(
+            result,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::BucketAlreadyExists
+            ))
+        ));
+    }
+
+    #[test]
+    fn updates_settings_atomically() {
+        let (store, id) = create_store();
+
+        // Concurrent updates are not lost
+        std::thread::scope(|scope| {
+            for user in 0..8u8 {
+                let store = &store;
+                let id = &id;
+                scope.spawn(move || {
+                    store
+                        .update_settings_with(id, &mut |settings| {
+                            settings.access_control_list.push([user; 16]);
+                            Ok(())
+                        })
+                        .unwrap()
+                });
+            }
+        });
+        assert_eq!(
+            8,
+            store.get_settings(&id).unwrap().access_control_list.len()
+        );
+
+        // A failed update changes nothing
+        let result = store.update_settings_with(&id, &mut |settings| {
+            settings.access_control_list.clear();
+            Err(PlabbleError::InvalidRequest.into())
+        });
+        assert!(matches!(
+            result,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::InvalidRequest
+            ))
+        ));
+        assert_eq!(
+            8,
+            store.get_settings(&id).unwrap().access_control_list.len()
+        );
+    }
+
+    #[test]
+    fn can_write_and_read_numeric_range_with_limit() {
+        let (store, id) = create_store();
+        store
+            .write(
+                &id,
+                numeric(&[(1, b"a"), (5, b"b"), (7, b"c"), (9, b"d")]),
+                true,
+            )
+            .unwrap();
+
+        let body = store
+            .read(&id, &BucketRange::Numeric(Some(2), Some(9)), Some(2))
+            .unwrap();
+        assert_eq!(body, numeric(&[(5, b"b"), (7, b"c")]));
+
+        let body = store
+            .read(&id, &BucketRange::Numeric(None, Some(5)), None)
+            .unwrap();
+        assert_eq!(body, numeric(&[(1, b"a"), (5, b"b")]));
+
+        let body = store
+            .read(&id, &BucketRange::Numeric(Some(9), Some(1)), None)
+            .unwrap();
+        assert_eq!(body, numeric(&[]));
+    }
+
+    #[test]
+    fn can_write_and_read_binary_range() {
+        let (store, id) = create_store();
+        let slots = HashMap::from([
+            ("alias".to_string(), b"henk".to_vec()),
+            ("name".to_string(), b"Henk".to_vec()),
+            ("zip".to_string(), b"1234".to_vec()),
+        ]);
+        store.write(&id, BucketBody::Binary(slots), true).unwrap();
+
+        let body = store
+            .read(
+                &id,
+                &BucketRange::Binary(Some("b".into()), Some("name".into())),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            body,
+            BucketBody::Binary(HashMap::from([("name".to_string(), b"Henk".to_vec())]))
+        );
+
+        // Numeric and binary slots are separated
+        let body = store
+            .read(&id, &BucketRange::Numeric(None, None), None)
+            .unwrap();
+        assert_eq!(body, numeric(&[]));
+    }
+
+    #[test]
+    fn write_without_overwrite_is_atomic() {
+        let (store, id) = create_store();
+        store.write(&id, numeric(&[(2, b"a")]), true).unwrap();
+
+        let result = store.write(&id, numeric(&[(1, b"b"), (2, b"c")]), false);
+        assert!(matches!(
+            result,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::SlotAlreadyExists
+            ))
+        ));
+
+        let body = store
+            .read(&id, &BucketRange::Numeric(None, None), None)
+            .unwrap();
+        assert_eq!(body, numeric(&[(2, b"a")]));
+    }
+
+    #[test]
+    fn can_append_after_last_slot() {
+        let (store, id) = create_store();
+        assert_eq!(
+            store
+                .append(&id, vec![b"a".to_vec(), b"b".to_vec()])
+                .unwrap(),
+            vec![0, 1]
+        );
+
+        store.write(&id, numeric(&[(10, b"c")]), true).unwrap();
+        assert_eq!(store.append(&id, vec![b"d".to_vec()]).unwrap(), vec![11]);
+
+        store
+            .write(&id, numeric(&[(u32::MAX, b"e")]), true)
+            .unwrap();
+        let result = store.append(&id, vec![b"f".to_vec()]);
+        assert!(matches!(
+            result,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::BucketFull
+            ))
+        ));
+    }
+
+    #[test]
+    fn can_write_bytes_at_offset_or_append_to_slot() {
+        let (store, id) = create_store();
+        assert_eq!(
+            3,
+            store
+                .write_bytes(&id, &SlotRange::Numeric(1, None, None), b"abc")
+                .unwrap()
+        );
+        assert_eq!(
+            5,
+            store
+                .write_bytes(&id, &SlotRange::Numeric(1, Some(2), None), b"CDE")
+                .unwrap()
+        );
+        assert_eq!(
+            store
+                .read(&id, &BucketRange::Numeric(Some(1), Some(1)), None)
+                .unwrap(),
+            numeric(&[(1, b"abCDE")])
+        );
+
+        let result = store.write_bytes(&id, &SlotRange::Numeric(1, Some(6), None), b"f");
+        assert!(matches!(
+            result,
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::InvalidRequest
+            ))
+        ));
+    }
+
+    #[test]
+    fn can_delete_range_with_limit_and_return_deleted() {
+        let (store, id) = create_store();
+        store
+            .append(&id, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+            .unwrap();
+
+        let deleted = store
+            .delete(&id, &BucketRange::Numeric(None, None), Some(2))
+            .unwrap();
+        assert_eq!(deleted, numeric(&[(0, b"a"), (1, b"b")]));
+
+        let body = store
+            .read(&id, &BucketRange::Numeric(None, None), None)
+            .unwrap();
+        assert_eq!(body, numeric(&[(2, b"c")]));
+    }
+
+    #[test]
+    fn operations_on_missing_bucket_fail() {
+        let store = MemoryBucketStore::new();
+        let id = BucketId::parse("#missing").unwrap();
+        assert!(matches!(
+            store.get_settings(&id),
+            Err(PlabbleProtocolError::ProtocolError(
+                PlabbleError::BucketNotFound
+            ))
+        ));
+        assert!(store.delete_bucket(&id).is_err());
+        assert!(store.append(&id, vec![]).is_err());
+    }
+}
@@ -1,13 +1,41 @@
+use std::pin::Pin;
+
 #[cfg(feature = "blockchain")]
 use crate::blockchain::transaction::TransactionLock;
 
-use crate::protocol::error::PlabbleProtocolError;
+pub mod file;
+pub mod memory;
+
+use crate::{
+    core::{BucketId, Claims},
+    crypto::certificate::Certificate,
+    packets::body::{
+        bucket::{BucketBody, BucketRange},
+        error::PlabbleError,
+        post::BucketSettings,
+        stream::SlotRange,
+    },
+    protocol::error::PlabbleProtocolError,
+};
+
+/// Future of an asynchronous provider method, for providers that do I/O (like the [`ProxyRelay`])
+pub type ProviderFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, PlabbleProtocolError>> + Send + 'a>>;
 
 // Key/storage provider for Plabble Connection
 pub trait KeyProvider: Send + Sync {
     /// Given a bucket ID serialized as bytes, return the 32-byte bucket key, or None.
     fn get_bucket_key(&self, bucket_id: &[u8; 16]) -> Option<[u8; 32]>;
 
+    /// Given a bucket ID serialized as bytes, return the 64-byte bucket key that was derived from the session key
+    /// when the bucket was created with POST (see `PlabbleConnectionContext::create_bucket_key`), or None.
+    /// Takes precedence over `get_bucket_key`. A key of any other length fails the packet with `InvalidBucketKey`.
+    /// Returns None by default.
+    fn get_derived_bucket_key(&self, bucket_id: &[u8; 16]) -> Option<Vec<u8>> {
+        let _ = bucket_id;
+        None
+    }
+
     /// Given a 12-byte PSK ID, return the 64-byte pre-shared key, or None.
     fn get_psk(&self, psk_id: &[u8; 12]) -> Option<[u8; 64]>;
 
@@ -15,6 +43,126 @@ pub trait KeyProvider: Send + Sync {
     fn store_psk(&self, psk_id: [u8; 12], psk: [u8; 64], expiration: Option<u32>);
 }
 
+/// Certificate provider, an interface for looking up the server certificate and other known certificates
+pub trait CertificateProvider: Send + Sync {
+    /// Get the certificate of this server, including its secret signing keys, or None.
+    fn get_server_certificate(&self) -> Option<Certificate>;
+
+    /// Given a 16-byte certificate ID, return the certificate, or None.
+    fn get_certificate(&self, id: &[u8; 16]) -> Option<Certificate>;
+
+    /// Given a certificate URI (for example the issuer URI of another certificate), return the certificate, or None.
+    fn get_certificate_by_uri(&self, uri: &str) -> Option<Certificate>;
+
+    /// Store a certificate that was issued with REGISTER, so it can be looked up by its ID and URI.
+    /// The server MUST fail with `PlabbleError::ClaimsAlreadyRegistered` if a certificate with the same claims (data) is already registered.
+    /// The client stores its own certificates (including the secret keys) with this method.
+    fn store_certificate(&self, certificate: Certificate) -> Result<(), PlabbleProtocolError>;
+
+    /// Validate the claims of a REGISTER request before a certificate is issued for them, for instance to require certain claims.
+    /// Fail with `PlabbleError::InvalidClaims` to reject the claims. Accepts all claims by default.
+    fn validate_claims(&self, claims: &Claims) -> Result<(), PlabbleError> {
+        let _ = claims;
+        Ok(())
+    }
+}
+
+/// Bucket store, the server-side storage backend for buckets, their settings (permissions and ACL) and their slots
+///
+/// Every bucket can contain both numeric and binary slots, the variant of the given `BucketRange` or `BucketBody` decides which are used.
+/// Ranges are inclusive on both ends, a missing bound means the range is open on that side.
+/// Numeric slots are ordered by slot number, binary slots by their (UTF-8) key.
+///
+/// All operations on a bucket that does not exist MUST fail with `PlabbleError::BucketNotFound`.
+/// Permission checks are NOT the responsibility of the store, the server handles those before calling it.
+pub trait BucketStore: Send + Sync {
+    /// Create a new, empty bucket with the given settings and (64-byte) bucket key.
+    /// If `persist` is not set, the bucket is kept in memory only (RAM bucket).
+    /// Fails with `PlabbleError::BucketAlreadyExists` if a bucket with this ID already exists.
+    fn create_bucket(
+        &self,
+        id: &BucketId,
+        settings: BucketSettings,
+        key: [u8; 64],
+        persist: bool,
+    ) -> Result<(), PlabbleProtocolError>;
+
+    /// Delete a bucket including all of its slots, returns the removed numeric and binary slots
+    fn delete_bucket(
+        &self,
+        id: &BucketId,
+    ) -> Result<(BucketBody, BucketBody), PlabbleProtocolError>;
+
+    /// Get the (64-byte) bucket key of a bucket, which was derived when the bucket was created
+    fn get_bucket_key(&self, id: &BucketId) -> Result<[u8; 64], PlabbleProtocolError>;
+
+    /// Get the settings (permissions and ACL) of a bucket
+    fn get_settings(&self, id: &BucketId) -> Result<BucketSettings, PlabbleProtocolError>;
+
+    /// Replace the settings (permissions and ACL) of a bucket
+    fn update_settings(
+        &self,
+        id: &BucketId,
+        settings: BucketSettings,
+    ) -> Result<(), PlabbleProtocolError>;
+
+    /// Update the settings of a bucket atomically: no other change to the bucket can happen between reading and writing the settings.
+    /// The settings are left unchanged if `update` fails, its error is returned.
+    fn update_settings_with(
+        &self,
+        id: &BucketId,
+        update: &mut dyn FnMut(&mut BucketSettings) -> Result<(), PlabbleProtocolError>,
+    ) -> Result<(), PlabbleProtocolError>;
+
+    /// Read the slots within the range (in order), returning at most `limit` slots if given.
+    /// The returned body has the same variant as the range.
+    fn read(
+        &self,
+        id: &BucketId,
+        range: &BucketRange,
+        limit: Option<u32>,
+    ) -> Result<BucketBody, PlabbleProtocolError>;
+
+    /// Write all slots in the body at once, inserting or overwriting them.
+    /// If `overwrite` is not set, fail with `PlabbleError::SlotAlreadyExists` if any of the slots already exists.
+    ///
+    /// The write MUST be atomic: if it fails, none of the slots are written.
+    fn write(
+        &self,
+        id: &BucketId,
+        body: BucketBody,
+        overwrite: bool,
+    ) -> Result<(), PlabbleProtocolError>;
+
+    /// Append the values (in order) to the next free numeric slots of the bucket, returning the slots they were stored in.
+    /// Fails with `PlabbleError::BucketFull` if there are not enough free slots left.
+    ///
+    /// The append MUST be atomic: if it fails, none of the values are written.
+    fn append(&self, id: &BucketId, values: Vec<Vec<u8>>)
+    -> Result<Vec<u32>, PlabbleProtocolError>;
+
+    /// Write the data into a single slot, starting at the start of the byte range (or at the end of the slot if it has no start).
+    /// The slot is extended if needed, or created if it does not exist yet. Returns the new size of the slot in bytes.
+    /// Fails with `PlabbleError::InvalidRequest` if the start lies beyond the end of the slot.
+    ///
+    /// The write MUST be atomic: if it fails, the slot is not changed.
+    fn write_bytes(
+        &self,
+        id: &BucketId,
+        slot: &SlotRange,
+        data: &[u8],
+    ) -> Result<u64, PlabbleProtocolError>;
+
+    /// Delete the slots within the range (in order), deleting at most `limit` slots if given.
+    /// Returns the deleted slots, the returned body has the same variant as the range.
+    fn delete(
+        &self,
+        id: &BucketId,
+        range: &BucketRange,
+        limit: Option<u32>,
+    ) -> Result<BucketBody, PlabbleProtocolError>;
+}
+
 /// Plabble bucket provider, an interface for interacting with buckets on current or another server
 pub trait PlabbleBucketProvider: Send + Sync {
     /// Connect to a Plabble server at the given address (e.g. "example.com:1234").
@@ -36,6 +184,31 @@ pub trait PlabbleBucketProvider: Send + Sync {
     fn delete(&self, slot: u32) -> Result<(), PlabbleProtocolError>;
 }
 
+/// Proxy relay, the transport a server uses to reach other servers (hops and targets) for PROXY tunnels (server only)
+pub trait ProxyRelay: Send + Sync {
+    /// Select the address of a random server to use as the next hop of a tunnel, other than the excluded addresses.
+    /// Returns None if no hop is available.
+    fn select_hop(&self, exclude: &[&str]) -> Option<String>;
+
+    /// Open a connection to the server at the address, which is kept open as long as the tunnel that uses it.
+    /// Fail with `PlabbleError::HopUnreachable` if the server can not be reached.
+    fn connect<'a>(&'a self, address: &'a str) -> ProviderFuture<'a, Box<dyn ProxyLink>>;
+}
+
+/// Connection of a PROXY tunnel to its next hop or target, opened by a [`ProxyRelay`]
+///
+/// Hops send plain (session-less) PROXY requests to each other, the tunnel frames in them are protected by the onion layers.
+/// The last hop sends the frames to the target as they are, they contain the packets of the client for the target.
+pub trait ProxyLink: Send {
+    /// Send a raw packet over the connection and wait for the raw response.
+    /// Fail with `PlabbleError::HopUnreachable` if the connection is lost.
+    fn send_and_recv(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, Vec<u8>>;
+
+    /// Send a raw fire-and-forget packet over the connection, without waiting for a response.
+    /// Fail with `PlabbleError::HopUnreachable` if the connection is lost.
+    fn send(&mut self, packet: Vec<u8>) -> ProviderFuture<'_, ()>;
+}
+
 /// Plabble provider for interacting with Plabble blockchain
 #[cfg(feature = "blockchain")]
 pub trait BlockchainProvider: Send + Sync {
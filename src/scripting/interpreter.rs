@@ -64,6 +64,12 @@ impl ScriptInterpreter {
         }
     }
 
+    /// Set the provider that is used by the bucket operation opcodes (SERVER, SELECT, READ, WRITE, APPEND, DELETE)
+    pub fn with_bucket_provider(mut self, provider: Arc<dyn PlabbleBucketProvider>) -> Self {
+        self.bucket_provider = Some(provider);
+        self
+    }
+
     /// Validate a script against the current settings, without executing it. This is useful to check if a script is valid before forking or executing it.
     pub fn validate_script(&self, script: &OpcodeScript) -> Result<(), ScriptError> {
         if !self.settings.allow_non_push && !script.is_push_only() {
@@ -106,6 +112,8 @@ impl ScriptInterpreter {
                     {
                         return Err(ScriptError::FunctionCallNotAllowed);
                     }
+
+                    return Err(ScriptError::OpcodeNotSupported);
                 }
                 Opcode::JMP => {
                     if !self.settings.allow_control_flow {
@@ -141,6 +149,13 @@ impl ScriptInterpreter {
                         return Err(ScriptError::EvalNotAllowed);
                     }
                 }
+                // Opcodes (and algorithms) that are not implemented yet are rejected before anything is executed
+                Opcode::CHECKLOCK | Opcode::SELBLOCK | Opcode::SELTX | Opcode::GETENTRY => {
+                    return Err(ScriptError::OpcodeNotSupported);
+                }
+                Opcode::CRYPTO(OpAlgorithm::KeyStreamXChaCha20 | OpAlgorithm::KeyStreamAes256) => {
+                    return Err(ScriptError::AlgorithmNotSupported);
+                }
                 _ => {}
             }
         }
@@ -1351,8 +1366,6 @@ impl ScriptInterpreter {
                             return Err(ScriptError::AssertionFailed);
                         }
                     },
-                    OpAlgorithm::KeyStreamXChaCha20 => todo!(),
-                    OpAlgorithm::KeyStreamAes256 => todo!(),
                     #[allow(unreachable_patterns)]
                     _ => return Err(ScriptError::AlgorithmNotSupported),
                 }
@@ -1361,9 +1374,6 @@ impl ScriptInterpreter {
                 let now = PlabbleDateTime(Utc::now());
                 self.push(StackData::Number(now.timestamp() as i128))?;
             }
-            Opcode::CHECKLOCK => {
-                todo!()
-            }
             Opcode::TXID => {
                 #[cfg(feature = "blockchain")]
                 {
@@ -1379,12 +1389,12 @@ impl ScriptInterpreter {
 
                 return Err(ScriptError::BlockchainProviderNotAvailable);
             }
-            Opcode::SELBLOCK => todo!(),
-            Opcode::SELTX => todo!(),
-            Opcode::GETENTRY => todo!(),
-            Opcode::CALLEXT(id, params) => {
-                todo!()
-            }
+            // Not implemented yet, these are already rejected by validate_script
+            Opcode::CHECKLOCK
+            | Opcode::SELBLOCK
+            | Opcode::SELTX
+            | Opcode::GETENTRY
+            | Opcode::CALLEXT(..) => return Err(ScriptError::OpcodeNotSupported),
             Opcode::EVALSUB => {
                 let item = self.pop_bytes()?;
 
@@ -3469,4 +3479,32 @@ mod tests {
         let mut int = ScriptInterpreter::new(script, None);
         assert_eq!(Ok(None), int.exec());
     }
+
+    #[test]
+    fn rejects_unsupported_opcodes_before_execution() {
+        let settings = ScriptSettings {
+            allow_function_calls: true,
+            allow_external_function_calls: true,
+            ..Default::default()
+        };
+
+        for opcode in [
+            Opcode::CHECKLOCK,
+            Opcode::SELBLOCK,
+            Opcode::SELTX,
+            Opcode::GETENTRY,
+            Opcode::CALLEXT(1, 0),
+        ] {
+            let script = OpcodeScript::new(vec![Opcode::PUSHINT(1), Opcode::ASSERT, opcode]);
+            let mut int = ScriptInterpreter::new(script, Some(settings.clone()));
+            assert_eq!(Err(ScriptError::OpcodeNotSupported), int.exec());
+            assert_eq!(0, int.executions);
+        }
+
+        for algorithm in [OpAlgorithm::KeyStreamXChaCha20, OpAlgorithm::KeyStreamAes256] {
+            let script = OpcodeScript::new(vec![Opcode::PUSHINT(1), Opcode::CRYPTO(algorithm)]);
+            let mut int = ScriptInterpreter::new(script, None);
+            assert_eq!(Err(ScriptError::AlgorithmNotSupported), int.exec());
+        }
+    }
 }
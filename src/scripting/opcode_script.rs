@@ -273,6 +273,9 @@ pub enum ScriptError {
 
     AlgorithmNotSupported,
     CryptoOperationFailed,
+
+    /// When the opcode is not supported (yet) by the interpreter
+    OpcodeNotSupported,
 }
 
 // TODO: all algorithms in one, we can omit codes like HASH/SIGN etc and just have a few opcodes
@@ -62,6 +62,12 @@ impl KeyProvider for SessionKeyProvider {
         call_js_byte_array_cb_1(&self.get_bucket_key, bucket_id)
     }
 
+    /// The callback may return the 64-byte derived bucket key as well. Any key that is not 32 bytes is handed out
+    /// as derived key, so a key of an invalid length fails the packet.
+    fn get_derived_bucket_key(&self, bucket_id: &[u8; 16]) -> Option<Vec<u8>> {
+        call_js_bytes_cb_1(&self.get_bucket_key, bucket_id).filter(|key| key.len() != 32)
+    }
+
     fn get_psk(&self, psk_id: &[u8; 12]) -> Option<[u8; 64]> {
         call_js_byte_array_cb_1(&self.get_psk, psk_id)
     }
@@ -158,15 +164,14 @@ impl PlabbleConnection {
 
 /// Helper function to call JS callbacks and convert result to fixed-size array
 fn call_js_byte_array_cb_1<const N: usize>(callback: &Function, input: &[u8]) -> Option<[u8; N]> {
+    call_js_bytes_cb_1(callback, input)?.try_into().ok()
+}
+
+fn call_js_bytes_cb_1(callback: &Function, input: &[u8]) -> Option<Vec<u8>> {
     callback
         .call1(&JsValue::NULL, &Uint8Array::from(input).into())
         .ok()?
         .dyn_into::<Uint8Array>()
         .ok()
-        .filter(|arr| arr.length() == N as u32)
-        .map(|arr| {
-            let mut output = [0u8; N];
-            arr.copy_to(&mut output);
-            output
-        })
+        .map(|arr| arr.to_vec())
 }